@@ -0,0 +1,376 @@
+// Registry of every named action and the user's keyboard shortcuts for them
+
+use crate::config::ConfigFile;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub struct ActionInfo {
+    // Detailed action name, e.g. "timeline.split"
+    pub name: &'static str,
+    pub label: &'static str,
+    pub category: &'static str,
+    // Luvita's own defaults
+    pub keys: &'static [&'static str],
+    // AviUtl / 拡張編集 keys where it has an equivalent command
+    pub aviutl_keys: &'static [&'static str],
+}
+
+const fn action(
+    name: &'static str,
+    label: &'static str,
+    category: &'static str,
+    keys: &'static [&'static str],
+    aviutl_keys: &'static [&'static str],
+) -> ActionInfo {
+    ActionInfo {
+        name,
+        label,
+        category,
+        keys,
+        aviutl_keys,
+    }
+}
+
+// Every action that can get a shortcut, in the order the shortcut editor lists them
+pub const ACTIONS: &[ActionInfo] = &[
+    action(
+        "win.open-media",
+        "開く",
+        "ファイル",
+        &["<Control>o"],
+        &["<Control>o"],
+    ),
+    action(
+        "win.close-project",
+        "閉じる",
+        "ファイル",
+        &["<Control>w"],
+        &[],
+    ),
+    action(
+        "win.open-project",
+        "プロジェクトを開く",
+        "ファイル",
+        &["<Shift><Control>o"],
+        &["<Shift><Control>o"],
+    ),
+    action(
+        "win.save-project",
+        "プロジェクトを保存",
+        "ファイル",
+        &["<Control>s"],
+        &["<Control>s"],
+    ),
+    action("win.export", "書き出し", "ファイル", &["<Control>e"], &[]),
+    action(
+        "win.queue-export",
+        "レンダーキューに追加",
+        "ファイル",
+        &["<Shift><Control>e"],
+        &[],
+    ),
+    action("win.render-queue", "レンダーキュー", "ファイル", &[], &[]),
+    action("app.quit", "終了", "ファイル", &["<Control>q"], &[]),
+    action(
+        "timeline.undo",
+        "元に戻す",
+        "編集",
+        &["<Control>z"],
+        &["<Control>z"],
+    ),
+    action(
+        "timeline.redo",
+        "やり直す",
+        "編集",
+        &["<Shift><Control>z", "<Control>y"],
+        &["<Control>y"],
+    ),
+    action(
+        "timeline.cut",
+        "切り取り",
+        "編集",
+        &["<Control>x"],
+        &["<Control>x"],
+    ),
+    action(
+        "timeline.copy",
+        "コピー",
+        "編集",
+        &["<Control>c"],
+        &["<Control>c"],
+    ),
+    action(
+        "timeline.paste",
+        "貼り付け",
+        "編集",
+        &["<Control>v"],
+        &["<Control>v"],
+    ),
+    action("timeline.split", "再生位置で分割", "編集", &["s"], &["s"]),
+    action("timeline.delete", "削除", "編集", &["Delete"], &["Delete"]),
+    action(
+        "timeline.ripple-delete",
+        "リップル削除",
+        "編集",
+        &["<Shift>Delete"],
+        &[],
+    ),
+    action(
+        "timeline.ripple-delete-all",
+        "リップル削除 (全レイヤー)",
+        "編集",
+        &[],
+        &[],
+    ),
+    action("timeline.close-gap", "隙間を詰める", "編集", &[], &[]),
+    action("timeline.insert-time", "時間を挿入", "編集", &[], &[]),
+    action("timeline.add-marker", "マーカーを追加", "編集", &["m"], &[]),
+    action(
+        "timeline.properties",
+        "プロパティ",
+        "編集",
+        &["<Alt>Return"],
+        &[],
+    ),
+    action("timeline.add-text", "テキストを追加", "編集", &[], &[]),
+    action(
+        "transport.play",
+        "再生 / 停止",
+        "再生",
+        &["space"],
+        &["space"],
+    ),
+    action("transport.pause", "停止", "再生", &["k"], &[]),
+    action("transport.forward", "早送り", "再生", &["l"], &[]),
+    action("transport.backward", "巻き戻し", "再生", &["j"], &[]),
+    action(
+        "transport.step-forward",
+        "1フレーム進む",
+        "再生",
+        &["Right"],
+        &["Right"],
+    ),
+    action(
+        "transport.step-back",
+        "1フレーム戻る",
+        "再生",
+        &["Left"],
+        &["Left"],
+    ),
+    action(
+        "transport.start",
+        "先頭へ移動",
+        "再生",
+        &["Home"],
+        &["Home"],
+    ),
+    action("transport.end", "末尾へ移動", "再生", &["End"], &["End"]),
+    action("transport.set-in", "イン点を設定", "再生", &["i"], &[]),
+    action("transport.set-out", "アウト点を設定", "再生", &["o"], &[]),
+    action(
+        "transport.clear-in-out",
+        "イン・アウト点を解除",
+        "再生",
+        &["<Alt>x"],
+        &[],
+    ),
+    action(
+        "timeline.fit",
+        "プロジェクト全体を表示",
+        "表示",
+        &["<Control>0"],
+        &[],
+    ),
+    action(
+        "timeline.fit-selection",
+        "選択範囲を表示",
+        "表示",
+        &["<Shift><Control>0"],
+        &[],
+    ),
+    action("timeline.snap", "スナップ", "表示", &[], &[]),
+    action(
+        "win.profiles",
+        "プロファイルの管理",
+        "プロファイル",
+        &[],
+        &[],
+    ),
+    action(
+        "win.command-palette",
+        "コマンドパレット",
+        "その他",
+        &["<Shift><Control>p"],
+        &["<Shift><Control>p"],
+    ),
+    action(
+        "win.preferences",
+        "環境設定",
+        "その他",
+        &["<Control>comma"],
+        &[],
+    ),
+    action(
+        "win.shortcuts",
+        "キーボードショートカット",
+        "その他",
+        &[],
+        &[],
+    ),
+];
+
+pub fn find(name: &str) -> Option<&'static ActionInfo> {
+    ACTIONS.iter().find(|a| a.name == name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    Luvita,
+    Aviutl,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 2] = [Preset::Luvita, Preset::Aviutl];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Luvita => "Luvita 標準",
+            Preset::Aviutl => "AviUtl 互換",
+        }
+    }
+}
+
+// "<ctrl>Z" and "<Control>z" are the same shortcut; compare in one spelling
+pub fn normalize_accel(accel: &str) -> String {
+    const ORDER: [&str; 5] = ["<Shift>", "<Control>", "<Alt>", "<Super>", "<Meta>"];
+    let mut mods = Vec::new();
+    let mut rest = accel.trim();
+    while rest.starts_with('<')
+        && let Some(end) = rest.find('>')
+    {
+        let name = rest[1..end].to_ascii_lowercase();
+        let canonical = match name.as_str() {
+            "shift" => "<Shift>",
+            "ctrl" | "control" | "primary" => "<Control>",
+            "alt" | "mod1" => "<Alt>",
+            "super" => "<Super>",
+            "meta" => "<Meta>",
+            _ => "",
+        };
+        if !canonical.is_empty() && !mods.contains(&canonical) {
+            mods.push(canonical);
+        }
+        rest = &rest[end + 1..];
+    }
+    mods.sort_by_key(|m| ORDER.iter().position(|o| o == m));
+    let key = if rest.chars().count() == 1 {
+        rest.to_lowercase()
+    } else {
+        rest.to_string()
+    };
+    mods.concat() + &key
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeymapFile {
+    bindings: BTreeMap<String, Vec<String>>,
+}
+
+// Action name -> shortcuts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset(Preset::Luvita)
+    }
+}
+
+impl Keymap {
+    pub fn preset(preset: Preset) -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .map(|a| {
+                let keys = match preset {
+                    Preset::Luvita => a.keys,
+                    Preset::Aviutl => a.aviutl_keys,
+                };
+                let keys = keys.iter().map(|k| normalize_accel(k)).collect();
+                (a.name.to_string(), keys)
+            })
+            .collect();
+        Self { bindings }
+    }
+
+    pub fn accels(&self, action: &str) -> &[String] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    // Bind `accel` to `action`, taking it away from any other action.
+    // Returns the actions that lost it.
+    pub fn bind(&mut self, action: &str, accel: &str) -> Vec<String> {
+        let accel = normalize_accel(accel);
+        let mut taken = Vec::new();
+        for (name, keys) in self.bindings.iter_mut() {
+            if name != action && keys.contains(&accel) {
+                keys.retain(|k| *k != accel);
+                taken.push(name.clone());
+            }
+        }
+        let keys = self.bindings.entry(action.to_string()).or_default();
+        if !keys.contains(&accel) {
+            keys.push(accel);
+        }
+        taken
+    }
+
+    // Kept as an empty list so loading doesn't bring the default back
+    pub fn clear(&mut self, action: &str) {
+        self.bindings.insert(action.to_string(), Vec::new());
+    }
+
+    // Shortcuts bound to more than one action
+    pub fn conflicts(&self) -> Vec<(String, Vec<String>)> {
+        let mut users: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (name, keys) in &self.bindings {
+            for key in keys {
+                users.entry(key).or_default().push(name.clone());
+            }
+        }
+        users
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .map(|(key, names)| (key.to_string(), names))
+            .collect()
+    }
+}
+
+impl ConfigFile for Keymap {
+    const NAME: &'static str = "keymap.json";
+    const WHAT: &'static str = "キー設定";
+    const VERSION: u32 = 1;
+
+    type Document = KeymapFile;
+
+    fn to_document(&self) -> KeymapFile {
+        KeymapFile {
+            bindings: self.bindings.clone(),
+        }
+    }
+
+    // Actions missing from the file keep their defaults; unknown ones are dropped
+    fn from_document(file: KeymapFile) -> Keymap {
+        let mut keymap = Keymap::default();
+        for (name, keys) in file.bindings {
+            if find(&name).is_none() {
+                eprintln!("キー設定: 不明なアクション {} を無視します", name);
+                continue;
+            }
+            let keys = keys.iter().map(|k| normalize_accel(k)).collect();
+            keymap.bindings.insert(name, keys);
+        }
+        keymap
+    }
+}
@@ -0,0 +1,207 @@
+// Shared by the GIF and APNG backends: play count, frame skipping, delays and
+// the area that changed since the previous frame
+
+use crate::encoder::{self, EncoderOptions, OptionSpec};
+use crate::project::FrameRate;
+use crate::timeline::Frame;
+
+pub fn options() -> Vec<OptionSpec> {
+    vec![
+        encoder::int_option("plays", "再生回数 (0 = 無限)", (0, 1000), 0),
+        encoder::int_option("skip", "間引き (n フレームごと)", (1, 30), 1),
+        encoder::bool_option("delta", "変化した部分だけ書き込む", true),
+    ]
+}
+
+// Which rendered frames are kept and how long each one is shown
+pub struct Timing {
+    rate: FrameRate,
+    skip: Frame,
+    pushed: Frame,
+}
+
+impl Timing {
+    pub fn new(rate: FrameRate, options: &EncoderOptions) -> Timing {
+        Timing {
+            rate,
+            skip: options.int("skip", 1).clamp(1, 30),
+            pushed: 0,
+        }
+    }
+
+    // Call once per rendered frame. Returns the frame's position (in rendered
+    // frames) if it is kept.
+    pub fn next(&mut self) -> Option<Frame> {
+        let frame = self.pushed;
+        self.pushed += 1;
+        (frame % self.skip == 0).then_some(frame)
+    }
+
+    // Time from the start to `frame` in 1/100 s, rounded so delays don't drift
+    pub fn centiseconds(&self, frame: Frame) -> i64 {
+        let num = frame as i128 * 100 * self.rate.den as i128;
+        let den = self.rate.num.max(1) as i128;
+        ((2 * num + den) / (2 * den)) as i64
+    }
+
+    // How long the kept frame at `frame` is shown, in 1/100 s
+    pub fn delay_cs(&self, frame: Frame) -> i64 {
+        self.centiseconds(frame + self.skip) - self.centiseconds(frame)
+    }
+
+    // The same as a fraction of a second, (num, den)
+    pub fn delay_fraction(&self) -> (u64, u64) {
+        (
+            self.skip as u64 * self.rate.den as u64,
+            self.rate.num.max(1) as u64,
+        )
+    }
+}
+
+// Play count as written to the file: 0 = forever
+pub fn plays(options: &EncoderOptions) -> u16 {
+    options.int("plays", 0).clamp(0, u16::MAX as i64) as u16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn full(width: usize, height: usize) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+}
+
+// Bounding box of the pixels that differ; None if the frames are identical.
+// `channels` is 3 (RGB) or 4 (RGBA).
+pub fn changed_rect(
+    previous: &[u8],
+    current: &[u8],
+    width: usize,
+    height: usize,
+    channels: usize,
+) -> Option<Rect> {
+    let row = width * channels;
+    let differs = |y: usize| previous[y * row..(y + 1) * row] != current[y * row..(y + 1) * row];
+    let top = (0..height).find(|&y| differs(y))?;
+    let bottom = (top..height).rev().find(|&y| differs(y))?;
+    let column_differs = |x: usize| {
+        (top..=bottom).any(|y| {
+            let i = y * row + x * channels;
+            previous[i..i + channels] != current[i..i + channels]
+        })
+    };
+    let left = (0..width).find(|&x| column_differs(x))?;
+    let right = (left..width).rev().find(|&x| column_differs(x))?;
+    Some(Rect {
+        x: left,
+        y: top,
+        width: right - left + 1,
+        height: bottom - top + 1,
+    })
+}
+
+// The pixels of `rect`, packed
+pub fn crop(data: &[u8], width: usize, channels: usize, rect: Rect) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rect.width * rect.height * channels);
+    for y in rect.y..rect.y + rect.height {
+        let start = (y * width + rect.x) * channels;
+        out.extend_from_slice(&data[start..start + rect.width * channels]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::OptionValue;
+
+    fn timing(num: u32, den: u32, skip: i64) -> Timing {
+        let mut options = EncoderOptions::default();
+        options.set("skip", OptionValue::Int(skip));
+        Timing::new(FrameRate::new(num, den), &options)
+    }
+
+    #[test]
+    fn delays_round_without_drifting() {
+        // 30fps in 1/100 s: 3.33 per frame, shown as 3, 4, 3
+        let t = timing(30, 1, 1);
+        let delays: Vec<i64> = (0..6).map(|f| t.delay_cs(f)).collect();
+        assert_eq!(delays, [3, 4, 3, 3, 4, 3]);
+        // An hour at 29.97 adds up to the exact length
+        let t = timing(30000, 1001, 1);
+        let total: i64 = (0..107_892).map(|f| t.delay_cs(f)).sum();
+        assert_eq!(total, t.centiseconds(107_892));
+        assert_eq!(total, 360_000);
+    }
+
+    #[test]
+    fn skipping_keeps_every_nth_frame_for_longer() {
+        let mut t = timing(30, 1, 2);
+        let kept: Vec<Frame> = (0..6).filter_map(|_| t.next()).collect();
+        assert_eq!(kept, [0, 2, 4]);
+        let delays: Vec<i64> = kept.iter().map(|&f| t.delay_cs(f)).collect();
+        assert_eq!(delays, [7, 6, 7]);
+        assert_eq!(t.delay_fraction(), (2, 30));
+    }
+
+    // A 4x3 RGB picture, black apart from the given white pixels
+    fn pixels(white: &[(usize, usize)]) -> Vec<u8> {
+        let mut data = vec![0; 4 * 3 * 3];
+        for &(x, y) in white {
+            let i = (y * 4 + x) * 3;
+            data[i..i + 3].fill(255);
+        }
+        data
+    }
+
+    #[test]
+    fn changed_rect_bounds_every_changed_pixel() {
+        let blank = pixels(&[]);
+        assert_eq!(changed_rect(&blank, &blank, 4, 3, 3), None);
+        assert_eq!(
+            changed_rect(&blank, &pixels(&[(2, 1)]), 4, 3, 3),
+            Some(Rect {
+                x: 2,
+                y: 1,
+                width: 1,
+                height: 1
+            })
+        );
+        assert_eq!(
+            changed_rect(&pixels(&[(1, 0)]), &pixels(&[(3, 2)]), 4, 3, 3),
+            Some(Rect {
+                x: 1,
+                y: 0,
+                width: 3,
+                height: 3
+            })
+        );
+    }
+
+    #[test]
+    fn changed_rect_looks_at_alpha() {
+        // 2x1 RGBA where only the second pixel's alpha changes
+        let previous = [0, 0, 0, 255, 0, 0, 0, 255];
+        let current = [0, 0, 0, 255, 0, 0, 0, 0];
+        assert_eq!(
+            changed_rect(&previous, &current, 2, 1, 4),
+            Some(Rect {
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// Animated PNG. Each frame is compressed by gdk-pixbuf's PNG saver and its
+// IDAT data is moved into the animation (as fdAT after the first frame).
+
+use crate::animation::{self, Rect, Timing};
+use crate::encoder::{EncodeSettings, Encoder, EncoderOptions, OptionSpec, Picture};
+use crate::export::ExportError;
+use gdk_pixbuf::Pixbuf;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// Signature and IHDR come first, so acTL always starts here
+const ACTL_AT: u64 = 8 + 12 + 13;
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for part in parts {
+        for &byte in *part {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+    }
+    !crc
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+// IHDR and the joined IDAT data of a PNG file
+fn png_parts(png: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut header = None;
+    let mut data = Vec::new();
+    let mut at = SIGNATURE.len();
+    while at + 8 <= png.len() {
+        let length = u32::from_be_bytes(png[at..at + 4].try_into().ok()?) as usize;
+        let kind = &png[at + 4..at + 8];
+        let body = png.get(at + 8..at + 8 + length)?;
+        match kind {
+            b"IHDR" => header = Some(body.to_vec()),
+            b"IDAT" => data.extend_from_slice(body),
+            _ => {}
+        }
+        at += 12 + length;
+    }
+    Some((header?, data))
+}
+
+fn compress(picture: &Picture, rect: Rect) -> Result<(Vec<u8>, Vec<u8>), ExportError> {
+    let channels = if picture.alpha { 4 } else { 3 };
+    let pixels = animation::crop(picture.data, picture.width, channels, rect);
+    let pixbuf = Pixbuf::from_bytes(
+        &glib::Bytes::from_owned(pixels),
+        gdk_pixbuf::Colorspace::Rgb,
+        picture.alpha,
+        8,
+        rect.width as i32,
+        rect.height as i32,
+        (rect.width * channels) as i32,
+    );
+    let png = pixbuf.save_to_bufferv("png", &[])?;
+    png_parts(&png).ok_or_else(|| ExportError::Encoder("PNG を読み取れません".to_string()))
+}
+
+// A frame waiting for its delay to be known
+struct PendingFrame {
+    rect: Rect,
+    data: Vec<u8>,
+    // In kept frames; grows while the picture doesn't change
+    length: u64,
+}
+
+pub struct ApngEncoder {
+    path: PathBuf,
+    out: Option<BufWriter<File>>,
+    timing: Timing,
+    plays: u16,
+    delta: bool,
+    previous: Option<Vec<u8>>,
+    pending: Option<PendingFrame>,
+    // fcTL / fdAT sequence number
+    sequence: u32,
+    frames: u32,
+}
+
+impl ApngEncoder {
+    fn actl(&self) -> [u8; 8] {
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&self.frames.to_be_bytes());
+        data[4..].copy_from_slice(&(self.plays as u32).to_be_bytes());
+        data
+    }
+
+    // delay_num / delay_den as u16, falling back to milliseconds
+    fn delay(&self, length: u64) -> (u16, u16) {
+        let (num, den) = self.timing.delay_fraction();
+        let num = num * length;
+        let g = gcd(num, den);
+        let (num, den) = (num / g, den / g);
+        if num <= u16::MAX as u64 && den <= u16::MAX as u64 {
+            (num as u16, den as u16)
+        } else {
+            ((num * 1000 / den).min(u16::MAX as u64) as u16, 1000)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(frame) = self.pending.take() else {
+            return Ok(());
+        };
+        let (delay_num, delay_den) = self.delay(frame.length);
+        let Some(out) = &mut self.out else {
+            return Ok(());
+        };
+        let mut control = Vec::with_capacity(26);
+        control.extend_from_slice(&self.sequence.to_be_bytes());
+        for v in [
+            frame.rect.width,
+            frame.rect.height,
+            frame.rect.x,
+            frame.rect.y,
+        ] {
+            control.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        control.extend_from_slice(&delay_num.to_be_bytes());
+        control.extend_from_slice(&delay_den.to_be_bytes());
+        // dispose_op NONE, blend_op SOURCE: the area is replaced as it is
+        control.extend_from_slice(&[0, 0]);
+        write_chunk(out, b"fcTL", &control)?;
+        self.sequence += 1;
+
+        if self.frames == 0 {
+            write_chunk(out, b"IDAT", &frame.data)?;
+        } else {
+            let mut data = self.sequence.to_be_bytes().to_vec();
+            data.extend_from_slice(&frame.data);
+            write_chunk(out, b"fdAT", &data)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a.max(1) } else { gcd(b, a % b) }
+}
+
+impl Encoder for ApngEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        fs::create_dir_all(&settings.dir)?;
+        let path = settings.file("png");
+        Ok(Self {
+            out: Some(BufWriter::new(File::create(&path)?)),
+            path,
+            timing: Timing::new(settings.output.frame_rate, options),
+            plays: animation::plays(options),
+            delta: options.flag("delta", true),
+            previous: None,
+            pending: None,
+            sequence: 0,
+            frames: 0,
+        })
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        animation::options()
+    }
+
+    fn extension(_options: &EncoderOptions) -> Option<&'static str> {
+        Some("png")
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        if self.timing.next().is_none() {
+            return Ok(());
+        }
+        let (width, height) = (picture.width, picture.height);
+        let channels = if picture.alpha { 4 } else { 3 };
+        let rect = match &self.previous {
+            Some(previous) if self.delta => {
+                match animation::changed_rect(previous, picture.data, width, height, channels) {
+                    Some(rect) => rect,
+                    None => {
+                        if let Some(pending) = &mut self.pending {
+                            pending.length += 1;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            _ => Rect::full(width, height),
+        };
+
+        let (header, data) = compress(picture, rect)?;
+        // The first frame is also the still image, so it sets the header
+        // (acTL is rewritten with the frame count by `finish`)
+        let actl = self.actl();
+        if self.previous.is_none()
+            && let Some(out) = &mut self.out
+        {
+            out.write_all(&SIGNATURE)?;
+            write_chunk(out, b"IHDR", &header)?;
+            write_chunk(out, b"acTL", &actl)?;
+        }
+        self.flush()?;
+        self.pending = Some(PendingFrame {
+            rect,
+            data,
+            length: 1,
+        });
+        self.previous = Some(picture.data.to_vec());
+        Ok(())
+    }
+
+    fn push_audio(&mut self, _samples: &[i16]) -> Result<(), ExportError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        self.flush()?;
+        let actl = self.actl();
+        let Some(mut out) = self.out.take() else {
+            return Ok(());
+        };
+        if self.frames == 0 {
+            return Err(ExportError::Encoder(
+                "書き出したフレームがありません".to_string(),
+            ));
+        }
+        write_chunk(&mut out, b"IEND", &[])?;
+        // The frame count is only known now
+        out.seek(SeekFrom::Start(ACTL_AT))?;
+        write_chunk(&mut out, b"acTL", &actl)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::OptionValue;
+    use crate::encoder::tests::animated::{self, HEIGHT, WIDTH};
+
+    struct Control {
+        rect: Rect,
+        delay: (u16, u16),
+    }
+
+    // Size from IHDR, the acTL frame count and the fcTL of every frame, checking
+    // the CRCs and sequence numbers on the way
+    fn decode(png: &[u8]) -> (u32, u32, u32, Vec<Control>) {
+        let be32 =
+            |data: &[u8], at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        let be16 = |data: &[u8], at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        assert_eq!(png[..8], SIGNATURE);
+        let (mut size, mut frames, mut controls) = (None, None, Vec::new());
+        let mut sequence = 0;
+        let mut at = 8;
+        loop {
+            let length = be32(png, at) as usize;
+            let kind: &[u8; 4] = png[at + 4..at + 8].try_into().unwrap();
+            let body = &png[at + 8..at + 8 + length];
+            assert_eq!(be32(png, at + 8 + length), crc32(&[kind, body]));
+            at += 12 + length;
+            match kind {
+                b"IHDR" => size = Some((be32(body, 0), be32(body, 4))),
+                b"acTL" => frames = Some(be32(body, 0)),
+                b"fcTL" | b"fdAT" => {
+                    assert_eq!(be32(body, 0), sequence);
+                    sequence += 1;
+                    if kind == b"fcTL" {
+                        controls.push(Control {
+                            rect: Rect {
+                                width: be32(body, 4) as usize,
+                                height: be32(body, 8) as usize,
+                                x: be32(body, 12) as usize,
+                                y: be32(body, 16) as usize,
+                            },
+                            delay: (be16(body, 20), be16(body, 22)),
+                        });
+                    }
+                }
+                b"IEND" => break,
+                _ => {}
+            }
+        }
+        assert_eq!(at, png.len());
+        let (width, height) = size.unwrap();
+        (width, height, frames.unwrap(), controls)
+    }
+
+    #[test]
+    fn frames_decode_back_to_the_picture_size() {
+        let png =
+            animated::encode::<ApngEncoder>("apng-size", &Default::default(), &animated::moving(3));
+        let (width, height, frames, controls) = decode(&png);
+        assert_eq!((width as usize, height as usize), (WIDTH, HEIGHT));
+        assert_eq!(frames, 3);
+        assert_eq!(controls.len(), 3);
+        assert_eq!(controls[0].rect, Rect::full(WIDTH, HEIGHT));
+        // Later frames only cover the pixels that moved
+        for control in &controls[1..] {
+            assert!(control.rect.width <= 2 && control.rect.height == 1);
+        }
+    }
+
+    #[test]
+    fn an_unchanged_run_is_one_longer_frame() {
+        let png = animated::encode::<ApngEncoder>(
+            "apng-static",
+            &Default::default(),
+            &animated::with_a_still_run(),
+        );
+        let (_, _, frames, controls) = decode(&png);
+        assert_eq!(frames, 3);
+        let delays: Vec<(u16, u16)> = controls.iter().map(|c| c.delay).collect();
+        assert_eq!(delays, [(1, 30), (1, 10), (1, 30)]);
+    }
+
+    #[test]
+    fn skipped_frames_are_not_written() {
+        let mut options = EncoderOptions::default();
+        options.set("skip", OptionValue::Int(2));
+        let png = animated::encode::<ApngEncoder>("apng-skip", &options, &animated::moving(6));
+        let (width, height, frames, controls) = decode(&png);
+        assert_eq!((width as usize, height as usize), (WIDTH, HEIGHT));
+        assert_eq!(frames, 3);
+        let delays: Vec<(u16, u16)> = controls.iter().map(|c| c.delay).collect();
+        assert_eq!(delays, [(1, 15); 3]);
+    }
+}
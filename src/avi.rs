@@ -0,0 +1,321 @@
+// AVI (RIFF, AVI 1.0 with idx1) muxer: one video stream, uncompressed or MJPEG,
+// and one 16-bit PCM audio stream interleaved frame by frame.
+// Counts and sizes in the headers are placeholders until `finish`.
+
+use crate::project::FrameRate;
+use crate::wav;
+use std::io::{self, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AviCodec {
+    // 24-bit BGR DIB, bottom-up
+    Uncompressed,
+    // One JPEG per frame
+    Mjpeg,
+}
+
+impl AviCodec {
+    fn chunk_id(self) -> &'static [u8; 4] {
+        match self {
+            AviCodec::Uncompressed => b"00db",
+            AviCodec::Mjpeg => b"00dc",
+        }
+    }
+
+    fn handler(self) -> [u8; 4] {
+        match self {
+            AviCodec::Uncompressed => [0; 4],
+            AviCodec::Mjpeg => *b"MJPG",
+        }
+    }
+}
+
+// Packed RGB to the bottom-up BGR rows an uncompressed AVI frame holds.
+// Rows are padded to 4 bytes.
+pub fn rgb_to_dib(rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let stride = (width * 3).div_ceil(4) * 4;
+    let mut out = vec![0; stride * height];
+    for y in 0..height {
+        let dst = &mut out[(height - 1 - y) * stride..];
+        for x in 0..width {
+            let src = (y * width + x) * 3;
+            dst[x * 3] = rgb[src + 2];
+            dst[x * 3 + 1] = rgb[src + 1];
+            dst[x * 3 + 2] = rgb[src];
+        }
+    }
+    out
+}
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIF_ISINTERLEAVED: u32 = 0x100;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+struct IndexEntry {
+    id: [u8; 4],
+    // From the "movi" fourcc, so the first chunk is at 4
+    offset: u32,
+    size: u32,
+}
+
+// Header fields patched by `finish`
+#[derive(Default)]
+struct Placeholders {
+    riff_size: u64,
+    total_frames: u64,
+    suggested_buffer: u64,
+    video_length: u64,
+    video_buffer: u64,
+    audio_length: u64,
+    movi_size: u64,
+}
+
+fn le16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn le32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    le32(out, body.len() as u32);
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn list(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    let mut content = kind.to_vec();
+    content.extend_from_slice(body);
+    chunk(out, b"LIST", &content);
+}
+
+fn stream_header(
+    kind: &[u8; 4],
+    handler: [u8; 4],
+    scale: u32,
+    rate: u32,
+    sample_size: u32,
+    width: u16,
+    height: u16,
+) -> Vec<u8> {
+    let mut h = Vec::with_capacity(56);
+    h.extend_from_slice(kind);
+    h.extend_from_slice(&handler);
+    le32(&mut h, 0); // flags
+    le16(&mut h, 0); // priority
+    le16(&mut h, 0); // language
+    le32(&mut h, 0); // initial frames
+    le32(&mut h, scale);
+    le32(&mut h, rate);
+    le32(&mut h, 0); // start
+    le32(&mut h, 0); // length, patched
+    le32(&mut h, 0); // suggested buffer size, patched
+    le32(&mut h, u32::MAX); // quality: default
+    le32(&mut h, sample_size);
+    for v in [0, 0, width, height] {
+        le16(&mut h, v);
+    }
+    h
+}
+
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    codec: AviCodec,
+    channels: u16,
+    movi_start: u64,
+    index: Vec<IndexEntry>,
+    frames: u32,
+    audio_samples: u64,
+    largest_video: u32,
+    largest_chunk: u32,
+    at: Placeholders,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(
+        mut out: W,
+        codec: AviCodec,
+        width: u32,
+        height: u32,
+        rate: FrameRate,
+        sample_rate: u32,
+        channels: u16,
+    ) -> io::Result<Self> {
+        let block_align = channels * wav::BITS_PER_SAMPLE / 8;
+        let mut at = Placeholders::default();
+
+        let mut avih = Vec::with_capacity(56);
+        let frame_us = 1_000_000u64 * rate.den as u64 / rate.num.max(1) as u64;
+        le32(&mut avih, frame_us as u32);
+        le32(&mut avih, 0); // max bytes per second
+        le32(&mut avih, 0); // padding granularity
+        le32(&mut avih, AVIF_HASINDEX | AVIF_ISINTERLEAVED);
+        le32(&mut avih, 0); // total frames, patched
+        le32(&mut avih, 0); // initial frames
+        le32(&mut avih, 2); // streams
+        le32(&mut avih, 0); // suggested buffer size, patched
+        le32(&mut avih, width);
+        le32(&mut avih, height);
+        avih.extend_from_slice(&[0; 16]);
+
+        let (w16, h16) = (
+            width.min(u16::MAX as u32) as u16,
+            height.min(u16::MAX as u32) as u16,
+        );
+        let video_strh = stream_header(b"vids", codec.handler(), rate.den, rate.num, 0, w16, h16);
+        let mut bitmap = Vec::with_capacity(40);
+        le32(&mut bitmap, 40);
+        le32(&mut bitmap, width);
+        le32(&mut bitmap, height);
+        le16(&mut bitmap, 1); // planes
+        le16(&mut bitmap, 24);
+        bitmap.extend_from_slice(&codec.handler()); // compression; 0 = BI_RGB
+        let image_size = match codec {
+            AviCodec::Uncompressed => (width * 3).div_ceil(4) * 4 * height,
+            AviCodec::Mjpeg => width * height * 3,
+        };
+        le32(&mut bitmap, image_size);
+        bitmap.extend_from_slice(&[0; 16]);
+
+        let audio_strh = stream_header(
+            b"auds",
+            [0; 4],
+            block_align as u32,
+            sample_rate * block_align as u32,
+            block_align as u32,
+            0,
+            0,
+        );
+        let mut wave_format = wav::format_chunk(sample_rate, channels);
+        le16(&mut wave_format, 0); // cbSize
+
+        let mut video_strl = Vec::new();
+        chunk(&mut video_strl, b"strh", &video_strh);
+        chunk(&mut video_strl, b"strf", &bitmap);
+        let mut audio_strl = Vec::new();
+        chunk(&mut audio_strl, b"strh", &audio_strh);
+        chunk(&mut audio_strl, b"strf", &wave_format);
+        let mut hdrl = Vec::new();
+        chunk(&mut hdrl, b"avih", &avih);
+        list(&mut hdrl, b"strl", &video_strl);
+        list(&mut hdrl, b"strl", &audio_strl);
+
+        // Offsets inside the file: "RIFF" size "AVI " "LIST" size "hdrl" = 24 bytes,
+        // then the avih chunk header (8) and the strl lists
+        at.riff_size = 4;
+        let avih_body = 24 + 8;
+        at.total_frames = avih_body + 16;
+        at.suggested_buffer = avih_body + 28;
+        let video_strh_body = avih_body + 56 + 12 + 8;
+        at.video_length = video_strh_body + 32;
+        at.video_buffer = video_strh_body + 36;
+        let audio_strh_body = video_strh_body + 56 + 8 + 40 + 12 + 8;
+        at.audio_length = audio_strh_body + 32;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"AVI ")?;
+        let mut head = Vec::new();
+        list(&mut head, b"hdrl", &hdrl);
+        out.write_all(&head)?;
+        at.movi_size = 12 + head.len() as u64 + 4;
+        out.write_all(b"LIST")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"movi")?;
+        let movi_start = at.movi_size + 4;
+
+        Ok(Self {
+            out,
+            codec,
+            channels,
+            movi_start,
+            index: Vec::new(),
+            frames: 0,
+            audio_samples: 0,
+            largest_video: 0,
+            largest_chunk: 0,
+            at,
+        })
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], data: &[u8]) -> io::Result<u32> {
+        let position = self.out.stream_position()?;
+        let size = data.len() as u64;
+        // idx1 entries and the RIFF header only hold 32-bit sizes
+        let limit = u32::MAX as u64 - 16 * (self.index.len() as u64 + 1) - 1024;
+        if position + 8 + size + 1 > limit {
+            return Err(io::Error::other(
+                "AVI ファイルが 4GB を超えます (MJPEG にするか範囲を分けてください)",
+            ));
+        }
+        self.out.write_all(&id)?;
+        self.out.write_all(&(size as u32).to_le_bytes())?;
+        self.out.write_all(data)?;
+        if size % 2 == 1 {
+            self.out.write_all(&[0])?;
+        }
+        self.index.push(IndexEntry {
+            id,
+            offset: (position - self.movi_start) as u32,
+            size: size as u32,
+        });
+        self.largest_chunk = self.largest_chunk.max(size as u32);
+        Ok(size as u32)
+    }
+
+    // One frame: DIB rows for uncompressed, a JPEG file for MJPEG
+    pub fn write_video(&mut self, data: &[u8]) -> io::Result<()> {
+        let size = self.write_chunk(*self.codec.chunk_id(), data)?;
+        self.largest_video = self.largest_video.max(size);
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Interleaved 16-bit samples following the last video frame
+    pub fn write_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.write_chunk(*b"01wb", &bytes)?;
+        self.audio_samples += (samples.len() / self.channels as usize) as u64;
+        Ok(())
+    }
+
+    fn patch(&mut self, position: u64, value: u32) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(position))?;
+        self.out.write_all(&value.to_le_bytes())
+    }
+
+    // Write the index and the final counts. Also makes a cut-short export playable.
+    pub fn finish(mut self) -> io::Result<W> {
+        let movi_end = self.out.stream_position()?;
+        let mut idx1 = Vec::with_capacity(self.index.len() * 16);
+        for entry in &self.index {
+            idx1.extend_from_slice(&entry.id);
+            le32(&mut idx1, AVIIF_KEYFRAME);
+            le32(&mut idx1, entry.offset);
+            le32(&mut idx1, entry.size);
+        }
+        let mut tail = Vec::new();
+        chunk(&mut tail, b"idx1", &idx1);
+        self.out.write_all(&tail)?;
+        let end = self.out.stream_position()?;
+
+        let at = std::mem::take(&mut self.at);
+        self.patch(at.riff_size, (end - 8) as u32)?;
+        self.patch(at.total_frames, self.frames)?;
+        self.patch(at.suggested_buffer, self.largest_chunk)?;
+        self.patch(at.video_length, self.frames)?;
+        self.patch(at.video_buffer, self.largest_video)?;
+        self.patch(at.audio_length, self.audio_samples as u32)?;
+        self.patch(at.movi_size, (movi_end - self.movi_start) as u32)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,433 @@
+// `luvita render`: export a project from the command line without creating a
+// window, for batch rendering
+
+use crate::config::ConfigFile;
+use crate::encoder::{self, Backend, EncoderOptions, OptionKind, OptionValue};
+use crate::export::{self, ExportJob, ExportRange, RangeKind};
+use crate::profile::{self, Profiles};
+use crate::project_file;
+use crate::timeline::Frame;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub const COMMAND: &str = "render";
+
+// Exit codes
+const EXIT_USAGE: i32 = 2;
+const EXIT_PROJECT: i32 = 3;
+const EXIT_MISSING_MEDIA: i32 = 4;
+const EXIT_EXPORT: i32 = 5;
+
+const USAGE: &str = "\
+使い方: luvita render <プロジェクト.luvita> -o <出力> [オプション]
+
+  -o, --output <パス>       出力ファイル。拡張子から形式を選びます
+                            (.y4m .avi .gif .png=APNG .tif=連番 .mp4 .mov .webm=ffmpeg)
+                            連番は <名前>_000000.tif のように番号が付きます
+      --range <開始-終了>   書き出すフレーム (終了は含まない)。
+                            省略時はイン点〜アウト点、なければプロジェクト全体
+      --profile <名前>      出力プロファイルに切り替えてから書き出す
+      --format <形式>       sequence, gif, apng, y4m, avi, ffmpeg
+      --scale <%>           拡大率 (既定 100)
+      --alpha               背景を透明にする (sequence, gif, apng)
+      --option <キー=値>    形式ごとのオプション (繰り返し可)
+  -q, --quiet               進行状況を表示しない
+  -h, --help                このヘルプ
+
+終了コード: 0 成功, 2 引数の誤り, 3 プロジェクトを読めない,
+            4 素材ファイルが見つからない, 5 書き出しに失敗";
+
+struct Args {
+    project: PathBuf,
+    output: PathBuf,
+    range: Option<(Frame, Frame)>,
+    profile: Option<String>,
+    format: Option<String>,
+    scale: f64,
+    alpha: bool,
+    options: Vec<(String, String)>,
+    quiet: bool,
+}
+
+fn parse_range(text: &str) -> Option<(Frame, Frame)> {
+    let (start, end) = text.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    (0 <= start && start < end).then_some((start, end))
+}
+
+// Ok(None) means --help
+fn parse_args(args: &[String]) -> Result<Option<Args>, String> {
+    let mut project = None;
+    let mut output = None;
+    let mut parsed = Args {
+        project: PathBuf::new(),
+        output: PathBuf::new(),
+        range: None,
+        profile: None,
+        format: None,
+        scale: 100.0,
+        alpha: false,
+        options: Vec::new(),
+        quiet: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} の値がありません", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+            "--range" => {
+                let text = value(arg)?;
+                parsed.range = Some(
+                    parse_range(&text)
+                        .ok_or_else(|| format!("範囲が正しくありません: {}", text))?,
+                );
+            }
+            "--profile" => parsed.profile = Some(value(arg)?),
+            "--format" => parsed.format = Some(value(arg)?),
+            "--scale" => {
+                let text = value(arg)?;
+                parsed.scale = text
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| (1.0..=1000.0).contains(s))
+                    .ok_or_else(|| format!("拡大率が正しくありません: {}", text))?;
+            }
+            "--alpha" => parsed.alpha = true,
+            "--option" => {
+                let text = value(arg)?;
+                let (key, v) = text
+                    .split_once('=')
+                    .ok_or_else(|| format!("キー=値 の形で指定してください: {}", text))?;
+                parsed.options.push((key.to_string(), v.to_string()));
+            }
+            "-q" | "--quiet" => parsed.quiet = true,
+            _ if arg.starts_with('-') => return Err(format!("不明なオプション: {}", arg)),
+            _ if project.is_none() => project = Some(PathBuf::from(arg)),
+            _ => return Err(format!("余分な引数: {}", arg)),
+        }
+    }
+    parsed.project = project.ok_or("プロジェクトファイルを指定してください")?;
+    parsed.output = output.ok_or("出力先を -o で指定してください")?;
+    Ok(Some(parsed))
+}
+
+// Backend id (and a default option) that fits the output file's extension
+fn format_for(path: &Path) -> Option<(&'static str, Option<(&'static str, &'static str)>)> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "y4m" => ("y4m", None),
+        "avi" => ("avi", None),
+        "gif" => ("gif", None),
+        "png" => ("apng", None),
+        "tif" | "tiff" => ("sequence", Some(("format", "tiff"))),
+        "mp4" => ("ffmpeg", Some(("codec", "libx264"))),
+        "webm" => ("ffmpeg", Some(("codec", "libvpx-vp9"))),
+        "mov" => ("ffmpeg", Some(("codec", "prores_ks"))),
+        _ => return None,
+    })
+}
+
+// A backend writing one file names it `<stem>.<its extension>`, so that has to
+// be the extension of `output` (in any case; `run` renames the file to match).
+// Numbered files use `output` as the prefix.
+fn check_extension(
+    backend: &Backend,
+    options: &EncoderOptions,
+    output: &Path,
+) -> Result<(), String> {
+    match (backend.extension)(options) {
+        Some(extension)
+            if !output
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case(extension)) =>
+        {
+            Err(format!(
+                "{} は .{} のファイルを書き出します。-o の拡張子を .{} にしてください",
+                backend.name, extension, extension
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Read `key=value` pairs as the backend's option types
+fn parse_options(backend: &Backend, pairs: &[(String, String)]) -> Result<EncoderOptions, String> {
+    let specs = (backend.options)();
+    let mut options = EncoderOptions::default();
+    for (key, text) in pairs {
+        let spec = specs.iter().find(|s| s.key == key).ok_or_else(|| {
+            let keys: Vec<&str> = specs.iter().map(|s| s.key).collect();
+            format!(
+                "{} にオプション {} はありません (使えるもの: {})",
+                backend.id,
+                key,
+                keys.join(", ")
+            )
+        })?;
+        let invalid = || format!("{} の値が正しくありません: {}", key, text);
+        let value = match &spec.kind {
+            OptionKind::Choice(choices) => {
+                let keys: Vec<&str> = choices.iter().map(|c| c.0).collect();
+                if !keys.contains(&text.as_str()) {
+                    return Err(format!("{} ({})", invalid(), keys.join(", ")));
+                }
+                OptionValue::Choice(text.clone())
+            }
+            OptionKind::Int { min, max } => OptionValue::Int(
+                text.parse::<i64>()
+                    .ok()
+                    .filter(|v| (*min..=*max).contains(v))
+                    .ok_or_else(invalid)?,
+            ),
+            OptionKind::Bool => OptionValue::Bool(match text.as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                _ => return Err(invalid()),
+            }),
+            OptionKind::Text => OptionValue::Text(text.clone()),
+        };
+        options.set(key, value);
+    }
+    Ok(options)
+}
+
+// "[##########··········]  50%  150/300  24.3 fps"
+fn progress_line(done: Frame, total: Frame, started: Instant) -> String {
+    const WIDTH: usize = 30;
+    let fraction = done as f64 / total.max(1) as f64;
+    let filled = (fraction * WIDTH as f64).round() as usize;
+    let seconds = started.elapsed().as_secs_f64();
+    let fps = if seconds > 0.0 {
+        done as f64 / seconds
+    } else {
+        0.0
+    };
+    format!(
+        "[{}{}] {:3.0}%  {}/{}  {:.1} fps",
+        "#".repeat(filled),
+        "·".repeat(WIDTH - filled),
+        fraction * 100.0,
+        done,
+        total,
+        fps
+    )
+}
+
+// Returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let args = match parse_args(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("luvita render: {}\n\n{}", e, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut project = match project_file::load(&args.project) {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("{}: {}", args.project.display(), e);
+            return EXIT_PROJECT;
+        }
+    };
+    if let Some(name) = &args.profile {
+        let Some(found) = Profiles::load_user().find(name) else {
+            let names: Vec<String> = Profiles::load_user()
+                .all()
+                .into_iter()
+                .map(|p| p.name)
+                .collect();
+            eprintln!(
+                "プロファイル {} がありません (使えるもの: {})",
+                name,
+                names.join(", ")
+            );
+            return EXIT_USAGE;
+        };
+        if let Err(e) = profile::apply(&mut project, &found, true) {
+            eprintln!("プロファイル {}: {}", name, e);
+            return EXIT_PROJECT;
+        }
+    }
+
+    let guessed = format_for(&args.output);
+    let Some(id) = args.format.as_deref().or(guessed.map(|g| g.0)) else {
+        eprintln!(
+            "{} の形式が分かりません。--format で指定してください",
+            args.output.display()
+        );
+        return EXIT_USAGE;
+    };
+    let backends = encoder::backends();
+    let Some(backend) = backends.iter().find(|b| b.id == id) else {
+        let ids: Vec<&str> = backends.iter().map(|b| b.id).collect();
+        eprintln!("形式 {} は使えません (使えるもの: {})", id, ids.join(", "));
+        return EXIT_USAGE;
+    };
+    let mut options = match parse_options(backend, &args.options) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    // The extension picks e.g. the ffmpeg codec unless it was given
+    if let Some((guessed_id, Some((key, value)))) = guessed
+        && guessed_id == backend.id
+        && !options.0.contains_key(key)
+    {
+        options.set(key, OptionValue::Choice(value.to_string()));
+    }
+    if let Err(e) = check_extension(backend, &options, &args.output) {
+        eprintln!("{}", e);
+        return EXIT_USAGE;
+    }
+
+    let range = args.range.or_else(|| {
+        let kind = if project.in_point.is_some() || project.out_point.is_some() {
+            RangeKind::InOut
+        } else {
+            RangeKind::Project
+        };
+        export::range(&project, kind, &[])
+    });
+    let Some((start, end)) = range else {
+        eprintln!("書き出すフレームがありません");
+        return EXIT_PROJECT;
+    };
+    let missing = export::missing_media(&project, start, end);
+    if !missing.is_empty() {
+        eprintln!("素材ファイルが見つかりません:");
+        for path in &missing {
+            eprintln!("  {}", path.display());
+        }
+        return EXIT_MISSING_MEDIA;
+    }
+
+    let dir = match args.output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = args
+        .output
+        .file_stem()
+        .map_or("export".to_string(), |s| s.to_string_lossy().into_owned());
+    let range = ExportRange {
+        start,
+        end,
+        scale: args.scale / 100.0,
+        alpha: args.alpha,
+    };
+    let mut job = match ExportJob::new(&project, range, backend, &options, dir, name) {
+        Ok(job) => job,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_EXPORT;
+        }
+    };
+
+    // A bar redrawn in place on a terminal, a line every 10% in logs
+    let terminal = std::io::stderr().is_terminal();
+    let started = Instant::now();
+    let mut reported = -1;
+    if !args.quiet {
+        eprintln!("{} → {}", args.project.display(), job.output().display());
+    }
+    loop {
+        match job.step() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                if terminal && !args.quiet {
+                    eprintln!();
+                }
+                eprintln!("{}", e);
+                return EXIT_EXPORT;
+            }
+        }
+        if args.quiet {
+            continue;
+        }
+        let (done, total) = (job.done(), job.total());
+        if terminal {
+            eprint!("\r{}", progress_line(done, total, started));
+            let _ = std::io::stderr().flush();
+        } else if done * 10 / total.max(1) != reported {
+            reported = done * 10 / total.max(1);
+            eprintln!("{}", progress_line(done, total, started));
+        }
+    }
+    // out.Y4M was written as out.y4m
+    let written = job.output();
+    if (backend.extension)(&options).is_some()
+        && written.file_name() != args.output.file_name()
+        && let Err(e) = std::fs::rename(&written, &args.output)
+    {
+        eprintln!(
+            "{} を {} にできません: {}",
+            written.display(),
+            args.output.display(),
+            e
+        );
+        return EXIT_EXPORT;
+    }
+    if !args.quiet {
+        if terminal {
+            eprintln!();
+        }
+        eprintln!(
+            "{} フレームを {:.1} 秒で書き出しました",
+            job.total(),
+            started.elapsed().as_secs_f64()
+        );
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(id: &str) -> Backend {
+        encoder::backends()
+            .into_iter()
+            .find(|b| b.id == id)
+            .unwrap()
+    }
+
+    #[test]
+    fn output_extension_picks_a_backend() {
+        let id = |path: &str| format_for(Path::new(path)).map(|f| f.0);
+        assert_eq!(id("out.Y4M"), Some("y4m"));
+        assert_eq!(id("dir/out.png"), Some("apng"));
+        assert_eq!(id("frames.tif"), Some("sequence"));
+        assert_eq!(id("out.webm"), Some("ffmpeg"));
+        assert_eq!(id("out.mkv"), None);
+        assert_eq!(id("out"), None);
+    }
+
+    #[test]
+    fn output_has_to_end_in_what_the_backend_writes() {
+        let options = EncoderOptions::default();
+        let check = |id: &str, path: &str| check_extension(&backend(id), &options, Path::new(path));
+        assert!(check("y4m", "out.y4m").is_ok());
+        assert!(check("y4m", "dir/OUT.Y4M").is_ok());
+        assert!(check("gif", "out.Gif").is_ok());
+        assert!(check("gif", "out.y4m").is_err());
+        assert!(check("apng", "out.apng").is_err());
+        assert!(check("avi", "out").is_err());
+        // Numbered files only take the name from the output
+        assert!(check("sequence", "frames").is_ok());
+    }
+}
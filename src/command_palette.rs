@@ -0,0 +1,147 @@
+// Ctrl+Shift+P: search every action and run it
+
+use crate::actions::Keymap;
+use crate::palette::{self, PaletteItem, Param, Recent};
+use crate::project::Project;
+use crate::shortcut_editor::accel_label;
+use gtk4::gdk::Key;
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box as GtkBox, EventControllerKey, Label, ListBox, Orientation, ScrolledWindow,
+    SearchEntry,
+};
+use libadwaita::ApplicationWindow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub fn show(
+    window: &ApplicationWindow,
+    keymap: Rc<RefCell<Keymap>>,
+    project: Rc<RefCell<Project>>,
+    recent: Rc<RefCell<Recent>>,
+) {
+    // Not attached to the application: single-key shortcuts must not fire while typing
+    let dialog = gtk4::Window::builder()
+        .transient_for(window)
+        .modal(true)
+        .decorated(false)
+        .default_width(560)
+        .default_height(420)
+        .build();
+
+    let content = GtkBox::new(Orientation::Vertical, 6);
+    content.set_margin_top(8);
+    content.set_margin_bottom(8);
+    content.set_margin_start(8);
+    content.set_margin_end(8);
+    let entry = SearchEntry::new();
+    entry.set_placeholder_text(Some("コマンドを検索 / フレーム番号・時間で移動"));
+    entry.set_search_delay(0);
+    content.append(&entry);
+    let list = ListBox::new();
+    let scrolled = ScrolledWindow::builder().child(&list).vexpand(true).build();
+    content.append(&scrolled);
+    dialog.set_child(Some(&content));
+
+    let (all_items, rate) = {
+        let project = project.borrow();
+        let rate = project.output.frame_rate;
+        (
+            palette::items(&keymap.borrow(), &project.timeline.markers, rate),
+            rate,
+        )
+    };
+    // Rows in the list, in order
+    let shown: Rc<RefCell<Vec<PaletteItem>>> = Rc::new(RefCell::new(Vec::new()));
+
+    let populate = {
+        let (list, shown, recent) = (list.clone(), shown.clone(), recent.clone());
+        move |query: &str| {
+            while let Some(row) = list.first_child() {
+                list.remove(&row);
+            }
+            let ranked = palette::rank(all_items.clone(), query, &recent.borrow(), rate);
+            for item in &ranked {
+                let row = GtkBox::new(Orientation::Horizontal, 12);
+                let title = Label::new(Some(&item.title));
+                title.set_halign(Align::Start);
+                title.set_hexpand(true);
+                let keys: Vec<String> = item.accels.iter().map(|a| accel_label(a)).collect();
+                let shortcut = Label::new(Some(&keys.join(", ")));
+                shortcut.add_css_class("dim-label");
+                row.append(&title);
+                row.append(&shortcut);
+                list.append(&row);
+            }
+            list.select_row(list.row_at_index(0).as_ref());
+            *shown.borrow_mut() = ranked;
+        }
+    };
+    populate("");
+    entry.connect_search_changed(move |entry| populate(&entry.text()));
+
+    // Close first so dialogs opened by the command get the main window as parent
+    let run = {
+        let (dialog, window) = (dialog.clone(), window.clone());
+        let (shown, recent) = (shown.clone(), recent.clone());
+        move |index: i32| {
+            let Some(item) = usize::try_from(index)
+                .ok()
+                .and_then(|i| shown.borrow().get(i).cloned())
+            else {
+                return;
+            };
+            recent.borrow_mut().push(item.id());
+            dialog.close();
+            let param = match item.param {
+                Param::None => None,
+                Param::Str(s) => Some(s.to_variant()),
+                Param::Frame(f) => Some(f.to_variant()),
+            };
+            if let Err(e) = WidgetExt::activate_action(&window, &item.action, param.as_ref()) {
+                eprintln!("{}: {}", item.action, e);
+            }
+        }
+    };
+    let run = Rc::new(run);
+    {
+        let (list, run) = (list.clone(), run.clone());
+        entry.connect_activate(move |_| {
+            if let Some(row) = list.selected_row() {
+                run(row.index());
+            }
+        });
+    }
+    list.connect_row_activated(move |_, row| run(row.index()));
+
+    // Up / Down move through the results while typing, Esc closes
+    let keys = EventControllerKey::new();
+    {
+        let (list, dialog, scrolled) = (list.clone(), dialog.clone(), scrolled.clone());
+        keys.connect_key_pressed(move |_, key, _, _| {
+            let step = match key {
+                Key::Up => -1,
+                Key::Down => 1,
+                Key::Escape => {
+                    dialog.close();
+                    return glib::Propagation::Stop;
+                }
+                _ => return glib::Propagation::Proceed,
+            };
+            let current = list.selected_row().map_or(0, |r| r.index());
+            if let Some(row) = list.row_at_index((current + step).max(0)) {
+                list.select_row(Some(&row));
+                // Focus stays in the entry, so scroll by hand
+                let area = row.allocation();
+                scrolled
+                    .vadjustment()
+                    .clamp_page(area.y() as f64, (area.y() + area.height()) as f64);
+            }
+            glib::Propagation::Stop
+        });
+    }
+    entry.add_controller(keys);
+
+    dialog.present();
+    entry.grab_focus();
+}
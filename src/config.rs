@@ -0,0 +1,189 @@
+// Per-user files under ~/.config/luvita (and ~/.cache/luvita)
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn config_dir() -> PathBuf {
+    // Tests save through the same code but never over the user's files
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("luvita-config-{}", std::process::id()));
+    }
+    glib::user_config_dir().join("luvita")
+}
+
+pub fn config_file(name: &str) -> PathBuf {
+    config_dir().join(name)
+}
+
+// Periodic copies of the open project (環境設定 / 自動保存)
+pub fn autosave_file(name: &str) -> PathBuf {
+    glib::user_cache_dir()
+        .join("luvita")
+        .join("autosave")
+        .join(name)
+}
+
+// Project snapshots of queued exports (ファイル / レンダーキュー)
+pub fn queue_file(name: &str) -> PathBuf {
+    config_dir().join("queue").join(name)
+}
+
+// Write through a temporary file so a crash never leaves half a file behind
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+// MIGRATIONS[n] upgrades a version n + 1 document to version n + 2
+pub type Migration = fn(&mut Value);
+
+// Bring a document of `version` (1 or later) up to date step by step
+pub fn migrate(document: &mut Value, version: u32, migrations: &[Migration]) {
+    for migration in migrations.iter().skip(version as usize - 1) {
+        migration(document);
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Json {
+        what: &'static str,
+        error: serde_json::Error,
+    },
+    BadVersion {
+        what: &'static str,
+    },
+    TooNew {
+        what: &'static str,
+        version: u32,
+        supported: u32,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "ファイルを読み書きできません: {}", e),
+            ConfigError::Json { what, error } => {
+                write!(f, "{}のファイルが壊れています: {}", what, error)
+            }
+            ConfigError::BadVersion { what } => {
+                write!(f, "{}のファイルのバージョン情報が正しくありません", what)
+            }
+            ConfigError::TooNew {
+                what,
+                version,
+                supported,
+            } => write!(
+                f,
+                "新しいバージョン ({}) の{}です (対応: {})",
+                version, what, supported
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+// Something kept as JSON in the config dir, with a top-level "version".
+// Implementors say how they map to the stored document; reading, writing and
+// upgrading old files is shared.
+pub trait ConfigFile: Default {
+    // File name in the config dir
+    const NAME: &'static str;
+    // What the file holds, for messages (e.g. "キー設定")
+    const WHAT: &'static str;
+    // Bump this when a field is renamed or changes meaning and add a migration.
+    // New fields only need a default.
+    const VERSION: u32;
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    // Everything written apart from the version
+    type Document: Serialize + DeserializeOwned;
+
+    fn to_document(&self) -> Self::Document;
+
+    // Fix up or drop whatever a hand-edited file got wrong
+    fn from_document(document: Self::Document) -> Self;
+
+    fn to_json(&self) -> Result<String, ConfigError> {
+        let json = |error| ConfigError::Json {
+            what: Self::WHAT,
+            error,
+        };
+        let mut document = serde_json::to_value(self.to_document()).map_err(json)?;
+        if let Value::Object(fields) = &mut document {
+            fields.insert("version".to_string(), Self::VERSION.into());
+        }
+        serde_json::to_string_pretty(&document).map_err(json)
+    }
+
+    fn parse(text: &str) -> Result<Self, ConfigError> {
+        let json = |error| ConfigError::Json {
+            what: Self::WHAT,
+            error,
+        };
+        let mut document: Value = serde_json::from_str(text).map_err(json)?;
+        // Files written before versioning count as version 1
+        let version = match document.get("version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .filter(|&v| v >= 1)
+                .ok_or(ConfigError::BadVersion { what: Self::WHAT })?,
+        };
+        if version > Self::VERSION as u64 {
+            return Err(ConfigError::TooNew {
+                what: Self::WHAT,
+                version: version.try_into().unwrap_or(u32::MAX),
+                supported: Self::VERSION,
+            });
+        }
+        migrate(&mut document, version as u32, Self::MIGRATIONS);
+        Ok(Self::from_document(
+            serde_json::from_value(document).map_err(json)?,
+        ))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        write_atomic(path, &self.to_json()?)?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // The user's file, or the defaults if there is none yet
+    fn load_user() -> Self {
+        let path = config_file(Self::NAME);
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    fn save_user(&self) {
+        let path = config_file(Self::NAME);
+        if let Err(e) = self.save(&path) {
+            eprintln!("{}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// Mouse editing on the timeline: move, trim and rubber-band selection
+
+use crate::history::Edit;
+use crate::timeline::{ClipId, Frame, Timeline};
+use crate::view::HitPart;
+
+#[derive(Default)]
+pub enum DragMode {
+    #[default]
+    Idle,
+    // Playhead follows the pointer
+    Seek,
+    // Rubber band from (x0, y0) to (x1, y1); `base` was selected before the drag
+    Select {
+        base: Vec<ClipId>,
+        rect: (f64, f64, f64, f64),
+    },
+    // (id, layer, start) of every selected object when the drag began
+    Move {
+        origins: Vec<(ClipId, usize, Frame)>,
+    },
+    Trim {
+        id: ClipId,
+        edge: HitPart,
+        start: Frame,
+        length: Frame,
+    },
+}
+
+// Edit moving the objects by (frames, layers) from where the drag began.
+// None if an object is on a locked layer, would leave the timeline, overlap
+// another object or not move.
+pub fn move_edit(
+    timeline: &Timeline,
+    origins: &[(ClipId, usize, Frame)],
+    frames: Frame,
+    layers: i64,
+) -> Option<Edit> {
+    let ids: Vec<ClipId> = origins.iter().map(|o| o.0).collect();
+    let mut edits = Vec::new();
+    let mut changed = false;
+    for &(id, layer, start) in origins {
+        let clip = timeline.clip(id)?;
+        // A rubber band can pick up objects on locked layers too
+        if timeline.layers.get(layer).is_none_or(|l| l.locked) {
+            return None;
+        }
+        let new_layer = layer as i64 + layers;
+        let new_start = start + frames;
+        if new_start < 0 || new_layer < 0 || new_layer >= timeline.layers.len() as i64 {
+            return None;
+        }
+        let new_layer = new_layer as usize;
+        if timeline.layers[new_layer].locked
+            || !timeline.is_free(new_layer, new_start, clip.length, &ids)
+        {
+            return None;
+        }
+        changed |= (clip.layer, clip.start) != (new_layer, new_start);
+        edits.push(Edit::MoveClip {
+            id,
+            from: (layer, start),
+            to: (new_layer, new_start),
+        });
+    }
+    if !changed {
+        return None;
+    }
+    Some(Edit::Batch {
+        name: "移動".to_string(),
+        edits,
+    })
+}
+
+// Edit moving one edge of an object by `frames`
+pub fn trim_edit(
+    timeline: &Timeline,
+    id: ClipId,
+    edge: HitPart,
+    start: Frame,
+    length: Frame,
+    frames: Frame,
+) -> Option<Edit> {
+    let clip = timeline.clip(id)?;
+    let (new_start, new_length) = match edge {
+        HitPart::LeftEdge => {
+            let delta = frames.clamp(-start, length - 1);
+            (start + delta, length - delta)
+        }
+        HitPart::RightEdge => (start, (length + frames).max(1)),
+        HitPart::Body => return None,
+    };
+    if (clip.start, clip.length) == (new_start, new_length)
+        || !timeline.is_free(clip.layer, new_start, new_length, &[id])
+    {
+        return None;
+    }
+    Some(Edit::TrimClip {
+        id,
+        from: (start, length),
+        to: (new_start, new_length),
+    })
+}
+
+// Objects touching the frame / layer rectangle
+pub fn clips_in_range(
+    timeline: &Timeline,
+    frames: (Frame, Frame),
+    layers: (usize, usize),
+) -> Vec<ClipId> {
+    timeline
+        .clips
+        .iter()
+        .filter(|c| c.layer >= layers.0 && c.layer <= layers.1)
+        .filter(|c| c.end() > frames.0 && c.start <= frames.1)
+        .map(|c| c.id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::{ShapeKind, Source};
+
+    fn shape() -> Source {
+        Source::Shape(ShapeKind::Rectangle)
+    }
+
+    #[test]
+    fn objects_move_together() {
+        let mut timeline = Timeline::new(3);
+        let a = timeline.add_clip(0, 0, 10, shape());
+        let b = timeline.add_clip(1, 20, 10, shape());
+        let origins = [(a, 0, 0), (b, 1, 20)];
+        let Some(Edit::Batch { edits, .. }) = move_edit(&timeline, &origins, 5, 1) else {
+            panic!("expected a move");
+        };
+        assert_eq!(edits.len(), 2);
+        assert!(move_edit(&timeline, &origins, 0, 0).is_none());
+        assert!(move_edit(&timeline, &origins, -1, 0).is_none());
+        assert!(move_edit(&timeline, &origins, 0, 2).is_none());
+    }
+
+    #[test]
+    fn objects_on_locked_layers_stay() {
+        let mut timeline = Timeline::new(3);
+        let free = timeline.add_clip(0, 0, 10, shape());
+        let locked = timeline.add_clip(1, 0, 10, shape());
+        timeline.layers[1].locked = true;
+        let origins = [(free, 0, 0), (locked, 1, 0)];
+        assert!(move_edit(&timeline, &origins, 0, 1).is_none());
+        assert!(move_edit(&timeline, &origins, 30, 0).is_none());
+        // Nor can anything be moved onto one
+        assert!(move_edit(&timeline, &[(free, 0, 0)], 20, 1).is_none());
+        assert!(move_edit(&timeline, &[(free, 0, 0)], 20, 0).is_some());
+    }
+}
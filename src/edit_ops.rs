@@ -0,0 +1,497 @@
+// Split / delete / ripple operations. Each returns one undoable edit.
+
+use crate::history::Edit;
+use crate::project::FrameRate;
+use crate::timeline::{Clip, ClipId, FilterKind, Frame, Layer, Source, Timeline};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Split,
+    Delete,
+    RippleDelete,
+    // Ripple delete shifting every layer
+    RippleDeleteAll,
+    CloseGap,
+    InsertTime,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Split => "再生位置で分割",
+            Command::Delete => "削除",
+            Command::RippleDelete => "リップル削除",
+            Command::RippleDeleteAll => "リップル削除 (全レイヤー)",
+            Command::CloseGap => "隙間を詰める",
+            Command::InsertTime => "時間を挿入 (1秒)",
+        }
+    }
+
+    // `pointer` is the (layer, frame) that was right-clicked, if any.
+    // Without it, gaps are closed on the selected object's layer at the playhead.
+    pub fn edit(
+        &self,
+        timeline: &mut Timeline,
+        selection: &[ClipId],
+        playhead: Frame,
+        pointer: Option<(usize, Frame)>,
+        rate: FrameRate,
+    ) -> Result<Edit, String> {
+        match self {
+            Command::Split => split(timeline, selection, playhead),
+            Command::Delete => delete(timeline, selection),
+            Command::RippleDelete => ripple_delete(timeline, selection, false),
+            Command::RippleDeleteAll => ripple_delete(timeline, selection, true),
+            Command::CloseGap => {
+                let (layer, frame) = match pointer {
+                    Some(pointer) => pointer,
+                    None => {
+                        let layer = selection
+                            .first()
+                            .and_then(|id| timeline.clip(*id))
+                            .map(|c| c.layer)
+                            .ok_or("レイヤーが選択されていません")?;
+                        (layer, playhead)
+                    }
+                };
+                close_gap(timeline, layer, frame)
+            }
+            Command::InsertTime => {
+                let frame = pointer.map_or(playhead, |(_, frame)| frame);
+                insert_time(timeline, frame, rate.seconds_to_frame(1.0).max(1))
+            }
+        }
+    }
+}
+
+// Objects to split: the selected ones under `frame`, or everything under it
+pub fn split(timeline: &mut Timeline, selection: &[ClipId], frame: Frame) -> Result<Edit, String> {
+    let inside = |id: &ClipId| {
+        timeline
+            .clip(*id)
+            .is_some_and(|c| c.start < frame && frame < c.end() && !timeline.layers[c.layer].locked)
+    };
+    let mut targets: Vec<ClipId> = selection.iter().copied().filter(inside).collect();
+    if targets.is_empty() {
+        targets = timeline.clips.iter().map(|c| c.id).filter(inside).collect();
+    }
+    if targets.is_empty() {
+        return Err("再生位置にオブジェクトがありません".to_string());
+    }
+
+    let edits = targets
+        .into_iter()
+        .map(|id| Edit::SplitClip {
+            id,
+            at: frame,
+            new_id: timeline.allocate_id(),
+        })
+        .collect();
+    Ok(batch("分割", edits))
+}
+
+pub fn delete(timeline: &Timeline, ids: &[ClipId]) -> Result<Edit, String> {
+    let edits: Vec<Edit> = editable(timeline, ids)
+        .into_iter()
+        .map(|clip| Edit::DeleteClip { clip: clip.clone() })
+        .collect();
+    if edits.is_empty() {
+        return Err("オブジェクトが選択されていません".to_string());
+    }
+    Ok(batch("削除", edits))
+}
+
+// Delete and pull later objects left over the hole, on the same layer or on all layers
+pub fn ripple_delete(
+    timeline: &Timeline,
+    ids: &[ClipId],
+    all_layers: bool,
+) -> Result<Edit, String> {
+    let deleted = editable(timeline, ids);
+    if deleted.is_empty() {
+        return Err("オブジェクトが選択されていません".to_string());
+    }
+
+    let mut edits: Vec<Edit> = deleted
+        .iter()
+        .map(|clip| Edit::DeleteClip {
+            clip: (*clip).clone(),
+        })
+        .collect();
+    let moving = timeline
+        .clips
+        .iter()
+        .filter(|c| !ids.contains(&c.id) && !timeline.layers[c.layer].locked);
+    for clip in moving {
+        // Removed time before this object, counting overlapping holes once
+        let holes: Vec<(Frame, Frame)> = deleted
+            .iter()
+            .filter(|d| all_layers || d.layer == clip.layer)
+            .filter(|d| d.end() <= clip.start)
+            .map(|d| (d.start, d.end()))
+            .collect();
+        let shift = union_length(holes);
+        if shift > 0 {
+            edits.push(Edit::MoveClip {
+                id: clip.id,
+                from: (clip.layer, clip.start),
+                to: (clip.layer, clip.start - shift),
+            });
+        }
+    }
+    checked(timeline, batch("リップル削除", edits))
+}
+
+// Remove the empty space around `frame` on `layer`
+pub fn close_gap(timeline: &Timeline, layer: usize, frame: Frame) -> Result<Edit, String> {
+    if timeline.layers.get(layer).is_none_or(|l| l.locked) {
+        return Err("レイヤーがロックされています".to_string());
+    }
+    let clips = timeline.clips_on_layer(layer);
+    if clips.iter().any(|c| c.contains(frame)) {
+        return Err("隙間の上ではありません".to_string());
+    }
+    let gap_start = clips
+        .iter()
+        .filter(|c| c.end() <= frame)
+        .map(|c| c.end())
+        .max()
+        .unwrap_or(0);
+    let later: Vec<_> = clips.iter().filter(|c| c.start > frame).collect();
+    let Some(gap_end) = later.iter().map(|c| c.start).min() else {
+        return Err("後ろにオブジェクトがありません".to_string());
+    };
+
+    let shift = gap_end - gap_start;
+    let edits = later
+        .iter()
+        .map(|c| Edit::MoveClip {
+            id: c.id,
+            from: (c.layer, c.start),
+            to: (c.layer, c.start - shift),
+        })
+        .collect();
+    Ok(batch("隙間を詰める", edits))
+}
+
+// Push everything starting at or after `frame` right by `length` on all layers
+pub fn insert_time(timeline: &Timeline, frame: Frame, length: Frame) -> Result<Edit, String> {
+    let edits: Vec<Edit> = timeline
+        .clips
+        .iter()
+        .filter(|c| c.start >= frame && !timeline.layers[c.layer].locked)
+        .map(|c| Edit::MoveClip {
+            id: c.id,
+            from: (c.layer, c.start),
+            to: (c.layer, c.start + length),
+        })
+        .collect();
+    if edits.is_empty() {
+        return Err("後ろにオブジェクトがありません".to_string());
+    }
+    checked(timeline, batch("時間の挿入", edits))
+}
+
+// Objects that exist and are not on a locked layer
+fn editable<'a>(timeline: &'a Timeline, ids: &[ClipId]) -> Vec<&'a Clip> {
+    ids.iter()
+        .filter_map(|id| timeline.clip(*id))
+        .filter(|c| !timeline.layers[c.layer].locked)
+        .collect()
+}
+
+fn batch(name: &str, edits: Vec<Edit>) -> Edit {
+    Edit::Batch {
+        name: name.to_string(),
+        edits,
+    }
+}
+
+// Refuse edits that would make objects overlap (e.g. an object spanning a
+// rippled hole on another layer)
+fn checked(timeline: &Timeline, edit: Edit) -> Result<Edit, String> {
+    let mut result = timeline.clone();
+    edit.apply(&mut result);
+    if result.has_overlaps() {
+        return Err("オブジェクトが重なるため実行できません".to_string());
+    }
+    Ok(edit)
+}
+
+fn union_length(mut ranges: Vec<(Frame, Frame)>) -> Frame {
+    ranges.sort_unstable();
+    let mut total = 0;
+    let mut current: Option<(Frame, Frame)> = None;
+    for (start, end) in ranges {
+        match current {
+            Some((s, e)) if start <= e => current = Some((s, e.max(end))),
+            _ => {
+                if let Some((s, e)) = current {
+                    total += e - s;
+                }
+                current = Some((start, end));
+            }
+        }
+    }
+    if let Some((s, e)) = current {
+        total += e - s;
+    }
+    total
+}
+
+// Copies of the objects for the clipboard, in timeline order
+pub fn copy(timeline: &Timeline, ids: &[ClipId]) -> Vec<Clip> {
+    let mut clips: Vec<Clip> = ids
+        .iter()
+        .filter_map(|id| timeline.clip(*id))
+        .cloned()
+        .collect();
+    clips.sort_by_key(|c| (c.start, c.layer));
+    clips
+}
+
+// Paste keeping the objects' relative positions, the earliest one at `frame` and
+// the topmost one on `layer`
+pub fn paste(
+    timeline: &mut Timeline,
+    clips: &[Clip],
+    layer: usize,
+    frame: Frame,
+) -> Result<Edit, String> {
+    let (Some(first_start), Some(first_layer)) = (
+        clips.iter().map(|c| c.start).min(),
+        clips.iter().map(|c| c.layer).min(),
+    ) else {
+        return Err("クリップボードが空です".to_string());
+    };
+
+    let mut edits = Vec::new();
+    let mut result = timeline.clone();
+    for clip in clips {
+        let layer = layer + (clip.layer - first_layer);
+        let start = frame + (clip.start - first_start);
+        if timeline.layers.get(layer).is_none_or(|l| l.locked)
+            || !result.is_free(layer, start, clip.length, &[])
+        {
+            return Err("貼り付け先に空きがありません".to_string());
+        }
+        let mut pasted = clip.clone();
+        pasted.id = timeline.allocate_id();
+        (pasted.layer, pasted.start) = (layer, start);
+        result.insert_clip(pasted.clone());
+        edits.push(Edit::AddClip { clip: pasted });
+    }
+    Ok(batch("貼り付け", edits))
+}
+
+// New text / shape / filter object
+pub fn add_object(
+    timeline: &mut Timeline,
+    source: Source,
+    layer: usize,
+    frame: Frame,
+    length: Frame,
+) -> Result<Edit, String> {
+    if timeline.layers.get(layer).is_none_or(|l| l.locked) {
+        return Err("レイヤーがロックされています".to_string());
+    }
+    if !timeline.is_free(layer, frame, length, &[]) {
+        return Err("そこには置けません".to_string());
+    }
+    let clip = timeline.new_clip(layer, frame, length, source);
+    Ok(Edit::AddClip { clip })
+}
+
+// Append `filter` to each object (filter objects themselves are skipped)
+pub fn add_filter(timeline: &Timeline, ids: &[ClipId], filter: FilterKind) -> Result<Edit, String> {
+    let edits: Vec<Edit> = editable(timeline, ids)
+        .into_iter()
+        .filter(|c| !matches!(c.source, Source::Filter(_)))
+        .map(|c| {
+            let mut to = c.filters.clone();
+            to.push(filter);
+            Edit::SetFilters {
+                id: c.id,
+                from: c.filters.clone(),
+                to,
+            }
+        })
+        .collect();
+    if edits.is_empty() {
+        return Err("オブジェクトが選択されていません".to_string());
+    }
+    Ok(batch("フィルタの追加", edits))
+}
+
+// Show / hide or lock / unlock a layer
+pub fn set_layer(
+    timeline: &Timeline,
+    layer: usize,
+    change: impl FnOnce(&mut Layer),
+) -> Result<Edit, String> {
+    let from = timeline
+        .layers
+        .get(layer)
+        .cloned()
+        .ok_or("レイヤーがありません")?;
+    let mut to = from.clone();
+    change(&mut to);
+    Ok(Edit::SetLayer { layer, from, to })
+}
+
+// Swap a layer with its neighbour above (-1) or below (+1)
+pub fn move_layer(timeline: &Timeline, layer: usize, delta: i64) -> Result<Edit, String> {
+    let to = layer as i64 + delta;
+    if to < 0 || to >= timeline.layers.len() as i64 {
+        return Err("これ以上移動できません".to_string());
+    }
+    Ok(Edit::MoveLayer {
+        from: layer,
+        to: to as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::ShapeKind;
+
+    fn shape() -> Source {
+        Source::Shape(ShapeKind::Rectangle)
+    }
+
+    // Objects by id, so reverting a delete (which appends) compares equal
+    fn clips(timeline: &Timeline) -> Vec<Clip> {
+        let mut clips = timeline.clips.clone();
+        clips.sort_by_key(|c| c.id);
+        clips
+    }
+
+    fn span(timeline: &Timeline, id: ClipId) -> (usize, Frame, Frame) {
+        let clip = timeline.clip(id).unwrap();
+        (clip.layer, clip.start, clip.end())
+    }
+
+    // Apply `edit`, let `check` look at the result, then revert it
+    fn round_trip(timeline: &mut Timeline, edit: Edit, check: impl FnOnce(&Timeline)) {
+        let before = clips(timeline);
+        edit.apply(timeline);
+        check(timeline);
+        edit.revert(timeline);
+        assert_eq!(clips(timeline), before);
+    }
+
+    #[test]
+    fn split_cuts_everything_under_the_frame() {
+        let mut timeline = Timeline::new(3);
+        let a = timeline.add_clip(0, 0, 30, shape());
+        let b = timeline.add_clip(1, 10, 10, shape());
+        let locked = timeline.add_clip(2, 0, 30, shape());
+        timeline.layers[2].locked = true;
+
+        let edit = split(&mut timeline, &[], 15).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(t.clips.len(), 5);
+            assert_eq!(span(t, a), (0, 0, 15));
+            assert_eq!(span(t, b), (1, 10, 15));
+            assert_eq!(span(t, locked), (2, 0, 30));
+            assert_eq!(t.clips_at(15, 0)[0].end(), 30);
+            assert_eq!(t.clips_at(15, 1)[0].end(), 20);
+        });
+
+        // Only the selection when it is under the frame
+        let edit = split(&mut timeline, &[b], 15).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(t.clips.len(), 4);
+            assert_eq!(span(t, a), (0, 0, 30));
+        });
+        // An edge is not inside an object
+        assert!(split(&mut timeline, &[], 30).is_err());
+    }
+
+    #[test]
+    fn ripple_delete_pulls_later_objects_left() {
+        let mut timeline = Timeline::new(2);
+        timeline.add_clip(0, 0, 10, shape());
+        let b = timeline.add_clip(0, 20, 10, shape());
+        let c = timeline.add_clip(0, 40, 10, shape());
+        let d = timeline.add_clip(1, 30, 10, shape());
+
+        let edit = ripple_delete(&timeline, &[b], false).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert!(t.clip(b).is_none());
+            assert_eq!(span(t, c), (0, 30, 40));
+            assert_eq!(span(t, d), (1, 30, 40));
+        });
+
+        let edit = ripple_delete(&timeline, &[b], true).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(span(t, c), (0, 30, 40));
+            assert_eq!(span(t, d), (1, 20, 30));
+        });
+
+        // Locked layers neither lose objects nor move
+        timeline.layers[1].locked = true;
+        assert!(ripple_delete(&timeline, &[d], false).is_err());
+        let edit = ripple_delete(&timeline, &[b], true).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(span(t, d), (1, 30, 40));
+        });
+    }
+
+    #[test]
+    fn ripple_delete_refuses_to_overlap_objects() {
+        let mut timeline = Timeline::new(2);
+        timeline.add_clip(0, 0, 10, shape());
+        let hole = timeline.add_clip(0, 10, 10, shape());
+        // Spans the hole, so it stays while the one after it is pulled in
+        timeline.add_clip(1, 5, 20, shape());
+        timeline.add_clip(1, 25, 10, shape());
+        assert_eq!(
+            ripple_delete(&timeline, &[hole], true),
+            Err("オブジェクトが重なるため実行できません".to_string())
+        );
+        assert!(ripple_delete(&timeline, &[hole], false).is_ok());
+    }
+
+    #[test]
+    fn close_gap_moves_the_rest_of_the_layer() {
+        let mut timeline = Timeline::new(2);
+        timeline.add_clip(0, 0, 10, shape());
+        let b = timeline.add_clip(0, 30, 10, shape());
+        let c = timeline.add_clip(0, 50, 10, shape());
+        let other = timeline.add_clip(1, 40, 10, shape());
+
+        let edit = close_gap(&timeline, 0, 20).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(span(t, b), (0, 10, 20));
+            assert_eq!(span(t, c), (0, 30, 40));
+            assert_eq!(span(t, other), (1, 40, 50));
+        });
+
+        assert!(close_gap(&timeline, 0, 5).is_err(), "on an object");
+        assert!(close_gap(&timeline, 0, 70).is_err(), "nothing after");
+        timeline.layers[0].locked = true;
+        assert!(close_gap(&timeline, 0, 20).is_err());
+    }
+
+    #[test]
+    fn insert_time_pushes_every_unlocked_layer() {
+        let mut timeline = Timeline::new(3);
+        let before = timeline.add_clip(0, 0, 10, shape());
+        let at = timeline.add_clip(0, 10, 10, shape());
+        let after = timeline.add_clip(1, 25, 10, shape());
+        let locked = timeline.add_clip(2, 40, 10, shape());
+        timeline.layers[2].locked = true;
+
+        let edit = insert_time(&timeline, 10, 30).unwrap();
+        round_trip(&mut timeline, edit, |t| {
+            assert_eq!(span(t, before), (0, 0, 10));
+            assert_eq!(span(t, at), (0, 40, 50));
+            assert_eq!(span(t, after), (1, 55, 65));
+            assert_eq!(span(t, locked), (2, 40, 50));
+        });
+
+        assert!(insert_time(&timeline, 50, 30).is_err());
+    }
+}
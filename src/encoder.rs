@@ -0,0 +1,733 @@
+// Encoder backends for 書き出し: a common trait, the options each backend takes,
+// and the registry the export dialog lists. Rendering is done by export::ExportJob.
+
+use crate::apng::ApngEncoder;
+use crate::avi::{self, AviCodec, AviWriter};
+use crate::export::{ExportError, ImageFormat};
+use crate::ffmpeg::FfmpegEncoder;
+use crate::gif::GifEncoder;
+use crate::project::OutputSettings;
+use crate::timeline::Frame;
+use crate::wav::WavWriter;
+use crate::y4m::{Chroma, Y4mWriter};
+use gdk_pixbuf::Pixbuf;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+// What to write and in which shape, decided before the encoder is opened
+#[derive(Clone, Debug)]
+pub struct EncodeSettings {
+    pub dir: PathBuf,
+    // File name without the extension (the prefix for image sequences)
+    pub name: String,
+    // Size after scaling
+    pub width: u32,
+    pub height: u32,
+    // The project's profile: frame rate, pixel aspect and audio format
+    pub output: OutputSettings,
+    pub frames: Frame,
+}
+
+impl EncodeSettings {
+    pub fn file(&self, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", self.name, extension))
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.output.channels.channels() as u16
+    }
+}
+
+// One rendered frame: packed 8-bit RGB, or straight (not premultiplied) RGBA with `alpha`
+pub struct Picture<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub alpha: bool,
+    pub data: &'a [u8],
+}
+
+impl Picture<'_> {
+    fn to_pixbuf(&self) -> Pixbuf {
+        let channels = if self.alpha { 4 } else { 3 };
+        Pixbuf::from_bytes(
+            &glib::Bytes::from(self.data),
+            gdk_pixbuf::Colorspace::Rgb,
+            self.alpha,
+            8,
+            self.width as i32,
+            self.height as i32,
+            (self.width * channels) as i32,
+        )
+    }
+}
+
+pub trait Encoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError>
+    where
+        Self: Sized;
+
+    // Options shown in the export dialog
+    fn options() -> Vec<OptionSpec>
+    where
+        Self: Sized;
+
+    // Extension of the file written with `options`; None for numbered files
+    fn extension(options: &EncoderOptions) -> Option<&'static str>
+    where
+        Self: Sized;
+
+    // The file (or first file) written, for messages
+    fn output(&self) -> PathBuf;
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError>;
+
+    // Interleaved 16-bit samples for the frame just pushed
+    fn push_audio(&mut self, samples: &[i16]) -> Result<(), ExportError>;
+
+    // Close the output. Called after the last frame and also when the export is
+    // cancelled, so a cut-short file should still be usable. Runs at most once.
+    fn finish(&mut self) -> Result<(), ExportError>;
+}
+
+#[derive(Clone, Debug)]
+pub enum OptionKind {
+    // (key, label) pairs; the value is the key
+    Choice(&'static [(&'static str, &'static str)]),
+    Int { min: i64, max: i64 },
+    Bool,
+    Text,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OptionValue {
+    Choice(String),
+    Int(i64),
+    Bool(bool),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct OptionSpec {
+    pub key: &'static str,
+    pub label: &'static str,
+    pub kind: OptionKind,
+    pub default: OptionValue,
+}
+
+pub fn choice_option(
+    key: &'static str,
+    label: &'static str,
+    choices: &'static [(&'static str, &'static str)],
+) -> OptionSpec {
+    OptionSpec {
+        key,
+        label,
+        kind: OptionKind::Choice(choices),
+        default: OptionValue::Choice(choices[0].0.to_string()),
+    }
+}
+
+pub fn int_option(
+    key: &'static str,
+    label: &'static str,
+    (min, max): (i64, i64),
+    default: i64,
+) -> OptionSpec {
+    OptionSpec {
+        key,
+        label,
+        kind: OptionKind::Int { min, max },
+        default: OptionValue::Int(default),
+    }
+}
+
+pub fn bool_option(key: &'static str, label: &'static str, default: bool) -> OptionSpec {
+    OptionSpec {
+        key,
+        label,
+        kind: OptionKind::Bool,
+        default: OptionValue::Bool(default),
+    }
+}
+
+pub fn text_option(key: &'static str, label: &'static str) -> OptionSpec {
+    OptionSpec {
+        key,
+        label,
+        kind: OptionKind::Text,
+        default: OptionValue::Text(String::new()),
+    }
+}
+
+// Values by option key. Getters fall back to the given default when a value is
+// missing or of another kind.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EncoderOptions(pub BTreeMap<String, OptionValue>);
+
+impl EncoderOptions {
+    pub fn set(&mut self, key: &str, value: OptionValue) {
+        self.0.insert(key.to_string(), value);
+    }
+
+    pub fn choice<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        match self.0.get(key) {
+            Some(OptionValue::Choice(v)) => v,
+            _ => default,
+        }
+    }
+
+    pub fn int(&self, key: &str, default: i64) -> i64 {
+        match self.0.get(key) {
+            Some(OptionValue::Int(v)) => *v,
+            _ => default,
+        }
+    }
+
+    pub fn flag(&self, key: &str, default: bool) -> bool {
+        match self.0.get(key) {
+            Some(OptionValue::Bool(v)) => *v,
+            _ => default,
+        }
+    }
+
+    pub fn text<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        match self.0.get(key) {
+            Some(OptionValue::Text(v)) => v,
+            _ => default,
+        }
+    }
+}
+
+type OpenFn = fn(&EncodeSettings, &EncoderOptions) -> Result<Box<dyn Encoder>, ExportError>;
+
+fn open_boxed<E: Encoder + 'static>(
+    settings: &EncodeSettings,
+    options: &EncoderOptions,
+) -> Result<Box<dyn Encoder>, ExportError> {
+    Ok(Box::new(E::open(settings, options)?))
+}
+
+pub struct Backend {
+    // For `luvita render --format`
+    pub id: &'static str,
+    pub name: &'static str,
+    // Can keep transparency
+    pub alpha: bool,
+    pub options: fn() -> Vec<OptionSpec>,
+    pub extension: fn(&EncoderOptions) -> Option<&'static str>,
+    pub open: OpenFn,
+}
+
+fn backend<E: Encoder + 'static>(id: &'static str, name: &'static str, alpha: bool) -> Backend {
+    Backend {
+        id,
+        name,
+        alpha,
+        options: E::options,
+        extension: E::extension,
+        open: open_boxed::<E>,
+    }
+}
+
+// Backends usable on this machine, in the order the dialog lists them.
+// ffmpeg only appears when the binary is found.
+pub fn backends() -> Vec<Backend> {
+    let mut list = vec![
+        backend::<SequenceEncoder>("sequence", "連番画像", true),
+        backend::<GifEncoder>("gif", "アニメーション GIF", true),
+        backend::<ApngEncoder>("apng", "APNG", true),
+        backend::<Y4mEncoder>("y4m", "Y4M + WAV", false),
+        backend::<AviEncoder>("avi", "AVI", false),
+    ];
+    if crate::ffmpeg::find().is_some() {
+        list.push(backend::<FfmpegEncoder>("ffmpeg", "ffmpeg", false));
+    }
+    list
+}
+
+// Numbered PNG / TIFF files, "<name>_000000.png"
+pub struct SequenceEncoder {
+    settings: EncodeSettings,
+    format: ImageFormat,
+    written: Frame,
+}
+
+impl SequenceEncoder {
+    fn path(&self, index: Frame) -> PathBuf {
+        let digits = self.settings.frames.to_string().len().max(6);
+        self.settings.dir.join(format!(
+            "{}_{:0digits$}.{}",
+            self.settings.name,
+            index,
+            self.format.extension(),
+        ))
+    }
+}
+
+const IMAGE_FORMATS: &[(&str, &str)] = &[("png", "PNG"), ("tiff", "TIFF")];
+
+impl Encoder for SequenceEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        fs::create_dir_all(&settings.dir)?;
+        let format = match options.choice("format", "png") {
+            "tiff" => ImageFormat::Tiff,
+            _ => ImageFormat::Png,
+        };
+        Ok(Self {
+            settings: settings.clone(),
+            format,
+            written: 0,
+        })
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        vec![choice_option("format", "画像形式", IMAGE_FORMATS)]
+    }
+
+    fn extension(_options: &EncoderOptions) -> Option<&'static str> {
+        None
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path(0)
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        picture
+            .to_pixbuf()
+            .savev(self.path(self.written), self.format.pixbuf_type(), &[])?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn push_audio(&mut self, _samples: &[i16]) -> Result<(), ExportError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        Ok(())
+    }
+}
+
+type FileWriter = BufWriter<File>;
+
+fn create(path: PathBuf) -> std::io::Result<FileWriter> {
+    File::create(path).map(BufWriter::new)
+}
+
+// YUV4MPEG2 video with the audio in a WAV file next to it
+pub struct Y4mEncoder {
+    path: PathBuf,
+    writers: Option<(Y4mWriter<FileWriter>, WavWriter<FileWriter>)>,
+}
+
+const CHROMAS: &[(&str, &str)] = &[("420", "4:2:0"), ("444", "4:4:4")];
+
+impl Encoder for Y4mEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        fs::create_dir_all(&settings.dir)?;
+        let chroma = match options.choice("chroma", "420") {
+            "444" => Chroma::C444,
+            _ => Chroma::C420,
+        };
+        let output = &settings.output;
+        let path = settings.file("y4m");
+        let video = Y4mWriter::new(
+            create(path.clone())?,
+            settings.width as usize,
+            settings.height as usize,
+            output.frame_rate,
+            output.pixel_aspect,
+            chroma,
+        )?;
+        let audio = WavWriter::new(
+            create(settings.file("wav"))?,
+            output.sample_rate,
+            settings.channels(),
+        )?;
+        Ok(Self {
+            path,
+            writers: Some((video, audio)),
+        })
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        vec![choice_option("chroma", "色差", CHROMAS)]
+    }
+
+    fn extension(_options: &EncoderOptions) -> Option<&'static str> {
+        Some("y4m")
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        if let Some((video, _)) = &mut self.writers {
+            video.write_frame(picture.data)?;
+        }
+        Ok(())
+    }
+
+    fn push_audio(&mut self, samples: &[i16]) -> Result<(), ExportError> {
+        if let Some((_, audio)) = &mut self.writers {
+            audio.write_samples(samples)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        if let Some((video, audio)) = self.writers.take() {
+            video.finish()?;
+            audio.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// Uncompressed or MJPEG video with PCM audio in one AVI file
+pub struct AviEncoder {
+    path: PathBuf,
+    codec: AviCodec,
+    quality: String,
+    writer: Option<AviWriter<FileWriter>>,
+}
+
+const AVI_CODECS: &[(&str, &str)] = &[("mjpeg", "MJPEG"), ("raw", "非圧縮")];
+
+impl Encoder for AviEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        fs::create_dir_all(&settings.dir)?;
+        let codec = match options.choice("codec", "mjpeg") {
+            "raw" => AviCodec::Uncompressed,
+            _ => AviCodec::Mjpeg,
+        };
+        let output = &settings.output;
+        let path = settings.file("avi");
+        let writer = AviWriter::new(
+            create(path.clone())?,
+            codec,
+            settings.width,
+            settings.height,
+            output.frame_rate,
+            output.sample_rate,
+            settings.channels(),
+        )?;
+        Ok(Self {
+            path,
+            codec,
+            quality: options.int("quality", 90).clamp(1, 100).to_string(),
+            writer: Some(writer),
+        })
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        vec![
+            choice_option("codec", "コーデック", AVI_CODECS),
+            int_option("quality", "MJPEG 品質", (1, 100), 90),
+        ]
+    }
+
+    fn extension(_options: &EncoderOptions) -> Option<&'static str> {
+        Some("avi")
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        match self.codec {
+            AviCodec::Mjpeg => writer.write_video(
+                &picture
+                    .to_pixbuf()
+                    .save_to_bufferv("jpeg", &[("quality", self.quality.as_str())])?,
+            )?,
+            AviCodec::Uncompressed => writer.write_video(&avi::rgb_to_dib(
+                picture.data,
+                picture.width,
+                picture.height,
+            ))?,
+        }
+        Ok(())
+    }
+
+    fn push_audio(&mut self, samples: &[i16]) -> Result<(), ExportError> {
+        if let Some(writer) = &mut self.writer {
+            writer.write_audio(samples)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::export::{ExportJob, ExportRange};
+    use crate::project::{FrameRate, Project};
+    use crate::timeline::{ShapeKind, Source};
+    use crate::wav;
+    use std::path::Path;
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 8;
+    const FRAMES: Frame = 3;
+
+    // A fresh directory per test under the system temp dir
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("luvita-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writing "out.<ext>" at the profile's defaults apart from the size
+    pub(crate) fn settings(dir: &Path, width: u32, height: u32, frames: Frame) -> EncodeSettings {
+        EncodeSettings {
+            dir: dir.to_path_buf(),
+            name: "out".to_string(),
+            width,
+            height,
+            output: OutputSettings {
+                width,
+                height,
+                ..OutputSettings::default()
+            },
+            frames,
+        }
+    }
+
+    // Pictures for the animated backends (GIF, APNG), fed to the encoder
+    // directly: a small flat colour with a dot that moves
+    pub(crate) mod animated {
+        use super::{settings, temp_dir};
+        use crate::encoder::{Encoder, EncoderOptions, Picture};
+        use crate::timeline::Frame;
+        use std::fs;
+
+        pub(crate) const WIDTH: usize = 6;
+        pub(crate) const HEIGHT: usize = 4;
+
+        pub(crate) const RED: [u8; 3] = [255, 0, 0];
+        pub(crate) const WHITE: [u8; 3] = [255, 255, 255];
+        pub(crate) const BLUE: [u8; 3] = [0, 0, 255];
+
+        // A flat colour with one pixel of `dot` at (dot_x, 1)
+        pub(crate) fn picture(color: [u8; 3], dot_x: usize, dot: [u8; 3]) -> Vec<u8> {
+            let mut data: Vec<u8> = color.repeat(WIDTH * HEIGHT);
+            let i = (WIDTH + dot_x) * 3;
+            data[i..i + 3].copy_from_slice(&dot);
+            data
+        }
+
+        // The dot moves one pixel every frame
+        pub(crate) fn moving(frames: usize) -> Vec<Vec<u8>> {
+            (0..frames).map(|x| picture(RED, x, WHITE)).collect()
+        }
+
+        // Five frames where the second is held for three
+        pub(crate) fn with_a_still_run() -> Vec<Vec<u8>> {
+            vec![
+                picture(RED, 0, WHITE),
+                picture(RED, 1, WHITE),
+                picture(RED, 1, WHITE),
+                picture(RED, 1, WHITE),
+                picture(RED, 2, BLUE),
+            ]
+        }
+
+        // The bytes `E` writes for `pictures` at 30fps
+        pub(crate) fn encode<E: Encoder>(
+            name: &str,
+            options: &EncoderOptions,
+            pictures: &[Vec<u8>],
+        ) -> Vec<u8> {
+            let dir = temp_dir(name);
+            let settings = settings(&dir, WIDTH as u32, HEIGHT as u32, pictures.len() as Frame);
+            let mut encoder = E::open(&settings, options).unwrap();
+            for data in pictures {
+                encoder
+                    .push_video(&Picture {
+                        width: WIDTH,
+                        height: HEIGHT,
+                        alpha: false,
+                        data,
+                    })
+                    .unwrap();
+            }
+            encoder.finish().unwrap();
+            let data = fs::read(encoder.output()).unwrap();
+            let _ = fs::remove_dir_all(&dir);
+            data
+        }
+    }
+
+    // A red background with a rectangle over the second frame
+    fn tiny_project() -> Project {
+        let mut project = Project::new("tiny");
+        project.output.width = WIDTH;
+        project.output.height = HEIGHT;
+        project.output.frame_rate = FrameRate::new(30, 1);
+        project.output.background = [1.0, 0.0, 0.0, 1.0];
+        project
+            .timeline
+            .add_clip(0, 1, 1, Source::Shape(ShapeKind::Rectangle));
+        project
+    }
+
+    fn export(id: &str, options: EncoderOptions, dir: &Path) -> PathBuf {
+        let backend = backends().into_iter().find(|b| b.id == id).unwrap();
+        let range = ExportRange {
+            start: 0,
+            end: FRAMES,
+            scale: 1.0,
+            alpha: false,
+        };
+        let project = tiny_project();
+        let mut job = ExportJob::new(
+            &project,
+            range,
+            &backend,
+            &options,
+            dir.to_path_buf(),
+            "tiny".to_string(),
+        )
+        .unwrap();
+        while job.step().unwrap() {}
+        job.output()
+    }
+
+    fn le32(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    // (id, body) of the RIFF chunks in `data`, skipping the pad bytes
+    fn chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut found = Vec::new();
+        while data.len() >= 8 {
+            let id = data[..4].try_into().unwrap();
+            let size = le32(data, 4) as usize;
+            found.push((id, &data[8..8 + size]));
+            data = &data[(8 + size + size % 2).min(data.len())..];
+        }
+        found
+    }
+
+    fn find<'a>(chunks: &[([u8; 4], &'a [u8])], id: &[u8; 4]) -> &'a [u8] {
+        chunks.iter().find(|c| &c.0 == id).unwrap().1
+    }
+
+    // Bodies of the LIST chunks of one kind, without the kind
+    fn lists<'a>(chunks: &[([u8; 4], &'a [u8])], kind: &[u8; 4]) -> Vec<&'a [u8]> {
+        chunks
+            .iter()
+            .filter(|c| &c.0 == b"LIST" && &c.1[..4] == kind)
+            .map(|c| &c.1[4..])
+            .collect()
+    }
+
+    fn riff<'a>(data: &'a [u8], form: &[u8; 4]) -> &'a [u8] {
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(le32(data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], form);
+        &data[12..]
+    }
+
+    #[test]
+    fn y4m_has_a_header_and_one_frame_per_exported_frame() {
+        let dir = temp_dir("y4m");
+        let path = export("y4m", EncoderOptions::default(), &dir);
+        let data = fs::read(&path).unwrap();
+
+        let header_end = data.iter().position(|b| *b == b'\n').unwrap();
+        let header = std::str::from_utf8(&data[..header_end]).unwrap();
+        assert_eq!(header, "YUV4MPEG2 W16 H8 F30:1 Ip A1:1 C420jpeg");
+
+        // 4:2:0: a full luma plane and two quarter chroma planes per frame
+        let frame_size = (WIDTH * HEIGHT + 2 * (WIDTH / 2) * (HEIGHT / 2)) as usize;
+        let frames = &data[header_end + 1..];
+        assert_eq!(frames.len(), FRAMES as usize * (6 + frame_size));
+        for frame in frames.chunks(6 + frame_size) {
+            assert_eq!(&frame[..6], b"FRAME\n");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn y4m_audio_goes_to_a_wav_next_to_it() {
+        let dir = temp_dir("y4m-wav");
+        let path = export("y4m", EncoderOptions::default(), &dir);
+        let data = fs::read(path.with_extension("wav")).unwrap();
+        let output = tiny_project().output;
+        let channels = output.channels.channels() as usize;
+
+        let chunks = chunks(riff(&data, b"WAVE"));
+        let fmt = find(&chunks, b"fmt ");
+        assert_eq!(fmt, wav::format_chunk(output.sample_rate, channels as u16));
+        let samples = wav::samples_until(FRAMES, output.frame_rate, output.sample_rate);
+        assert_eq!(
+            find(&chunks, b"data").len(),
+            samples as usize * channels * 2
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn avi_headers_and_index_count_what_was_written() {
+        let dir = temp_dir("avi");
+        let mut options = EncoderOptions::default();
+        options.set("codec", OptionValue::Choice("raw".to_string()));
+        let data = fs::read(export("avi", options, &dir)).unwrap();
+        let output = tiny_project().output;
+        let channels = output.channels.channels() as usize;
+        let samples = wav::samples_until(FRAMES, output.frame_rate, output.sample_rate);
+
+        let top = chunks(riff(&data, b"AVI "));
+        let hdrl = chunks(lists(&top, b"hdrl")[0]);
+        let avih = find(&hdrl, b"avih");
+        assert_eq!(le32(avih, 16), FRAMES as u32);
+        assert_eq!((le32(avih, 32), le32(avih, 36)), (WIDTH, HEIGHT));
+
+        let streams: Vec<_> = lists(&hdrl, b"strl").into_iter().map(chunks).collect();
+        assert_eq!(streams.len(), 2);
+        let video = find(&streams[0], b"strh");
+        assert_eq!(&video[..4], b"vids");
+        assert_eq!(le32(video, 32), FRAMES as u32);
+        let audio = find(&streams[1], b"strh");
+        assert_eq!(&audio[..4], b"auds");
+        assert_eq!(le32(audio, 32) as u64, samples);
+
+        // One video and one audio chunk per frame, each listed in idx1
+        let movi = chunks(lists(&top, b"movi")[0]);
+        assert_eq!(movi.len(), 2 * FRAMES as usize);
+        let stride = (WIDTH * 3).div_ceil(4) * 4;
+        let video_chunks: Vec<_> = movi.iter().filter(|c| &c.0 == b"00db").collect();
+        assert_eq!(video_chunks.len(), FRAMES as usize);
+        for (_, body) in video_chunks {
+            assert_eq!(body.len() as u32, stride * HEIGHT);
+        }
+        let audio_bytes: usize = movi
+            .iter()
+            .filter(|c| &c.0 == b"01wb")
+            .map(|c| c.1.len())
+            .sum();
+        assert_eq!(audio_bytes, samples as usize * channels * 2);
+        let idx1 = find(&top, b"idx1");
+        assert_eq!(idx1.len(), movi.len() * 16);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
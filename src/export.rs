@@ -0,0 +1,266 @@
+// Export: render a frame range and feed it to an encoder backend (no GTK)
+
+use crate::encoder::{Backend, EncodeSettings, Encoder, EncoderOptions, Picture};
+use crate::project::Project;
+use crate::render::{self, Renderer};
+use crate::timeline::{ClipId, Frame, Source};
+use crate::wav;
+use cairo::{Context, Format, ImageSurface};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Tiff,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tif",
+        }
+    }
+
+    // Saver name for gdk-pixbuf
+    pub fn pixbuf_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tiff",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeKind {
+    Project,
+    InOut,
+    Selection,
+}
+
+impl RangeKind {
+    pub const ALL: [RangeKind; 3] = [RangeKind::Project, RangeKind::InOut, RangeKind::Selection];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RangeKind::Project => "プロジェクト全体",
+            RangeKind::InOut => "イン点〜アウト点",
+            RangeKind::Selection => "選択オブジェクト",
+        }
+    }
+}
+
+// Frames to export as start..end (end exclusive); None if the range is empty
+pub fn range(project: &Project, kind: RangeKind, selection: &[ClipId]) -> Option<(Frame, Frame)> {
+    let timeline = &project.timeline;
+    let (start, end) = match kind {
+        RangeKind::Project => (0, timeline.end_frame()),
+        RangeKind::InOut => project.in_out_range(),
+        RangeKind::Selection => {
+            let clips: Vec<_> = selection
+                .iter()
+                .filter_map(|id| timeline.clip(*id))
+                .collect();
+            (
+                clips.iter().map(|c| c.start).min()?,
+                clips.iter().map(|c| c.end()).max()?,
+            )
+        }
+    };
+    (start < end).then_some((start, end))
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Cairo(cairo::Error),
+    Image(glib::Error),
+    // A failure reported by an encoder backend, e.g. ffmpeg's error output
+    Encoder(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "ファイルを書き込めません: {}", e),
+            ExportError::Cairo(e) => write!(f, "描画に失敗しました: {}", e),
+            ExportError::Image(e) => write!(f, "画像を書き込めません: {}", e),
+            ExportError::Encoder(e) => write!(f, "エンコードに失敗しました: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<cairo::Error> for ExportError {
+    fn from(e: cairo::Error) -> Self {
+        ExportError::Cairo(e)
+    }
+}
+
+impl From<glib::Error> for ExportError {
+    fn from(e: glib::Error) -> Self {
+        ExportError::Image(e)
+    }
+}
+
+// Media files used in start..end that don't exist
+pub fn missing_media(project: &Project, start: Frame, end: Frame) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = project
+        .timeline
+        .clips
+        .iter()
+        .filter(|c| c.start < end && c.end() > start)
+        .filter_map(|c| match c.source {
+            Source::Media(id) => project.media(id),
+            _ => None,
+        })
+        .filter(|m| !m.path.exists())
+        .map(|m| m.path.clone())
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+// Render a frame and resize it to the export size. With `alpha` the background
+// is left transparent.
+pub fn render_scaled(
+    renderer: &mut Renderer,
+    project: &Project,
+    frame: Frame,
+    scale: f64,
+    alpha: bool,
+) -> Result<ImageSurface, ExportError> {
+    let rendered = renderer.render_frame(project, frame)?;
+    let width = ((project.output.width as f64 * scale).round() as i32).max(1);
+    let height = ((project.output.height as f64 * scale).round() as i32).max(1);
+    let format = if alpha { Format::ARgb32 } else { Format::Rgb24 };
+    let surface = ImageSurface::create(format, width, height)?;
+    {
+        let cr = Context::new(&surface)?;
+        cr.scale(
+            width as f64 / rendered.width() as f64,
+            height as f64 / rendered.height() as f64,
+        );
+        cr.set_source_surface(&rendered, 0.0, 0.0)?;
+        cr.source().set_filter(cairo::Filter::Good);
+        cr.paint()?;
+    }
+    Ok(surface)
+}
+
+// What to export: the frames start..end of the project at `scale`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportRange {
+    pub start: Frame,
+    pub end: Frame,
+    // 1.0 = project resolution
+    pub scale: f64,
+    // Keep transparency instead of painting the background colour
+    pub alpha: bool,
+}
+
+// A snapshot of the project rendered one frame per `step` into an encoder, so
+// the caller can show progress and stop between frames
+pub struct ExportJob {
+    project: Project,
+    renderer: Renderer,
+    range: ExportRange,
+    encoder: Box<dyn Encoder>,
+    done: Frame,
+}
+
+impl ExportJob {
+    // `dir` / `name` are where the backend writes its file(s)
+    pub fn new(
+        project: &Project,
+        range: ExportRange,
+        backend: &Backend,
+        options: &EncoderOptions,
+        dir: PathBuf,
+        name: String,
+    ) -> Result<Self, ExportError> {
+        let mut project = project.clone();
+        let alpha = range.alpha && backend.alpha;
+        if alpha {
+            project.output.background[3] = 0.0;
+        }
+        let output = &project.output;
+        let settings = EncodeSettings {
+            dir,
+            name,
+            width: ((output.width as f64 * range.scale).round() as u32).max(1),
+            height: ((output.height as f64 * range.scale).round() as u32).max(1),
+            output: output.clone(),
+            frames: (range.end - range.start).max(0),
+        };
+        let encoder = (backend.open)(&settings, options)?;
+        Ok(Self {
+            project,
+            renderer: Renderer::new(),
+            range: ExportRange { alpha, ..range },
+            encoder,
+            done: 0,
+        })
+    }
+
+    pub fn total(&self) -> Frame {
+        (self.range.end - self.range.start).max(0)
+    }
+
+    pub fn done(&self) -> Frame {
+        self.done
+    }
+
+    pub fn output(&self) -> PathBuf {
+        self.encoder.output()
+    }
+
+    // Encode the next frame and its audio. Returns false once the encoder is
+    // finished.
+    pub fn step(&mut self) -> Result<bool, ExportError> {
+        if self.done >= self.total() {
+            self.encoder.finish()?;
+            return Ok(false);
+        }
+        let alpha = self.range.alpha;
+        let mut surface = render_scaled(
+            &mut self.renderer,
+            &self.project,
+            self.range.start + self.done,
+            self.range.scale,
+            alpha,
+        )?;
+        let pixbuf =
+            render::surface_to_pixbuf(&mut surface, alpha).ok_or(cairo::Error::SurfaceFinished)?;
+        let data = pixbuf.read_pixel_bytes();
+        self.encoder.push_video(&Picture {
+            width: pixbuf.width() as usize,
+            height: pixbuf.height() as usize,
+            alpha,
+            data: &data,
+        })?;
+
+        // There is no audio mixing yet, so every frame gets silence of the right length
+        let output = &self.project.output;
+        let samples = wav::samples_in_frame(self.done, output.frame_rate, output.sample_rate);
+        self.encoder.push_audio(&vec![
+            0;
+            samples as usize * output.channels.channels() as usize
+        ])?;
+        self.done += 1;
+        Ok(true)
+    }
+
+    // Stop early, closing the output with the frames written so far
+    pub fn cancel(&mut self) -> Result<(), ExportError> {
+        self.encoder.finish()
+    }
+}
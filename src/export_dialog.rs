@@ -0,0 +1,443 @@
+// ファイル / 書き出し: export options and a progress view that can be cancelled.
+// キューに追加 hands the same settings to the render queue instead.
+
+use crate::encoder::{self, EncoderOptions, OptionKind, OptionSpec, OptionValue};
+use crate::export::{self, ExportJob, ExportRange, RangeKind};
+use crate::project::Project;
+use crate::render_queue_window::QueueWindow;
+use crate::ruler::{self, TimeFormat};
+use crate::timeline::ClipId;
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box as GtkBox, Button, CheckButton, DropDown, Entry, FileDialog, Grid, Label,
+    Orientation, ProgressBar, SpinButton,
+};
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+fn labeled(grid: &Grid, row: i32, label: &str, widget: &impl IsA<gtk4::Widget>) {
+    let label = Label::new(Some(label));
+    label.set_halign(Align::Start);
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(widget, 1, row, 1, 1);
+}
+
+// One input per option of the chosen backend
+fn option_widget(spec: &OptionSpec) -> gtk4::Widget {
+    match (&spec.kind, &spec.default) {
+        (OptionKind::Choice(choices), default) => {
+            let labels: Vec<&str> = choices.iter().map(|c| c.1).collect();
+            let dropdown = DropDown::from_strings(&labels);
+            if let OptionValue::Choice(key) = default
+                && let Some(index) = choices.iter().position(|c| c.0 == key)
+            {
+                dropdown.set_selected(index as u32);
+            }
+            dropdown.upcast()
+        }
+        (OptionKind::Int { min, max }, default) => {
+            let spin = SpinButton::with_range(*min as f64, *max as f64, 1.0);
+            if let OptionValue::Int(v) = default {
+                spin.set_value(*v as f64);
+            }
+            spin.upcast()
+        }
+        (OptionKind::Bool, default) => {
+            let check = CheckButton::new();
+            check.set_active(*default == OptionValue::Bool(true));
+            check.upcast()
+        }
+        (OptionKind::Text, default) => {
+            let entry = Entry::new();
+            if let OptionValue::Text(text) = default {
+                entry.set_text(text);
+            }
+            entry.upcast()
+        }
+    }
+}
+
+fn option_value(spec: &OptionSpec, widget: &gtk4::Widget) -> Option<OptionValue> {
+    Some(match &spec.kind {
+        OptionKind::Choice(choices) => {
+            let selected = widget.downcast_ref::<DropDown>()?.selected() as usize;
+            OptionValue::Choice(choices.get(selected)?.0.to_string())
+        }
+        OptionKind::Int { .. } => {
+            OptionValue::Int(widget.downcast_ref::<SpinButton>()?.value() as i64)
+        }
+        OptionKind::Bool => OptionValue::Bool(widget.downcast_ref::<CheckButton>()?.is_active()),
+        OptionKind::Text => OptionValue::Text(widget.downcast_ref::<Entry>()?.text().to_string()),
+    })
+}
+
+type OptionWidgets = Rc<RefCell<Vec<(OptionSpec, gtk4::Widget)>>>;
+
+// `dir` is where the files go unless another folder is chosen. With
+// `queue_first` the dialog is opened from レンダーキューに追加 and offers that first.
+pub fn show(
+    parent: &impl IsA<gtk4::Window>,
+    project: &Project,
+    selection: &[ClipId],
+    dir: PathBuf,
+    queue: Rc<QueueWindow>,
+    queue_first: bool,
+) {
+    let dialog = gtk4::Window::builder()
+        .title(if queue_first {
+            "レンダーキューに追加"
+        } else {
+            "書き出し"
+        })
+        .transient_for(parent)
+        .modal(true)
+        .default_width(480)
+        .build();
+    let project = Rc::new(project.clone());
+    let selection = selection.to_vec();
+
+    let content = GtkBox::new(Orientation::Vertical, 8);
+    content.set_margin_top(12);
+    content.set_margin_bottom(12);
+    content.set_margin_start(12);
+    content.set_margin_end(12);
+
+    let backends = Rc::new(encoder::backends());
+    let settings = GtkBox::new(Orientation::Vertical, 6);
+    let grid = Grid::builder().row_spacing(6).column_spacing(12).build();
+    let backend_names: Vec<&str> = backends.iter().map(|b| b.name).collect();
+    let format = DropDown::from_strings(&backend_names);
+    labeled(&grid, 0, "形式", &format);
+
+    let range_names: Vec<&str> = RangeKind::ALL.iter().map(|r| r.name()).collect();
+    let range = DropDown::from_strings(&range_names);
+    // Start on the in / out range when one is set
+    if project.in_point.is_some() || project.out_point.is_some() {
+        range.set_selected(1);
+    }
+    labeled(&grid, 1, "範囲", &range);
+    let range_info = Label::new(None);
+    range_info.set_halign(Align::Start);
+    range_info.add_css_class("dim-label");
+    grid.attach(&range_info, 1, 2, 1, 1);
+
+    let scale = SpinButton::with_range(10.0, 400.0, 5.0);
+    scale.set_value(100.0);
+    labeled(&grid, 3, "拡大率 (%)", &scale);
+    let size_info = Label::new(None);
+    size_info.set_halign(Align::Start);
+    size_info.add_css_class("dim-label");
+    grid.attach(&size_info, 1, 4, 1, 1);
+
+    let alpha = CheckButton::with_label("アルファチャンネルを保持 (背景を透明にする)");
+    grid.attach(&alpha, 1, 5, 1, 1);
+
+    let prefix = Entry::new();
+    prefix.set_text(&project.name);
+    labeled(&grid, 6, "ファイル名", &prefix);
+
+    let dir = Rc::new(RefCell::new(dir));
+    let folder_row = GtkBox::new(Orientation::Horizontal, 6);
+    let folder = Label::new(Some(&dir.borrow().display().to_string()));
+    folder.set_hexpand(true);
+    folder.set_halign(Align::Start);
+    folder.set_ellipsize(gtk4::pango::EllipsizeMode::Start);
+    let choose = Button::with_label("選択…");
+    folder_row.append(&folder);
+    folder_row.append(&choose);
+    labeled(&grid, 7, "出力先", &folder_row);
+    settings.append(&grid);
+
+    // Options of the chosen backend, rebuilt when the format changes
+    let option_grid = Grid::builder().row_spacing(6).column_spacing(12).build();
+    settings.append(&option_grid);
+    content.append(&settings);
+    let option_widgets: OptionWidgets = Rc::new(RefCell::new(Vec::new()));
+    let show_options: Rc<dyn Fn()> = {
+        let (backends, format, alpha) = (backends.clone(), format.clone(), alpha.clone());
+        let (option_grid, option_widgets) = (option_grid.clone(), option_widgets.clone());
+        Rc::new(move || {
+            while let Some(child) = option_grid.first_child() {
+                option_grid.remove(&child);
+            }
+            let mut widgets = option_widgets.borrow_mut();
+            widgets.clear();
+            let Some(backend) = backends.get(format.selected() as usize) else {
+                return;
+            };
+            // Only backends that write images can keep transparency
+            alpha.set_sensitive(backend.alpha);
+            for (row, spec) in (backend.options)().into_iter().enumerate() {
+                let widget = option_widget(&spec);
+                labeled(&option_grid, row as i32, spec.label, &widget);
+                widgets.push((spec, widget));
+            }
+        })
+    };
+    {
+        let show_options = show_options.clone();
+        format.connect_selected_notify(move |_| show_options());
+    }
+    show_options();
+
+    let progress = ProgressBar::new();
+    progress.set_show_text(true);
+    progress.set_visible(false);
+    content.append(&progress);
+    let status = Label::new(None);
+    status.set_halign(Align::Start);
+    status.set_wrap(true);
+    content.append(&status);
+
+    let buttons = GtkBox::new(Orientation::Horizontal, 6);
+    buttons.set_halign(Align::End);
+    let cancel = Button::with_label("キャンセル");
+    cancel.set_sensitive(false);
+    let close = Button::with_label("閉じる");
+    let add = Button::with_label("キューに追加");
+    let start = Button::with_label("書き出し");
+    if queue_first {
+        add.add_css_class("suggested-action");
+    } else {
+        start.add_css_class("suggested-action");
+    }
+    buttons.append(&cancel);
+    buttons.append(&close);
+    buttons.append(&add);
+    buttons.append(&start);
+    content.append(&buttons);
+
+    // Frame range and output size under the options
+    let update_info: Rc<dyn Fn()> = {
+        let (project, range, scale) = (project.clone(), range.clone(), scale.clone());
+        let (range_info, size_info) = (range_info.clone(), size_info.clone());
+        let selection = selection.clone();
+        Rc::new(move || {
+            let kind = RangeKind::ALL[range.selected() as usize % RangeKind::ALL.len()];
+            let rate = project.output.frame_rate;
+            range_info.set_text(&match export::range(&project, kind, &selection) {
+                Some((start, end)) => format!(
+                    "{} 〜 {} ({} フレーム)",
+                    ruler::format_time(start, rate, TimeFormat::Timecode),
+                    ruler::format_time(end - 1, rate, TimeFormat::Timecode),
+                    end - start
+                ),
+                None => "書き出すフレームがありません".to_string(),
+            });
+            let factor = scale.value() / 100.0;
+            size_info.set_text(&format!(
+                "{}×{}",
+                ((project.output.width as f64 * factor).round() as i32).max(1),
+                ((project.output.height as f64 * factor).round() as i32).max(1)
+            ));
+        })
+    };
+    {
+        let update_info = update_info.clone();
+        range.connect_selected_notify(move |_| update_info());
+    }
+    {
+        let update_info = update_info.clone();
+        scale.connect_value_changed(move |_| update_info());
+    }
+    update_info();
+
+    {
+        let (dialog_for_choose, dir, folder) = (dialog.clone(), dir.clone(), folder.clone());
+        choose.connect_clicked(move |_| {
+            let file_dialog = FileDialog::builder()
+                .title("出力先のフォルダ")
+                .modal(true)
+                .initial_folder(&gio::File::for_path(&*dir.borrow()))
+                .build();
+            let (dir, folder) = (dir.clone(), folder.clone());
+            file_dialog.select_folder(
+                Some(&dialog_for_choose),
+                gio::Cancellable::NONE,
+                move |result| {
+                    if let Some(path) = result.ok().and_then(|file| file.path()) {
+                        folder.set_text(&path.display().to_string());
+                        *dir.borrow_mut() = path;
+                    }
+                },
+            );
+        });
+    }
+
+    // Set while an export runs; cleared by キャンセル or closing the window
+    let running = Rc::new(Cell::new(false));
+    {
+        let running = running.clone();
+        cancel.connect_clicked(move |_| running.set(false));
+    }
+    {
+        let running = running.clone();
+        dialog.connect_close_request(move |_| {
+            running.set(false);
+            glib::Propagation::Proceed
+        });
+    }
+    {
+        let dialog = dialog.clone();
+        close.connect_clicked(move |_| dialog.close());
+    }
+
+    // What the controls say to export: range, backend index, options and file name
+    type Chosen = (ExportRange, usize, EncoderOptions, String);
+    let chosen: Rc<dyn Fn() -> Option<Chosen>> = {
+        let (project, selection, range, status) = (
+            project.clone(),
+            selection.clone(),
+            range.clone(),
+            status.clone(),
+        );
+        let (format, option_widgets, prefix) =
+            (format.clone(), option_widgets.clone(), prefix.clone());
+        let (scale, alpha) = (scale.clone(), alpha.clone());
+        Rc::new(move || {
+            let kind = RangeKind::ALL[range.selected() as usize % RangeKind::ALL.len()];
+            let Some((first, end)) = export::range(&project, kind, &selection) else {
+                status.set_text("書き出すフレームがありません");
+                return None;
+            };
+            let mut options = EncoderOptions::default();
+            for (spec, widget) in option_widgets.borrow().iter() {
+                if let Some(value) = option_value(spec, widget) {
+                    options.set(spec.key, value);
+                }
+            }
+            let name = prefix.text().trim().to_string();
+            let range = ExportRange {
+                start: first,
+                end,
+                scale: scale.value() / 100.0,
+                alpha: alpha.is_active(),
+            };
+            Some((
+                range,
+                format.selected() as usize,
+                options,
+                if name.is_empty() {
+                    "export".to_string()
+                } else {
+                    name
+                },
+            ))
+        })
+    };
+
+    {
+        let (project, backends, dir) = (project.clone(), backends.clone(), dir.clone());
+        let (chosen, status, dialog) = (chosen.clone(), status.clone(), dialog.clone());
+        add.connect_clicked(move |_| {
+            let Some((range, index, options, name)) = chosen() else {
+                return;
+            };
+            let Some(backend) = backends.get(index) else {
+                return;
+            };
+            let added = queue.queue().borrow_mut().add(
+                &project,
+                range,
+                backend.id,
+                &options,
+                dir.borrow().clone(),
+                name,
+            );
+            match added {
+                Ok(_) => {
+                    // Editing goes on while the queue renders
+                    queue.present();
+                    dialog.close();
+                }
+                Err(e) => status.set_text(&e),
+            }
+        });
+    }
+
+    start.connect_clicked(move |start| {
+        let Some((range, index, options, name)) = chosen() else {
+            return;
+        };
+        let Some(backend) = backends.get(index) else {
+            return;
+        };
+        // Refuse before the output file is created
+        let missing = export::missing_media(&project, range.start, range.end);
+        if !missing.is_empty() {
+            let paths: Vec<String> = missing.iter().map(|p| p.display().to_string()).collect();
+            status.set_text(&format!(
+                "素材ファイルが見つかりません:\n{}",
+                paths.join("\n")
+            ));
+            return;
+        }
+        let export = match ExportJob::new(
+            &project,
+            range,
+            backend,
+            &options,
+            dir.borrow().clone(),
+            name,
+        ) {
+            Ok(export) => export,
+            Err(e) => {
+                status.set_text(&e.to_string());
+                return;
+            }
+        };
+
+        // One frame per idle callback keeps the window responsive
+        let export = RefCell::new(export);
+        settings.set_sensitive(false);
+        start.set_sensitive(false);
+        add.set_sensitive(false);
+        cancel.set_sensitive(true);
+        progress.set_visible(true);
+        progress.set_fraction(0.0);
+        status.set_text(&format!(
+            "{} に書き出しています…",
+            export.borrow().output().display()
+        ));
+        running.set(true);
+        let (settings, start, add, cancel) =
+            (settings.clone(), start.clone(), add.clone(), cancel.clone());
+        let (progress, status, running) = (progress.clone(), status.clone(), running.clone());
+        glib::idle_add_local(move || {
+            let mut export = export.borrow_mut();
+            let finish = |message: String| {
+                status.set_text(&message);
+                settings.set_sensitive(true);
+                start.set_sensitive(true);
+                add.set_sensitive(true);
+                cancel.set_sensitive(false);
+                running.set(false);
+                glib::ControlFlow::Break
+            };
+            if !running.get() {
+                // Leave a playable file with the frames written so far
+                if let Err(e) = export.cancel() {
+                    return finish(e.to_string());
+                }
+                return finish(format!(
+                    "キャンセルしました ({} / {} フレームを書き出し済み)",
+                    export.done(),
+                    export.total()
+                ));
+            }
+            match export.step() {
+                Ok(true) => {
+                    progress.set_fraction(export.done() as f64 / export.total() as f64);
+                    progress.set_text(Some(&format!("{} / {}", export.done(), export.total())));
+                    glib::ControlFlow::Continue
+                }
+                Ok(false) => finish(format!("{} フレームを書き出しました", export.total())),
+                Err(e) => finish(e.to_string()),
+            }
+        });
+    });
+
+    dialog.set_child(Some(&content));
+    dialog.present();
+}
@@ -0,0 +1,380 @@
+// ffmpeg backend: raw RGB frames are piped into an `ffmpeg` process found on PATH.
+// Audio goes to a temporary WAV and is muxed in by a second ffmpeg run at the end.
+// LUVITA_FFMPEG overrides the binary found on PATH.
+
+use crate::encoder::{self, EncodeSettings, Encoder, EncoderOptions, OptionSpec, Picture};
+use crate::export::ExportError;
+use crate::wav::WavWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+pub fn find() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("LUVITA_FFMPEG") {
+        return Some(PathBuf::from(path));
+    }
+    glib::find_program_in_path("ffmpeg")
+}
+
+const CODECS: &[(&str, &str)] = &[
+    ("libx264", "H.264 (mp4)"),
+    ("libx265", "H.265 (mp4)"),
+    ("libvpx-vp9", "VP9 (webm)"),
+    ("prores_ks", "ProRes 422 (mov)"),
+];
+
+// Container, pixel format and audio codec that go with a video codec
+fn codec_settings(codec: &str) -> (&'static str, &'static str, &'static str) {
+    match codec {
+        "libvpx-vp9" => ("webm", "yuv420p", "libopus"),
+        "prores_ks" => ("mov", "yuv422p10le", "pcm_s16le"),
+        _ => ("mp4", "yuv420p", "aac"),
+    }
+}
+
+// Wait for `child` and turn a failure into its error output
+fn check(mut child: Child) -> Result<(), ExportError> {
+    let mut errors = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        stderr.read_to_string(&mut errors)?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ExportError::Encoder(format!(
+            "ffmpeg が終了しました ({}): {}",
+            status,
+            errors.trim()
+        )))
+    }
+}
+
+fn spawn(program: &Path, args: &[String], stdin: Stdio) -> Result<Child, ExportError> {
+    Command::new(program)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ExportError::Encoder(format!("{} を起動できません: {}", program.display(), e)))
+}
+
+pub struct FfmpegEncoder {
+    program: PathBuf,
+    path: PathBuf,
+    // Video only, until the audio is muxed in
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    audio_codec: &'static str,
+    // Index at the front of mp4 / mov files so they start playing while downloading
+    faststart: bool,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    audio: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl FfmpegEncoder {
+    // `open` with a given binary instead of the one `find` picks
+    fn open_with(
+        program: PathBuf,
+        settings: &EncodeSettings,
+        options: &EncoderOptions,
+    ) -> Result<Self, ExportError> {
+        fs::create_dir_all(&settings.dir)?;
+        let codec = options.choice("codec", CODECS[0].0);
+        let (container, pixel_format, audio_codec) = codec_settings(codec);
+        let output = &settings.output;
+        let path = settings.file(container);
+        let video_path = settings.file(&format!("video.{}", container));
+        let audio_path = settings.file("audio.wav");
+
+        let mut args: Vec<String> = [
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+            "-s",
+            &format!("{}x{}", settings.width, settings.height),
+            "-r",
+            &format!("{}/{}", output.frame_rate.num, output.frame_rate.den),
+            "-i",
+            "-",
+            "-c:v",
+            codec,
+            "-pix_fmt",
+            pixel_format,
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        // ProRes has no CRF; its profile sets the quality
+        if codec != "prores_ks" {
+            args.extend(["-crf".to_string(), options.int("crf", 20).to_string()]);
+        }
+        args.extend(
+            options
+                .text("args", "")
+                .split_whitespace()
+                .map(String::from),
+        );
+        args.push(video_path.to_string_lossy().into_owned());
+
+        let mut child = spawn(&program, &args, Stdio::piped())?;
+        let stdin = child.stdin.take();
+        let audio = WavWriter::new(
+            BufWriter::new(File::create(&audio_path)?),
+            output.sample_rate,
+            settings.channels(),
+        )?;
+        Ok(Self {
+            program,
+            path,
+            video_path,
+            audio_path,
+            audio_codec,
+            faststart: options.flag("faststart", true) && container != "webm",
+            child: Some(child),
+            stdin,
+            audio: Some(audio),
+        })
+    }
+
+    // Second run: copy the video and encode the audio into the final file
+    fn mux(&self) -> Result<(), ExportError> {
+        let mut args: Vec<String> = [
+            "-i",
+            &self.video_path.to_string_lossy(),
+            "-i",
+            &self.audio_path.to_string_lossy(),
+            "-map",
+            "0:v",
+            "-map",
+            "1:a",
+            "-c:v",
+            "copy",
+            "-c:a",
+            self.audio_codec,
+            "-shortest",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        if self.faststart {
+            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        }
+        args.push(self.path.to_string_lossy().into_owned());
+        check(spawn(&self.program, &args, Stdio::null())?)
+    }
+
+    // The video-only file and the WAV, whether or not the export worked
+    fn remove_temporary(&mut self) {
+        self.audio = None;
+        let _ = fs::remove_file(&self.video_path);
+        let _ = fs::remove_file(&self.audio_path);
+    }
+}
+
+impl Encoder for FfmpegEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        let program =
+            find().ok_or_else(|| ExportError::Encoder("ffmpeg が見つかりません".to_string()))?;
+        Self::open_with(program, settings, options)
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        vec![
+            encoder::choice_option("codec", "コーデック", CODECS),
+            encoder::int_option("crf", "CRF (小さいほど高画質)", (0, 51), 20),
+            encoder::bool_option("faststart", "ウェブ再生向けに最適化 (mp4 / mov)", true),
+            encoder::text_option("args", "追加の引数"),
+        ]
+    }
+
+    fn extension(options: &EncoderOptions) -> Option<&'static str> {
+        Some(codec_settings(options.choice("codec", CODECS[0].0)).0)
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        let Some(stdin) = &mut self.stdin else {
+            return Ok(());
+        };
+        if stdin.write_all(picture.data).is_err() {
+            // ffmpeg quit early; its error output says why
+            self.stdin = None;
+            if let Some(child) = self.child.take() {
+                check(child)?;
+            }
+            return Err(ExportError::Encoder("ffmpeg が終了しました".to_string()));
+        }
+        Ok(())
+    }
+
+    fn push_audio(&mut self, samples: &[i16]) -> Result<(), ExportError> {
+        if let Some(audio) = &mut self.audio {
+            audio.write_samples(samples)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        // Closing stdin ends the video input
+        self.stdin = None;
+        let Some(child) = self.child.take() else {
+            return Ok(());
+        };
+        // The WAV header is completed even when ffmpeg failed
+        let audio = match self.audio.take() {
+            Some(audio) => audio.finish().map(drop),
+            None => Ok(()),
+        };
+        let result = check(child)
+            .and_then(|()| audio.map_err(ExportError::from))
+            .and_then(|()| self.mux());
+        self.remove_temporary();
+        result
+    }
+}
+
+impl Drop for FfmpegEncoder {
+    // Don't leave ffmpeg waiting on a pipe nobody writes to, nor its
+    // temporary files behind
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.remove_temporary();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::encoder::OptionValue;
+    use crate::encoder::tests::{settings, temp_dir};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::OnceLock;
+
+    // Stands in for ffmpeg: logs its arguments and stdin next to the output it
+    // was asked for. It fails with a message when given `--fail`, and the mux
+    // run fails when a `fail-mux` file is next to the output.
+    const STUB: &str = r#"#!/bin/sh
+for last; do :; done
+dir=$(dirname "$last")
+printf '%s\n' "$@" --- >> "$dir/args"
+case " $* " in
+*" -i - "*) cat > "$dir/stdin" ;;
+*) [ -e "$dir/fail-mux" ] && { echo "stub: mux failed" >&2; exit 1; } ;;
+esac
+case " $* " in
+*" --fail "*) echo "stub: unknown encoder" >&2; exit 1 ;;
+esac
+: > "$last"
+"#;
+
+    fn stub() -> PathBuf {
+        static STUB_PATH: OnceLock<PathBuf> = OnceLock::new();
+        STUB_PATH
+            .get_or_init(|| {
+                let path = temp_dir("ffmpeg-stub").join("ffmpeg");
+                fs::write(&path, STUB).unwrap();
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+                path
+            })
+            .clone()
+    }
+
+    fn encode(dir: &Path, options: &EncoderOptions) -> Result<(), ExportError> {
+        let (width, height, frames) = (8, 4, 3);
+        let settings = settings(dir, width, height, frames);
+        let mut encoder = FfmpegEncoder::open_with(stub(), &settings, options)?;
+        let data = vec![128; (width * height * 3) as usize];
+        for _ in 0..frames {
+            encoder.push_video(&Picture {
+                width: width as usize,
+                height: height as usize,
+                alpha: false,
+                data: &data,
+            })?;
+            encoder.push_audio(&[0; 3200])?;
+        }
+        encoder.finish()
+    }
+
+    fn no_temporary_files(dir: &Path) {
+        assert!(!dir.join("out.video.mp4").exists());
+        assert!(!dir.join("out.audio.wav").exists());
+    }
+
+    fn encoder_error(result: Result<(), ExportError>) -> String {
+        match result {
+            Err(ExportError::Encoder(message)) => message,
+            other => panic!("expected an encoder error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn frames_are_piped_to_ffmpeg_as_raw_rgb() {
+        let dir = temp_dir("ffmpeg-pipe");
+        let mut options = EncoderOptions::default();
+        options.set("crf", OptionValue::Int(23));
+        encode(&dir, &options).unwrap();
+
+        assert_eq!(fs::read(dir.join("stdin")).unwrap().len(), 8 * 4 * 3 * 3);
+        let log = fs::read_to_string(dir.join("args")).unwrap();
+        let runs: Vec<Vec<&str>> = log
+            .split_terminator("---\n")
+            .map(|run| run.lines().collect())
+            .collect();
+        assert_eq!(runs.len(), 2, "encode, then mux the audio");
+        let value = |run: &[&str], flag: &str| {
+            let at = run.iter().position(|a| *a == flag)?;
+            run.get(at + 1).map(|v| v.to_string())
+        };
+        assert_eq!(value(&runs[0], "-s").as_deref(), Some("8x4"));
+        assert_eq!(value(&runs[0], "-r").as_deref(), Some("30/1"));
+        assert_eq!(value(&runs[0], "-c:v").as_deref(), Some("libx264"));
+        assert_eq!(value(&runs[0], "-crf").as_deref(), Some("23"));
+        assert!(dir.join("out.mp4").exists());
+        no_temporary_files(&dir);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ffmpeg_failure_comes_back_with_its_error_output() {
+        let dir = temp_dir("ffmpeg-fail");
+        let mut options = EncoderOptions::default();
+        options.set("args", OptionValue::Text("--fail".to_string()));
+        let message = encoder_error(encode(&dir, &options));
+        assert!(message.contains("stub: unknown encoder"), "{}", message);
+        no_temporary_files(&dir);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_failed_mux_leaves_no_temporary_files() {
+        let dir = temp_dir("ffmpeg-mux");
+        fs::write(dir.join("fail-mux"), "").unwrap();
+        let message = encoder_error(encode(&dir, &EncoderOptions::default()));
+        assert!(message.contains("stub: mux failed"), "{}", message);
+        no_temporary_files(&dir);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dropping_an_unfinished_export_cleans_up() {
+        let dir = temp_dir("ffmpeg-drop");
+        let settings = settings(&dir, 8, 4, 3);
+        let encoder = FfmpegEncoder::open_with(stub(), &settings, &EncoderOptions::default());
+        drop(encoder.unwrap());
+        no_temporary_files(&dir);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
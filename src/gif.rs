@@ -0,0 +1,715 @@
+// Animated GIF: median-cut palettes per frame, optional Floyd–Steinberg dithering,
+// LZW compression, and frames cut down to the area that changed
+
+use crate::animation::{self, Rect, Timing};
+use crate::encoder::{self, EncodeSettings, Encoder, EncoderOptions, OptionSpec, Picture};
+use crate::export::ExportError;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+const MAX_CODE: u16 = 4096;
+
+// 5 bits per channel
+fn bucket(c: [u8; 3]) -> usize {
+    ((c[0] as usize >> 3) << 10) | ((c[1] as usize >> 3) << 5) | (c[2] as usize >> 3)
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    count: u64,
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    fn mean(&self, channel: usize) -> u64 {
+        self.sum[channel] / self.count
+    }
+}
+
+fn box_mean(entries: &[Bucket]) -> [u8; 3] {
+    let count: u64 = entries.iter().map(|b| b.count).sum();
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let sum: u64 = entries.iter().map(|b| b.sum[channel]).sum();
+        *value = ((sum + count / 2) / count.max(1)) as u8;
+    }
+    color
+}
+
+// Widest channel of a box and its range, weighted by how many pixels it holds
+fn split_score(entries: &[Bucket]) -> (u64, usize) {
+    if entries.len() < 2 {
+        return (0, 0);
+    }
+    let count: u64 = entries.iter().map(|b| b.count).sum();
+    (0..3)
+        .map(|channel| {
+            let values = entries.iter().map(|b| b.mean(channel));
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (range * count, channel)
+        })
+        .max()
+        .unwrap_or((0, 0))
+}
+
+// Up to `max_colors` colours for `colors`. Colours are grouped by their top
+// 5 bits first; each palette entry is the mean of the real colours in its box,
+// so flat artwork keeps its exact colours.
+pub fn median_cut(colors: impl Iterator<Item = [u8; 3]>, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut buckets = vec![
+        Bucket {
+            count: 0,
+            sum: [0; 3]
+        };
+        1 << 15
+    ];
+    for c in colors {
+        let b = &mut buckets[bucket(c)];
+        b.count += 1;
+        for (sum, value) in b.sum.iter_mut().zip(c) {
+            *sum += value as u64;
+        }
+    }
+    let entries: Vec<Bucket> = buckets.into_iter().filter(|b| b.count > 0).collect();
+    if entries.is_empty() {
+        return vec![[0; 3]];
+    }
+
+    let mut boxes = vec![entries];
+    while boxes.len() < max_colors.max(1) {
+        let Some((index, (score, channel))) = boxes
+            .iter()
+            .map(|b| split_score(b))
+            .enumerate()
+            .max_by_key(|(_, s)| s.0)
+        else {
+            break;
+        };
+        if score == 0 {
+            break;
+        }
+        let mut entries = boxes.swap_remove(index);
+        entries.sort_by_key(|b| b.mean(channel));
+        // Split where half of the pixels are on each side
+        let half = entries.iter().map(|b| b.count).sum::<u64>() / 2;
+        let mut seen = 0;
+        let at = entries
+            .iter()
+            .position(|b| {
+                seen += b.count;
+                seen >= half
+            })
+            .map_or(1, |i| i + 1)
+            .clamp(1, entries.len() - 1);
+        let upper = entries.split_off(at);
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|b| box_mean(b)).collect()
+}
+
+// Nearest palette entry, remembered per colour
+struct Mapper<'a> {
+    palette: &'a [[u8; 3]],
+    cache: HashMap<[u8; 3], u8>,
+}
+
+impl Mapper<'_> {
+    fn nearest(&mut self, c: [u8; 3]) -> u8 {
+        let palette = self.palette;
+        *self.cache.entry(c).or_insert_with(|| {
+            let distance = |p: &[u8; 3]| {
+                (0..3)
+                    .map(|i| (p[i] as i32 - c[i] as i32).pow(2))
+                    .sum::<i32>()
+            };
+            (0..palette.len())
+                .min_by_key(|&i| distance(&palette[i]))
+                .unwrap_or(0) as u8
+        })
+    }
+}
+
+// Palette indices for `pixels` (width × height); pixels where `visible` is false
+// get `transparent`. With `dither`, the rounding error is spread to the neighbours.
+fn map_pixels(
+    pixels: &[[u8; 3]],
+    visible: &[bool],
+    width: usize,
+    palette: &[[u8; 3]],
+    dither: bool,
+    transparent: u8,
+) -> Vec<u8> {
+    let mut mapper = Mapper {
+        palette,
+        cache: HashMap::new(),
+    };
+    if !dither {
+        return pixels
+            .iter()
+            .zip(visible)
+            .map(|(c, &v)| if v { mapper.nearest(*c) } else { transparent })
+            .collect();
+    }
+
+    let mut out = Vec::with_capacity(pixels.len());
+    // Error carried to this row and the next, with a spare column on each side
+    let mut current = vec![[0i32; 3]; width + 2];
+    let mut next = vec![[0i32; 3]; width + 2];
+    for (y, row) in pixels.chunks(width).enumerate() {
+        for (x, c) in row.iter().enumerate() {
+            if !visible[y * width + x] {
+                out.push(transparent);
+                continue;
+            }
+            let wanted: [i32; 3] =
+                std::array::from_fn(|i| (c[i] as i32 + current[x + 1][i] / 16).clamp(0, 255));
+            let index = mapper.nearest(wanted.map(|v| v as u8));
+            out.push(index);
+            let got = palette[index as usize];
+            for i in 0..3 {
+                let error = wanted[i] - got[i] as i32;
+                current[x + 2][i] += error * 7;
+                next[x][i] += error * 3;
+                next[x + 1][i] += error * 5;
+                next[x + 2][i] += error;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0; 3]);
+    }
+    out
+}
+
+// Codes packed least significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = min_code_size + 1;
+    let mut next = end + 1;
+    out.write(clear, size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        out.write(end, size);
+        return out.finish();
+    };
+    let mut prefix = first as u16;
+    for &k in rest {
+        if let Some(&code) = table.get(&(prefix, k)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, size);
+        if next < MAX_CODE {
+            table.insert((prefix, k), next);
+            next += 1;
+            // The decoder adds its entries one code later, so it widens at the same point
+            if next > (1 << size) && size < 12 {
+                size += 1;
+            }
+        } else {
+            out.write(clear, size);
+            table.clear();
+            size = min_code_size + 1;
+            next = end + 1;
+        }
+        prefix = k as u16;
+    }
+    out.write(prefix, size);
+    // Reading that code makes the decoder's table `next` entries long, which
+    // may widen the end code
+    if next >= (1 << size) && size < 12 {
+        size += 1;
+    }
+    out.write(end, size);
+    out.finish()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disposal {
+    // Leave the frame for the next one to draw over
+    Keep = 1,
+    // Clear the frame's area to transparent before the next one
+    Background = 2,
+}
+
+pub struct GifFrame {
+    pub rect: Rect,
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>,
+    pub transparent: Option<u8>,
+    // 1/100 s
+    pub delay: u16,
+    pub disposal: Disposal,
+}
+
+pub struct GifWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> GifWriter<W> {
+    // `plays` = 0 loops forever
+    pub fn new(mut out: W, width: u16, height: u16, plays: u16) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // No global colour table; every frame has its own
+        out.write_all(&[0, 0, 0])?;
+        // NETSCAPE2.0 counts the repeats after the first play
+        if plays != 1 {
+            out.write_all(&[0x21, 0xff, 0x0b])?;
+            out.write_all(b"NETSCAPE2.0")?;
+            out.write_all(&[0x03, 0x01])?;
+            out.write_all(&plays.saturating_sub(1).to_le_bytes())?;
+            out.write_all(&[0])?;
+        }
+        Ok(Self { out })
+    }
+
+    pub fn write_frame(&mut self, frame: &GifFrame) -> io::Result<()> {
+        let out = &mut self.out;
+        // Graphic control extension
+        out.write_all(&[0x21, 0xf9, 0x04])?;
+        out.write_all(&[((frame.disposal as u8) << 2) | frame.transparent.is_some() as u8])?;
+        out.write_all(&frame.delay.to_le_bytes())?;
+        out.write_all(&[frame.transparent.unwrap_or(0), 0])?;
+
+        // Image descriptor with a local colour table of 2^bits entries, which
+        // has to reach the transparent index too
+        let entries = frame
+            .palette
+            .len()
+            .max(frame.transparent.map_or(0, |t| t as usize + 1));
+        let bits = (1..=8).find(|b| 1usize << b >= entries).unwrap_or(8);
+        out.write_all(&[0x2c])?;
+        for v in [
+            frame.rect.x,
+            frame.rect.y,
+            frame.rect.width,
+            frame.rect.height,
+        ] {
+            out.write_all(&(v as u16).to_le_bytes())?;
+        }
+        out.write_all(&[0x80 | (bits as u8 - 1)])?;
+        for i in 0..1usize << bits {
+            out.write_all(&frame.palette.get(i).copied().unwrap_or([0; 3]))?;
+        }
+
+        let min_code_size = bits.max(2);
+        out.write_all(&[min_code_size as u8])?;
+        for block in lzw(&frame.indices, min_code_size).chunks(255) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub struct GifEncoder {
+    path: PathBuf,
+    writer: Option<GifWriter<BufWriter<File>>>,
+    timing: Timing,
+    dither: bool,
+    delta: bool,
+    // The last kept picture, to find what changed
+    previous: Option<Vec<u8>>,
+    // Written once the next frame shows whether it needs to be longer
+    pending: Option<GifFrame>,
+}
+
+impl GifEncoder {
+    fn flush(&mut self) -> io::Result<()> {
+        if let (Some(writer), Some(frame)) = (&mut self.writer, self.pending.take()) {
+            writer.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encoder for GifEncoder {
+    fn open(settings: &EncodeSettings, options: &EncoderOptions) -> Result<Self, ExportError> {
+        if settings.width > u16::MAX as u32 || settings.height > u16::MAX as u32 {
+            return Err(ExportError::Encoder(
+                "GIF は 65535 ピクセルまでです".to_string(),
+            ));
+        }
+        fs::create_dir_all(&settings.dir)?;
+        let path = settings.file("gif");
+        let writer = GifWriter::new(
+            BufWriter::new(File::create(&path)?),
+            settings.width as u16,
+            settings.height as u16,
+            animation::plays(options),
+        )?;
+        Ok(Self {
+            path,
+            writer: Some(writer),
+            timing: Timing::new(settings.output.frame_rate, options),
+            dither: options.flag("dither", true),
+            delta: options.flag("delta", true),
+            previous: None,
+            pending: None,
+        })
+    }
+
+    fn options() -> Vec<OptionSpec> {
+        let mut options = animation::options();
+        options.push(encoder::bool_option("dither", "ディザリング", true));
+        options
+    }
+
+    fn extension(_options: &EncoderOptions) -> Option<&'static str> {
+        Some("gif")
+    }
+
+    fn output(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn push_video(&mut self, picture: &Picture) -> Result<(), ExportError> {
+        let Some(frame) = self.timing.next() else {
+            return Ok(());
+        };
+        // Most viewers show delays under 2/100 s as 1/10 s
+        let delay = self.timing.delay_cs(frame).max(2);
+        let (width, height) = (picture.width, picture.height);
+        let channels = if picture.alpha { 4 } else { 3 };
+        let data = picture.data;
+
+        // With transparency a frame has to clear what was under it, so every
+        // frame is drawn in full
+        let rect = match &self.previous {
+            Some(previous) if self.delta && !picture.alpha => {
+                match animation::changed_rect(previous, data, width, height, channels) {
+                    Some(rect) => rect,
+                    None => {
+                        if let Some(pending) = &mut self.pending {
+                            pending.delay =
+                                (pending.delay as i64 + delay).min(u16::MAX as i64) as u16;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            _ => Rect::full(width, height),
+        };
+
+        let cropped = animation::crop(data, width, channels, rect);
+        let pixels: Vec<[u8; 3]> = cropped
+            .chunks(channels)
+            .map(|p| [p[0], p[1], p[2]])
+            .collect();
+        // Unchanged pixels inside the area show the previous frame through
+        let visible: Vec<bool> = match (&self.previous, picture.alpha) {
+            (_, true) => cropped.chunks(4).map(|p| p[3] >= 128).collect(),
+            (Some(previous), false) if self.delta => {
+                let before = animation::crop(previous, width, channels, rect);
+                cropped
+                    .chunks(3)
+                    .zip(before.chunks(3))
+                    .map(|(a, b)| a != b)
+                    .collect()
+            }
+            _ => vec![true; pixels.len()],
+        };
+        let see_through = visible.iter().any(|v| !v);
+        let palette = median_cut(
+            pixels
+                .iter()
+                .zip(&visible)
+                .filter(|(_, v)| **v)
+                .map(|(c, _)| *c),
+            if see_through { 255 } else { 256 },
+        );
+        let transparent = see_through.then_some(palette.len() as u8);
+        let indices = map_pixels(
+            &pixels,
+            &visible,
+            rect.width,
+            &palette,
+            self.dither,
+            transparent.unwrap_or(0),
+        );
+
+        self.flush()?;
+        self.pending = Some(GifFrame {
+            rect,
+            palette,
+            indices,
+            transparent,
+            delay: delay.min(u16::MAX as i64) as u16,
+            disposal: if picture.alpha {
+                Disposal::Background
+            } else {
+                Disposal::Keep
+            },
+        });
+        self.previous = Some(data.to_vec());
+        Ok(())
+    }
+
+    fn push_audio(&mut self, _samples: &[i16]) -> Result<(), ExportError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExportError> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::OptionValue;
+    use crate::encoder::tests::animated::{self, HEIGHT, RED, WHITE, WIDTH};
+
+    // A plain GIF LZW decoder, strict about the stream ending right after the
+    // end code
+    fn unlzw(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear = 1u16 << min_code_size;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..=end).map(|i| vec![i as u8]).collect() };
+        let mut table = reset();
+        let mut size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let (mut buffer, mut bits) = (0u32, 0);
+        let mut bytes = data.iter();
+        let mut out = Vec::new();
+        loop {
+            while bits < size {
+                let byte = *bytes.next().expect("stream ended before the end code");
+                buffer |= (byte as u32) << bits;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << size) - 1)) as u16;
+            buffer >>= size;
+            bits -= size;
+            if code == clear {
+                table = reset();
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(p)) if code as usize == table.len() => {
+                    let mut entry = p.clone();
+                    entry.push(p[0]);
+                    entry
+                }
+                _ => panic!("code {} is not in the table", code),
+            };
+            if let Some(mut p) = previous.take()
+                && table.len() < MAX_CODE as usize
+            {
+                p.push(entry[0]);
+                table.push(p);
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+        assert!(
+            bytes.next().is_none() && buffer == 0,
+            "data after the end code"
+        );
+        out
+    }
+
+    #[test]
+    fn lzw_round_trips_at_every_code_size() {
+        // Lengths cross the points where the code size grows, with the end
+        // code landing on every bit position
+        let mut seed = 1u32;
+        for min_code_size in 2..=8 {
+            let colors = 1u32 << min_code_size;
+            for length in 0..700 {
+                let indices: Vec<u8> = (0..length)
+                    .map(|i| {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        let value = if i % 3 == 0 { seed >> 16 } else { i as u32 };
+                        (value % colors) as u8
+                    })
+                    .collect();
+                let encoded = lzw(&indices, min_code_size);
+                assert_eq!(unlzw(&encoded, min_code_size), indices, "{}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn lzw_survives_the_table_filling_up() {
+        let indices: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(unlzw(&lzw(&indices, 8), 8), indices);
+    }
+
+    struct DecodedFrame {
+        rect: Rect,
+        delay: u16,
+        palette: Vec<[u8; 3]>,
+        indices: Vec<u8>,
+    }
+
+    // Logical screen size and frames of a GIF as written by GifWriter
+    fn decode(data: &[u8]) -> (u16, u16, Vec<DecodedFrame>) {
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        assert_eq!(&data[..6], b"GIF89a");
+        let (width, height) = (u16_at(6), u16_at(8));
+        assert_eq!(data[10] & 0x80, 0, "no global colour table");
+        let skip_blocks = |mut at: usize| {
+            while data[at] != 0 {
+                at += 1 + data[at] as usize;
+            }
+            at + 1
+        };
+        let mut frames = Vec::new();
+        let mut delay = 0;
+        let mut at = 13;
+        loop {
+            match data[at] {
+                0x21 => {
+                    if data[at + 1] == 0xf9 {
+                        delay = u16_at(at + 4);
+                    }
+                    at = skip_blocks(at + 2);
+                }
+                0x2c => {
+                    let rect = Rect {
+                        x: u16_at(at + 1) as usize,
+                        y: u16_at(at + 3) as usize,
+                        width: u16_at(at + 5) as usize,
+                        height: u16_at(at + 7) as usize,
+                    };
+                    let colors = 2usize << (data[at + 9] & 7);
+                    at += 10;
+                    let palette = data[at..at + colors * 3]
+                        .chunks(3)
+                        .map(|c| [c[0], c[1], c[2]])
+                        .collect();
+                    at += colors * 3;
+                    let min_code_size = data[at] as u32;
+                    at += 1;
+                    let mut stream = Vec::new();
+                    while data[at] != 0 {
+                        let length = data[at] as usize;
+                        stream.extend_from_slice(&data[at + 1..at + 1 + length]);
+                        at += 1 + length;
+                    }
+                    at += 1;
+                    let indices = unlzw(&stream, min_code_size);
+                    assert_eq!(indices.len(), rect.width * rect.height);
+                    frames.push(DecodedFrame {
+                        rect,
+                        delay,
+                        palette,
+                        indices,
+                    });
+                }
+                0x3b => return (width, height, frames),
+                other => panic!("unexpected block {:#x}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn frames_decode_back_to_the_picture_size() {
+        let gif =
+            animated::encode::<GifEncoder>("gif-size", &Default::default(), &animated::moving(3));
+        let (width, height, frames) = decode(&gif);
+        assert_eq!((width as usize, height as usize), (WIDTH, HEIGHT));
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].rect, Rect::full(WIDTH, HEIGHT));
+        // The first frame holds exactly the two colours
+        let first = &frames[0];
+        let colors: Vec<[u8; 3]> = first
+            .indices
+            .iter()
+            .map(|&i| first.palette[i as usize])
+            .collect();
+        assert_eq!(
+            colors,
+            animated::picture(RED, 0, WHITE)
+                .chunks(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<_>>()
+        );
+        // Later frames only cover the pixels that moved
+        for frame in &frames[1..] {
+            assert!(frame.rect.width <= 2 && frame.rect.height == 1);
+        }
+    }
+
+    #[test]
+    fn an_unchanged_run_is_one_longer_frame() {
+        // At 30fps the frames start at 0, 3, 7, 10, 13 and 17 / 100 s
+        let gif = animated::encode::<GifEncoder>(
+            "gif-static",
+            &Default::default(),
+            &animated::with_a_still_run(),
+        );
+        let (_, _, frames) = decode(&gif);
+        let delays: Vec<u16> = frames.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, [3, 10, 4]);
+    }
+
+    #[test]
+    fn skipped_frames_are_not_written() {
+        let mut options = EncoderOptions::default();
+        options.set("skip", OptionValue::Int(2));
+        let gif = animated::encode::<GifEncoder>("gif-skip", &options, &animated::moving(6));
+        let (width, height, frames) = decode(&gif);
+        assert_eq!((width as usize, height as usize), (WIDTH, HEIGHT));
+        assert_eq!(frames.len(), 3);
+        // Each kept frame stands for two, and the whole still lasts 6 / 30 s
+        let delays: Vec<u16> = frames.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, [7, 6, 7]);
+    }
+}
@@ -0,0 +1,509 @@
+// Undo / redo history of timeline edits
+
+use crate::timeline::{Clip, ClipId, FilterKind, Frame, Layer, Marker, Properties, Timeline};
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+// One reversible change to the timeline
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    AddClip {
+        clip: Clip,
+    },
+    DeleteClip {
+        clip: Clip,
+    },
+    // (layer, start)
+    MoveClip {
+        id: ClipId,
+        from: (usize, Frame),
+        to: (usize, Frame),
+    },
+    // (start, length)
+    TrimClip {
+        id: ClipId,
+        from: (Frame, Frame),
+        to: (Frame, Frame),
+    },
+    SplitClip {
+        id: ClipId,
+        at: Frame,
+        new_id: ClipId,
+    },
+    SetProperties {
+        id: ClipId,
+        from: Properties,
+        to: Properties,
+    },
+    // Filters applied to one object
+    SetFilters {
+        id: ClipId,
+        from: Vec<FilterKind>,
+        to: Vec<FilterKind>,
+    },
+    MoveLayer {
+        from: usize,
+        to: usize,
+    },
+    // Visibility / lock of a layer
+    SetLayer {
+        layer: usize,
+        from: Layer,
+        to: Layer,
+    },
+    AddMarker {
+        marker: Marker,
+    },
+    // Several edits undone as one step
+    Batch {
+        name: String,
+        edits: Vec<Edit>,
+    },
+}
+
+impl Edit {
+    // Shown in the 編集 menu
+    pub fn name(&self) -> String {
+        match self {
+            Edit::AddClip { .. } => "オブジェクトの追加".to_string(),
+            Edit::DeleteClip { .. } => "削除".to_string(),
+            Edit::MoveClip { .. } => "移動".to_string(),
+            Edit::TrimClip { .. } => "長さの変更".to_string(),
+            Edit::SplitClip { .. } => "分割".to_string(),
+            Edit::SetProperties { .. } => "プロパティの変更".to_string(),
+            Edit::SetFilters { .. } => "フィルタの変更".to_string(),
+            Edit::MoveLayer { .. } => "レイヤーの移動".to_string(),
+            Edit::SetLayer { .. } => "レイヤーの設定".to_string(),
+            Edit::AddMarker { .. } => "マーカーの追加".to_string(),
+            Edit::Batch { name, .. } => name.clone(),
+        }
+    }
+
+    pub fn apply(&self, timeline: &mut Timeline) {
+        match self {
+            Edit::AddClip { clip } => timeline.insert_clip(clip.clone()),
+            Edit::DeleteClip { clip } => {
+                timeline.remove_clip(clip.id);
+            }
+            Edit::MoveClip { id, to, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    (clip.layer, clip.start) = *to;
+                }
+            }
+            Edit::TrimClip { id, to, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    (clip.start, clip.length) = *to;
+                }
+            }
+            Edit::SplitClip { id, at, new_id } => {
+                let Some(clip) = timeline.clip_mut(*id) else {
+                    return;
+                };
+                let mut tail = clip.clone();
+                clip.length = at - clip.start;
+                tail.id = *new_id;
+                tail.length = tail.end() - at;
+                tail.start = *at;
+                timeline.insert_clip(tail);
+            }
+            Edit::SetProperties { id, to, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    clip.props = to.clone();
+                }
+            }
+            Edit::SetFilters { id, to, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    clip.filters = to.clone();
+                }
+            }
+            Edit::MoveLayer { from, to } => move_layer(timeline, *from, *to),
+            Edit::SetLayer { layer, to, .. } => {
+                if let Some(layer) = timeline.layers.get_mut(*layer) {
+                    *layer = to.clone();
+                }
+            }
+            Edit::AddMarker { marker } => {
+                timeline.markers.push(marker.clone());
+                timeline.markers.sort_by_key(|m| m.frame);
+            }
+            Edit::Batch { edits, .. } => {
+                for edit in edits {
+                    edit.apply(timeline);
+                }
+            }
+        }
+    }
+
+    pub fn revert(&self, timeline: &mut Timeline) {
+        match self {
+            Edit::AddClip { clip } => {
+                timeline.remove_clip(clip.id);
+            }
+            Edit::DeleteClip { clip } => timeline.insert_clip(clip.clone()),
+            Edit::MoveClip { id, from, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    (clip.layer, clip.start) = *from;
+                }
+            }
+            Edit::TrimClip { id, from, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    (clip.start, clip.length) = *from;
+                }
+            }
+            Edit::SplitClip { id, new_id, .. } => {
+                let Some(tail) = timeline.remove_clip(*new_id) else {
+                    return;
+                };
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    clip.length = tail.end() - clip.start;
+                }
+            }
+            Edit::SetProperties { id, from, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    clip.props = from.clone();
+                }
+            }
+            Edit::SetFilters { id, from, .. } => {
+                if let Some(clip) = timeline.clip_mut(*id) {
+                    clip.filters = from.clone();
+                }
+            }
+            Edit::MoveLayer { from, to } => move_layer(timeline, *to, *from),
+            Edit::SetLayer { layer, from, .. } => {
+                if let Some(layer) = timeline.layers.get_mut(*layer) {
+                    *layer = from.clone();
+                }
+            }
+            Edit::AddMarker { marker } => {
+                if let Some(index) = timeline.markers.iter().position(|m| m == marker) {
+                    timeline.markers.remove(index);
+                }
+            }
+            Edit::Batch { edits, .. } => {
+                for edit in edits.iter().rev() {
+                    edit.revert(timeline);
+                }
+            }
+        }
+    }
+
+    // Fold `next` into self when both belong to one continuous drag
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Edit::MoveClip { id, to, .. },
+                Edit::MoveClip {
+                    id: id2, to: to2, ..
+                },
+            ) if id == id2 => {
+                *to = *to2;
+                true
+            }
+            (
+                Edit::TrimClip { id, to, .. },
+                Edit::TrimClip {
+                    id: id2, to: to2, ..
+                },
+            ) if id == id2 => {
+                *to = *to2;
+                true
+            }
+            (
+                Edit::SetProperties { id, to, .. },
+                Edit::SetProperties {
+                    id: id2, to: to2, ..
+                },
+            ) if id == id2 => {
+                *to = to2.clone();
+                true
+            }
+            (
+                Edit::Batch { name, edits },
+                Edit::Batch {
+                    name: name2,
+                    edits: edits2,
+                },
+            ) if name == name2 && edits.len() == edits2.len() => {
+                // Dry run first so a partial merge never happens
+                let mut merged = edits.clone();
+                if merged.iter_mut().zip(edits2).all(|(a, b)| a.merge(b)) {
+                    *edits = merged;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+// Move a layer and renumber the objects that live on the layers in between
+fn move_layer(timeline: &mut Timeline, from: usize, to: usize) {
+    if from == to || from >= timeline.layers.len() || to >= timeline.layers.len() {
+        return;
+    }
+    let layer = timeline.layers.remove(from);
+    timeline.layers.insert(to, layer);
+    for clip in &mut timeline.clips {
+        if clip.layer == from {
+            clip.layer = to;
+        } else if from < to && clip.layer > from && clip.layer <= to {
+            clip.layer -= 1;
+        } else if to < from && clip.layer >= to && clip.layer < from {
+            clip.layer += 1;
+        }
+    }
+}
+
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    limit: usize,
+    // True while a drag is running; its edits collapse into one step
+    group_open: bool,
+    group_started: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            limit: limit.max(1),
+            group_open: false,
+            group_started: false,
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+        self.trim();
+    }
+
+    // Apply `edit` and record it
+    pub fn perform(&mut self, edit: Edit, timeline: &mut Timeline) {
+        edit.apply(timeline);
+        self.record(edit);
+    }
+
+    // Record an edit that has already been applied to the timeline
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+
+        if self.group_open
+            && self.group_started
+            && let Some(last) = self.undo.last_mut()
+            && last.merge(&edit)
+        {
+            return;
+        }
+        self.group_started = self.group_open;
+        self.undo.push(edit);
+        self.trim();
+    }
+
+    // Start / finish a continuous drag
+    pub fn begin_group(&mut self) {
+        self.group_open = true;
+        self.group_started = false;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_open = false;
+        self.group_started = false;
+    }
+
+    pub fn undo(&mut self, timeline: &mut Timeline) -> bool {
+        self.end_group();
+        let Some(edit) = self.undo.pop() else {
+            return false;
+        };
+        edit.revert(timeline);
+        self.redo.push(edit);
+        true
+    }
+
+    pub fn redo(&mut self, timeline: &mut Timeline) -> bool {
+        self.end_group();
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        edit.apply(timeline);
+        self.undo.push(edit);
+        true
+    }
+
+    pub fn undo_name(&self) -> Option<String> {
+        self.undo.last().map(Edit::name)
+    }
+
+    pub fn redo_name(&self) -> Option<String> {
+        self.redo.last().map(Edit::name)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.end_group();
+    }
+
+    fn trim(&mut self) {
+        if self.undo.len() > self.limit {
+            let excess = self.undo.len() - self.limit;
+            self.undo.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::{ShapeKind, Source};
+
+    fn shape() -> Source {
+        Source::Shape(ShapeKind::Rectangle)
+    }
+
+    fn moved(id: ClipId, from: Frame, to: Frame) -> Edit {
+        Edit::MoveClip {
+            id,
+            from: (0, from),
+            to: (0, to),
+        }
+    }
+
+    #[test]
+    fn a_drag_is_one_undo_step() {
+        let mut timeline = Timeline::new(2);
+        let id = timeline.add_clip(0, 0, 10, shape());
+        let original = timeline.clone();
+        let mut history = History::default();
+
+        history.begin_group();
+        history.perform(moved(id, 0, 5), &mut timeline);
+        history.perform(moved(id, 5, 9), &mut timeline);
+        history.end_group();
+        history.begin_group();
+        for (from, to) in [((0, 10), (0, 12)), ((0, 12), (0, 20))] {
+            history.perform(Edit::TrimClip { id, from, to }, &mut timeline);
+        }
+        history.end_group();
+        assert_eq!(
+            history.undo,
+            [
+                moved(id, 0, 9),
+                Edit::TrimClip {
+                    id,
+                    from: (0, 10),
+                    to: (0, 20)
+                }
+            ]
+        );
+
+        assert!(history.undo(&mut timeline));
+        assert_eq!(timeline.clip(id).unwrap().length, 10);
+        assert!(history.undo(&mut timeline));
+        assert_eq!(timeline, original);
+        assert!(!history.undo(&mut timeline));
+    }
+
+    #[test]
+    fn edits_outside_a_drag_stay_separate() {
+        let mut timeline = Timeline::new(2);
+        let id = timeline.add_clip(0, 0, 10, shape());
+        let mut history = History::default();
+        history.perform(moved(id, 0, 5), &mut timeline);
+        history.perform(moved(id, 5, 9), &mut timeline);
+        assert_eq!(history.undo.len(), 2);
+    }
+
+    #[test]
+    fn batches_merge_whole_or_not_at_all() {
+        let mut timeline = Timeline::new(2);
+        let a = timeline.add_clip(0, 0, 10, shape());
+        let b = timeline.add_clip(0, 20, 10, shape());
+        let c = timeline.add_clip(0, 40, 10, shape());
+        let batch = |edits| Edit::Batch {
+            name: "移動".to_string(),
+            edits,
+        };
+        let mut history = History::default();
+        history.begin_group();
+        history.perform(batch(vec![moved(a, 0, 1), moved(b, 20, 21)]), &mut timeline);
+        history.perform(batch(vec![moved(a, 1, 2), moved(b, 21, 22)]), &mut timeline);
+        // `a` would merge but `c` is not `b`, so the first batch is left alone
+        history.perform(batch(vec![moved(a, 2, 3), moved(c, 40, 41)]), &mut timeline);
+        history.end_group();
+        assert_eq!(
+            history.undo,
+            [
+                batch(vec![moved(a, 0, 2), moved(b, 20, 22)]),
+                batch(vec![moved(a, 2, 3), moved(c, 40, 41)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn recording_clears_redo() {
+        let mut timeline = Timeline::new(2);
+        let id = timeline.add_clip(0, 0, 10, shape());
+        let mut history = History::default();
+        history.perform(moved(id, 0, 5), &mut timeline);
+        history.undo(&mut timeline);
+        assert_eq!(history.redo_name().as_deref(), Some("移動"));
+        history.perform(moved(id, 0, 7), &mut timeline);
+        assert_eq!(history.redo_name(), None);
+        assert!(!history.redo(&mut timeline));
+        assert_eq!(timeline.clip(id).unwrap().start, 7);
+    }
+
+    #[test]
+    fn lowering_the_limit_drops_the_oldest_steps() {
+        let mut timeline = Timeline::new(2);
+        let id = timeline.add_clip(0, 0, 10, shape());
+        let mut history = History::new(10);
+        for start in 0..5 {
+            history.perform(moved(id, start, start + 1), &mut timeline);
+        }
+        history.set_limit(2);
+        assert_eq!(history.undo, [moved(id, 3, 4), moved(id, 4, 5)]);
+        // Further edits keep to the new limit
+        history.perform(moved(id, 5, 6), &mut timeline);
+        assert_eq!(history.undo, [moved(id, 4, 5), moved(id, 5, 6)]);
+    }
+
+    #[test]
+    fn moving_a_layer_undoes_and_redoes() {
+        let mut timeline = Timeline::new(4);
+        for (i, layer) in timeline.layers.iter_mut().enumerate() {
+            layer.name = format!("L{}", i);
+        }
+        let clips: Vec<ClipId> = (0..4)
+            .map(|layer| timeline.add_clip(layer, 0, 10, shape()))
+            .collect();
+        let original = timeline.clone();
+        let mut history = History::default();
+
+        history.perform(Edit::MoveLayer { from: 0, to: 2 }, &mut timeline);
+        let names: Vec<&str> = timeline.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["L1", "L2", "L0", "L3"]);
+        let layers: Vec<usize> = clips
+            .iter()
+            .map(|&id| timeline.clip(id).unwrap().layer)
+            .collect();
+        assert_eq!(layers, [2, 0, 1, 3]);
+        let moved = timeline.clone();
+
+        assert!(history.undo(&mut timeline));
+        assert_eq!(timeline, original);
+        assert!(history.redo(&mut timeline));
+        assert_eq!(timeline, moved);
+    }
+}
@@ -0,0 +1,102 @@
+// Import media files as objects on the timeline
+
+use crate::history::{Edit, History};
+use crate::project::{MediaKind, Project};
+use crate::timeline::{ClipId, Frame, Source};
+use gdk_pixbuf::Pixbuf;
+use std::path::{Path, PathBuf};
+
+pub const IMAGE_SUFFIXES: &[&str] = &["png", "jpg", "jpeg", "webp", "svg"];
+
+pub struct ImportOptions {
+    // Length of a still image object
+    pub image_seconds: f64,
+    // Length of text / shape / filter objects added from the menu
+    pub object_seconds: f64,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            image_seconds: 3.0,
+            object_seconds: 3.0,
+        }
+    }
+}
+
+pub fn media_kind(path: &Path) -> Option<MediaKind> {
+    let suffix = path.extension()?.to_str()?.to_ascii_lowercase();
+    if IMAGE_SUFFIXES.contains(&suffix.as_str()) {
+        Some(MediaKind::Image)
+    } else {
+        None
+    }
+}
+
+// Place the files one after another starting at `start`. With `layer` the objects
+// go on that layer (or the next free one below it), otherwise on the first free layer.
+pub fn import_files(
+    project: &mut Project,
+    history: &mut History,
+    paths: &[PathBuf],
+    start: Frame,
+    layer: Option<usize>,
+    options: &ImportOptions,
+) -> Vec<ClipId> {
+    let mut edits = Vec::new();
+    let mut position = start;
+
+    for path in paths {
+        if media_kind(path) != Some(MediaKind::Image) {
+            eprintln!("{}: 対応していない形式です", path.display());
+            continue;
+        }
+        if Pixbuf::file_info(path).is_none() {
+            eprintln!("{}: 画像を読み込めません", path.display());
+            continue;
+        }
+
+        let length = project
+            .output
+            .frame_rate
+            .seconds_to_frame(options.image_seconds)
+            .max(1);
+        let timeline = &project.timeline;
+        let free_layer = match layer {
+            Some(layer) => {
+                (layer..timeline.layers.len()).find(|&l| timeline.is_free(l, position, length, &[]))
+            }
+            None => timeline.first_free_layer(position, length),
+        };
+        let Some(free_layer) = free_layer else {
+            eprintln!("{}: 空いているレイヤーがありません", path.display());
+            continue;
+        };
+
+        let media = project.add_media(path.clone(), MediaKind::Image);
+        let clip = project
+            .timeline
+            .new_clip(free_layer, position, length, Source::Media(media));
+        let edit = Edit::AddClip { clip };
+        edit.apply(&mut project.timeline);
+        edits.push(edit);
+        position += length;
+    }
+
+    let ids = edits
+        .iter()
+        .filter_map(|edit| match edit {
+            Edit::AddClip { clip } => Some(clip.id),
+            _ => None,
+        })
+        .collect();
+    match edits.len() {
+        0 => {}
+        1 => history.record(edits.remove(0)),
+        _ => history.record(Edit::Batch {
+            name: "メディアの読み込み".to_string(),
+            edits,
+        }),
+    }
+    ids
+}
@@ -1,18 +1,78 @@
 use cairo::Context;
 use glib::ControlFlow;
-use gtk4::gdk::Display;
+use gtk4::CssProvider;
+use gtk4::gdk::{Display, DragAction, FileList, ModifierType};
 use gtk4::gdk_pixbuf::PixbufLoader;
 use gtk4::prelude::WidgetExtManual;
 use gtk4::prelude::*;
 use gtk4::{
-    Align, Box as GtkBox, DrawingArea, EventControllerMotion, GestureClick, GestureDrag, Image,
-    Label, Orientation, Overlay,
+    Adjustment, Align, Box as GtkBox, Button, DrawingArea, DropTarget, EventControllerMotion,
+    EventControllerScroll, EventControllerScrollFlags, FileDialog, FileFilter, GestureClick,
+    GestureDrag, Grid, Image, Label, Orientation, Paned, PopoverMenu, PopoverMenuBar, Scrollbar,
+    SpinButton,
 };
-use gtk4::{CssProvider, StyleContext};
 use libadwaita::prelude::*;
 use libadwaita::{Application as AdwApplication, ApplicationWindow, HeaderBar};
-use std::cell::RefCell;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Duration;
+
+mod actions;
+mod animation;
+mod apng;
+mod avi;
+mod cli;
+mod command_palette;
+mod config;
+mod drag;
+mod edit_ops;
+mod encoder;
+mod export;
+mod export_dialog;
+mod ffmpeg;
+mod gif;
+mod history;
+mod import;
+mod palette;
+mod preferences;
+mod profile;
+mod profile_manager;
+mod project;
+mod project_file;
+mod render;
+mod render_queue;
+mod render_queue_window;
+mod ruler;
+mod settings;
+mod shortcut_editor;
+mod snap;
+mod timeline;
+mod transport;
+mod view;
+mod wav;
+mod y4m;
+
+use actions::Keymap;
+use config::ConfigFile;
+use drag::DragMode;
+use edit_ops::Command;
+use history::{Edit, History};
+use import::ImportOptions;
+use profile::Profiles;
+use project::{OutputSettings, Project};
+use project_file::ProjectFileError;
+use render::Renderer;
+use render_queue::{RenderQueue, Update};
+use render_queue_window::QueueWindow;
+use ruler::{RULER_HEIGHT, TimeFormat};
+use settings::Settings;
+use snap::Snapper;
+use timeline::{
+    Clip, ClipId, FilterKind, Frame, Layer, Marker, Properties, ShapeKind, Source, Timeline,
+};
+use transport::Transport;
+use view::{HitPart, TimelineView};
 
 const ICON_DATA: &[u8] = include_bytes!("../icon.png");
 
@@ -28,26 +88,7 @@ const ICON_DATA: &[u8] = include_bytes!("../icon.png");
 //     image
 // }
 
-// Apply css to label
-fn apply_label_hover(label: &Label, hover_class: &str) {
-    let motion = EventControllerMotion::new();
-
-    let label_enter = label.clone();
-    let hover_class_enter = hover_class.to_string(); // clone here
-    motion.connect_enter(move |_, _, _| {
-        label_enter.add_css_class(&hover_class_enter);
-    });
-
-    let label_leave = label.clone();
-    let hover_class_leave = hover_class.to_string(); // clone again
-    motion.connect_leave(move |_| {
-        label_leave.remove_css_class(&hover_class_leave);
-    });
-
-    label.add_controller(motion);
-}
-
-// Function for menubar
+// Rounded rectangle path (objects, drop preview)
 fn draw_rounded_rectangle(cr: &Context, x: f64, y: f64, w: f64, h: f64, r: f64) {
     // 左上角
     cr.new_sub_path();
@@ -82,37 +123,1459 @@ fn draw_rounded_rectangle(cr: &Context, x: f64, y: f64, w: f64, h: f64, r: f64)
     cr.close_path();
 }
 
-// Apply css to the header
-fn apply_hover_effects(label: &Label, is_clicked: Rc<RefCell<bool>>) {
-    let label_hover = label.clone();
-    let is_clicked_hover = is_clicked.clone();
+fn project_file_filters() -> gio::ListStore {
+    let filter = FileFilter::new();
+    filter.set_name(Some("Luvita プロジェクト"));
+    filter.add_suffix(project_file::EXTENSION);
+    let filters = gio::ListStore::new::<FileFilter>();
+    filters.append(&filter);
+    filters
+}
+
+// What to bring up to date after a change. Painting changes nothing, so the code
+// that changes the project, history, selection, clipboard or playhead asks for
+// what depends on it.
+#[derive(Clone)]
+struct Refresh {
+    timeline: DrawingArea,
+    preview: DrawingArea,
+    // Menu items, actions and the scrollbar range; set once they exist
+    controls: Rc<OnceCell<Box<dyn Fn()>>>,
+    controls_queued: Rc<Cell<bool>>,
+}
+
+impl Refresh {
+    fn new(timeline: &DrawingArea, preview: &DrawingArea) -> Refresh {
+        Refresh {
+            timeline: timeline.clone(),
+            preview: preview.clone(),
+            controls: Rc::new(OnceCell::new()),
+            controls_queued: Rc::new(Cell::new(false)),
+        }
+    }
+
+    fn set_controls(&self, update: impl Fn() + 'static) {
+        let _ = self.controls.set(Box::new(update));
+    }
+
+    // Hover, drag feedback and other things only the timeline shows
+    fn timeline(&self) {
+        self.timeline.queue_draw();
+    }
+
+    // Deferred to idle because callers may still hold the project or history
+    // borrowed; several changes in a row update once
+    fn controls(&self) {
+        if self.controls_queued.replace(true) {
+            return;
+        }
+        let (controls, queued) = (self.controls.clone(), self.controls_queued.clone());
+        glib::idle_add_local_once(move || {
+            queued.set(false);
+            if let Some(update) = controls.get() {
+                update();
+            }
+        });
+    }
+
+    // The preview shows another frame. This is the only place the preview is
+    // invalidated from: timeline repaints leave it alone, and moving the splits
+    // resizes it, which GTK redraws by itself.
+    fn playhead(&self) {
+        self.timeline.queue_draw();
+        self.preview.queue_draw();
+    }
+
+    // Edits, undo, and loading or replacing the project
+    fn project(&self) {
+        self.playhead();
+        self.controls();
+    }
+}
 
-    let motion_controller = EventControllerMotion::new();
-    motion_controller.connect_enter(move |_, _, _| {
-        if !*is_clicked_hover.borrow() {
-            label_hover.add_css_class("header-label-hover");
+// Layer under a drop, below the last layer clamped to it
+fn drop_layer(view: &TimelineView, y: f64, layer_count: usize) -> Option<usize> {
+    let last = layer_count.checked_sub(1)?;
+    view.y_to_layer(y).map(|layer| layer.min(last))
+}
+
+// Project files that can't be read or written are reported in a dialog
+fn show_file_error(
+    window: &ApplicationWindow,
+    message: &str,
+    path: &Path,
+    error: &ProjectFileError,
+) {
+    gtk4::AlertDialog::builder()
+        .message(message)
+        .detail(format!("{}\n\n{}", path.display(), error))
+        .modal(true)
+        .build()
+        .show(Some(window));
+}
+
+// "プロジェクトを開く". The history and selection refer to the old timeline's
+// objects, so they are cleared as by 閉じる.
+fn open_project(
+    window: &ApplicationWindow,
+    project: Rc<RefCell<Project>>,
+    project_path: Rc<RefCell<Option<PathBuf>>>,
+    history: Rc<RefCell<History>>,
+    selection: Rc<RefCell<Vec<ClipId>>>,
+    refresh: Refresh,
+) {
+    let dialog = FileDialog::builder()
+        .title("プロジェクトを開く")
+        .modal(true)
+        .filters(&project_file_filters())
+        .build();
+    let window_for_error = window.clone();
+    dialog.open(Some(window), gio::Cancellable::NONE, move |result| {
+        let Some(path) = result.ok().and_then(|file| file.path()) else {
+            return;
+        };
+        match project_file::load(&path) {
+            Ok(mut loaded) => {
+                // Files from before profiles existed
+                if loaded.profile.is_empty() {
+                    loaded.profile = profile::builtin_name(&loaded.output);
+                }
+                *project.borrow_mut() = loaded;
+                *project_path.borrow_mut() = Some(path);
+                history.borrow_mut().clear();
+                selection.borrow_mut().clear();
+                refresh.project();
+            }
+            Err(e) => show_file_error(&window_for_error, "プロジェクトを開けません", &path, &e),
         }
     });
+}
 
-    let label_leave = label.clone();
-    let is_clicked_leave = is_clicked.clone();
-    motion_controller.connect_leave(move |_| {
-        if !*is_clicked_leave.borrow() {
-            label_leave.remove_css_class("header-label-hover");
+// "開く": import still images at the playhead, or at (layer, frame) when given
+fn open_media(
+    window: &ApplicationWindow,
+    at: Option<(usize, Frame)>,
+    project: Rc<RefCell<Project>>,
+    history: Rc<RefCell<History>>,
+    transport: Rc<RefCell<Transport>>,
+    import_options: Rc<RefCell<ImportOptions>>,
+    refresh: Refresh,
+) {
+    let filter = FileFilter::new();
+    filter.set_name(Some("画像"));
+    for suffix in import::IMAGE_SUFFIXES {
+        filter.add_suffix(suffix);
+    }
+    let filters = gio::ListStore::new::<FileFilter>();
+    filters.append(&filter);
+
+    let dialog = FileDialog::builder()
+        .title("開く")
+        .modal(true)
+        .filters(&filters)
+        .build();
+    dialog.open_multiple(Some(window), gio::Cancellable::NONE, move |result| {
+        let Ok(files) = result else {
+            return;
+        };
+        let paths: Vec<PathBuf> = (0..files.n_items())
+            .filter_map(|i| files.item(i).and_downcast::<gio::File>())
+            .filter_map(|file| file.path())
+            .collect();
+        import::import_files(
+            &mut project.borrow_mut(),
+            &mut history.borrow_mut(),
+            &paths,
+            at.map_or(transport.borrow().playhead, |(_, frame)| frame),
+            at.map(|(layer, _)| layer),
+            &import_options.borrow(),
+        );
+        refresh.project();
+    });
+}
+
+// "プロジェクトを保存" (asks for a path only the first time)
+fn save_project(
+    window: &ApplicationWindow,
+    project: Rc<RefCell<Project>>,
+    project_path: Rc<RefCell<Option<PathBuf>>>,
+) {
+    if let Some(path) = project_path.borrow().clone() {
+        if let Err(e) = project_file::save(&project.borrow(), &path) {
+            show_file_error(window, "プロジェクトを保存できません", &path, &e);
+        }
+        return;
+    }
+
+    let initial_name = format!("{}.{}", project.borrow().name, project_file::EXTENSION);
+    let dialog = FileDialog::builder()
+        .title("プロジェクトを保存")
+        .modal(true)
+        .initial_name(initial_name.as_str())
+        .filters(&project_file_filters())
+        .build();
+    let window_for_error = window.clone();
+    dialog.save(Some(window), gio::Cancellable::NONE, move |result| {
+        let Some(mut path) = result.ok().and_then(|file| file.path()) else {
+            return;
+        };
+        if path.extension().is_none() {
+            path.set_extension(project_file::EXTENSION);
+        }
+        match project_file::save(&project.borrow(), &path) {
+            Ok(()) => *project_path.borrow_mut() = Some(path),
+            Err(e) => show_file_error(&window_for_error, "プロジェクトを保存できません", &path, &e),
         }
     });
+}
+
+fn undo(project: &Rc<RefCell<Project>>, history: &Rc<RefCell<History>>, refresh: &Refresh) {
+    if history
+        .borrow_mut()
+        .undo(&mut project.borrow_mut().timeline)
+    {
+        refresh.project();
+    }
+}
 
-    label.add_controller(motion_controller);
+fn redo(project: &Rc<RefCell<Project>>, history: &Rc<RefCell<History>>, refresh: &Refresh) {
+    if history
+        .borrow_mut()
+        .redo(&mut project.borrow_mut().timeline)
+    {
+        refresh.project();
+    }
+}
+
+// Build an edit from the timeline and record it for undo; errors are only logged
+fn perform_edit(
+    name: &str,
+    project: &Rc<RefCell<Project>>,
+    history: &Rc<RefCell<History>>,
+    refresh: &Refresh,
+    make: impl FnOnce(&mut Timeline) -> Result<Edit, String>,
+) -> bool {
+    let mut project = project.borrow_mut();
+    match make(&mut project.timeline) {
+        Ok(edit) => {
+            history.borrow_mut().perform(edit, &mut project.timeline);
+            refresh.project();
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            false
+        }
+    }
+}
+
+// Run an edit command on the selection / playhead and record it for undo
+fn run_command(
+    command: Command,
+    pointer: Option<(usize, Frame)>,
+    project: &Rc<RefCell<Project>>,
+    history: &Rc<RefCell<History>>,
+    selection: &Rc<RefCell<Vec<ClipId>>>,
+    transport: &Rc<RefCell<Transport>>,
+    refresh: &Refresh,
+) {
+    let rate = project.borrow().output.frame_rate;
+    let playhead = transport.borrow().playhead;
+    let ids = selection.borrow().clone();
+    perform_edit(command.name(), project, history, refresh, |timeline| {
+        command.edit(timeline, &ids, playhead, pointer, rate)
+    });
+    // Deleted objects drop out of the selection
+    let project = project.borrow();
+    selection
+        .borrow_mut()
+        .retain(|id| project.timeline.clip(*id).is_some());
+}
+
+// "timeline." actions used by the menu bar, the right-click menus and shortcuts.
+// Object commands act on the right-clicked spot if there is one, otherwise on the
+// selection / playhead.
+#[allow(clippy::too_many_arguments)]
+fn timeline_actions(
+    window: &ApplicationWindow,
+    project: Rc<RefCell<Project>>,
+    history: Rc<RefCell<History>>,
+    selection: Rc<RefCell<Vec<ClipId>>>,
+    transport: Rc<RefCell<Transport>>,
+    view: Rc<RefCell<TimelineView>>,
+    import_options: Rc<RefCell<ImportOptions>>,
+    clipboard: Rc<RefCell<Vec<Clip>>>,
+    pointer: Rc<RefCell<Option<(usize, Frame)>>>,
+    snap_enabled: Rc<RefCell<bool>>,
+    time_format: Rc<RefCell<TimeFormat>>,
+    refresh: Refresh,
+) -> gio::SimpleActionGroup {
+    let group = gio::SimpleActionGroup::new();
+
+    for (name, command) in [
+        ("split", Command::Split),
+        ("delete", Command::Delete),
+        ("ripple-delete", Command::RippleDelete),
+        ("ripple-delete-all", Command::RippleDeleteAll),
+        ("close-gap", Command::CloseGap),
+        ("insert-time", Command::InsertTime),
+    ] {
+        let action = gio::SimpleAction::new(name, None);
+        let (project, history, selection, transport, refresh) = (
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            transport.clone(),
+            refresh.clone(),
+        );
+        let pointer = pointer.clone();
+        action.connect_activate(move |_, _| {
+            let pointer = *pointer.borrow();
+            run_command(
+                command, pointer, &project, &history, &selection, &transport, &refresh,
+            );
+        });
+        group.add_action(&action);
+    }
+
+    // Clipboard
+    let copy = gio::SimpleAction::new("copy", None);
+    {
+        let (project, selection, clipboard) =
+            (project.clone(), selection.clone(), clipboard.clone());
+        let refresh = refresh.clone();
+        copy.connect_activate(move |_, _| {
+            let clips = edit_ops::copy(&project.borrow().timeline, &selection.borrow());
+            if !clips.is_empty() {
+                *clipboard.borrow_mut() = clips;
+                // 貼り付け becomes available
+                refresh.controls();
+            }
+        });
+    }
+    group.add_action(&copy);
+
+    let cut = gio::SimpleAction::new("cut", None);
+    {
+        let (project, history, selection, transport, refresh) = (
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            transport.clone(),
+            refresh.clone(),
+        );
+        let clipboard = clipboard.clone();
+        cut.connect_activate(move |_, _| {
+            let clips = edit_ops::copy(&project.borrow().timeline, &selection.borrow());
+            if clips.is_empty() {
+                return;
+            }
+            *clipboard.borrow_mut() = clips;
+            run_command(
+                Command::Delete,
+                None,
+                &project,
+                &history,
+                &selection,
+                &transport,
+                &refresh,
+            );
+        });
+    }
+    group.add_action(&cut);
+
+    // Pasted at the right-clicked spot, otherwise at the playhead on the copied layers
+    let paste = gio::SimpleAction::new("paste", None);
+    {
+        let (project, history, selection, transport, refresh) = (
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            transport.clone(),
+            refresh.clone(),
+        );
+        let (clipboard, pointer) = (clipboard.clone(), pointer.clone());
+        paste.connect_activate(move |_, _| {
+            let clips = clipboard.borrow().clone();
+            let (layer, frame) = pointer.borrow().unwrap_or_else(|| {
+                let layer = clips.iter().map(|c| c.layer).min().unwrap_or(0);
+                (layer, transport.borrow().playhead)
+            });
+            let mut pasted = Vec::new();
+            perform_edit("貼り付け", &project, &history, &refresh, |timeline| {
+                let edit = edit_ops::paste(timeline, &clips, layer, frame)?;
+                if let Edit::Batch { edits, .. } = &edit {
+                    pasted = edits
+                        .iter()
+                        .filter_map(|e| match e {
+                            Edit::AddClip { clip } => Some(clip.id),
+                            _ => None,
+                        })
+                        .collect();
+                }
+                Ok(edit)
+            });
+            if !pasted.is_empty() {
+                *selection.borrow_mut() = pasted;
+            }
+        });
+    }
+    group.add_action(&paste);
+
+    // New objects go where the menu was opened, or on the first free layer at the playhead
+    let add_object: Rc<dyn Fn(Source)> = {
+        let (project, history, import_options, refresh) = (
+            project.clone(),
+            history.clone(),
+            import_options.clone(),
+            refresh.clone(),
+        );
+        let (selection, transport, pointer) =
+            (selection.clone(), transport.clone(), pointer.clone());
+        Rc::new(move |source| {
+            let length = project
+                .borrow()
+                .output
+                .frame_rate
+                .seconds_to_frame(import_options.borrow().object_seconds)
+                .max(1);
+            let frame = transport.borrow().playhead;
+            let Some((layer, frame)) = pointer.borrow().or_else(|| {
+                let layer = project.borrow().timeline.first_free_layer(frame, length)?;
+                Some((layer, frame))
+            }) else {
+                eprintln!("オブジェクトの追加: 空いているレイヤーがありません");
+                return;
+            };
+            let mut added = None;
+            perform_edit(
+                "オブジェクトの追加",
+                &project,
+                &history,
+                &refresh,
+                |timeline| {
+                    let edit = edit_ops::add_object(timeline, source, layer, frame, length)?;
+                    if let Edit::AddClip { clip } = &edit {
+                        added = Some(clip.id);
+                    }
+                    Ok(edit)
+                },
+            );
+            if let Some(id) = added {
+                *selection.borrow_mut() = vec![id];
+            }
+        })
+    };
+
+    let add_media = gio::SimpleAction::new("add-media", None);
+    {
+        let window = window.clone();
+        let (project, history, transport, import_options, refresh) = (
+            project.clone(),
+            history.clone(),
+            transport.clone(),
+            import_options.clone(),
+            refresh.clone(),
+        );
+        let pointer = pointer.clone();
+        add_media.connect_activate(move |_, _| {
+            open_media(
+                &window,
+                *pointer.borrow(),
+                project.clone(),
+                history.clone(),
+                transport.clone(),
+                import_options.clone(),
+                refresh.clone(),
+            );
+        });
+    }
+    group.add_action(&add_media);
+
+    let add_text = gio::SimpleAction::new("add-text", None);
+    {
+        let add_object = add_object.clone();
+        add_text.connect_activate(move |_, _| add_object(Source::Text("テキスト".to_string())));
+    }
+    group.add_action(&add_text);
+
+    let add_shape = gio::SimpleAction::new("add-shape", Some(glib::VariantTy::STRING));
+    {
+        let add_object = add_object.clone();
+        add_shape.connect_activate(move |_, key| {
+            if let Some(shape) = key.and_then(|key| key.str()).and_then(ShapeKind::from_key) {
+                add_object(Source::Shape(shape));
+            }
+        });
+    }
+    group.add_action(&add_shape);
+
+    let add_filter = gio::SimpleAction::new("add-filter", Some(glib::VariantTy::STRING));
+    add_filter.connect_activate(move |_, key| {
+        if let Some(filter) = key.and_then(|key| key.str()).and_then(FilterKind::from_key) {
+            add_object(Source::Filter(filter));
+        }
+    });
+    group.add_action(&add_filter);
+
+    let add_clip_filter = gio::SimpleAction::new("add-clip-filter", Some(glib::VariantTy::STRING));
+    {
+        let (project, history, selection, refresh) = (
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            refresh.clone(),
+        );
+        add_clip_filter.connect_activate(move |_, key| {
+            let Some(filter) = key.and_then(|key| key.str()).and_then(FilterKind::from_key) else {
+                return;
+            };
+            let ids = selection.borrow().clone();
+            perform_edit(
+                "フィルタの追加",
+                &project,
+                &history,
+                &refresh,
+                |timeline| edit_ops::add_filter(timeline, &ids, filter),
+            );
+        });
+    }
+    group.add_action(&add_clip_filter);
+
+    let properties = gio::SimpleAction::new("properties", None);
+    {
+        let window = window.clone();
+        let (project, history, selection, refresh) = (
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            refresh.clone(),
+        );
+        properties.connect_activate(move |_, _| {
+            let Some(&id) = selection.borrow().first() else {
+                return;
+            };
+            show_properties(
+                &window,
+                id,
+                project.clone(),
+                history.clone(),
+                refresh.clone(),
+            );
+        });
+    }
+    group.add_action(&properties);
+
+    // Layer operations on the right-clicked row. The check boxes follow the layer
+    // because the state is refreshed every time the menu opens.
+    let set_layer_flag = |name: &str, set: fn(&mut Layer, bool)| {
+        let action = gio::SimpleAction::new_stateful(name, None, &true.to_variant());
+        let (project, history, refresh, pointer) = (
+            project.clone(),
+            history.clone(),
+            refresh.clone(),
+            pointer.clone(),
+        );
+        action.connect_change_state(move |action, value| {
+            let (Some(value), Some((layer, _))) = (value, *pointer.borrow()) else {
+                return;
+            };
+            let Some(flag) = value.get::<bool>() else {
+                return;
+            };
+            if perform_edit(
+                "レイヤーの設定",
+                &project,
+                &history,
+                &refresh,
+                |timeline| edit_ops::set_layer(timeline, layer, |l| set(l, flag)),
+            ) {
+                action.set_state(value);
+            }
+        });
+        group.add_action(&action);
+    };
+    set_layer_flag("layer-visible", |l, v| l.visible = v);
+    set_layer_flag("layer-locked", |l, v| l.locked = v);
+
+    let move_layer = |name: &str, delta: i64| {
+        let action = gio::SimpleAction::new(name, None);
+        let (project, history, refresh, pointer) = (
+            project.clone(),
+            history.clone(),
+            refresh.clone(),
+            pointer.clone(),
+        );
+        action.connect_activate(move |_, _| {
+            let Some((layer, _)) = *pointer.borrow() else {
+                return;
+            };
+            perform_edit(
+                "レイヤーの移動",
+                &project,
+                &history,
+                &refresh,
+                |timeline| edit_ops::move_layer(timeline, layer, delta),
+            );
+        });
+        group.add_action(&action);
+    };
+    move_layer("layer-up", -1);
+    move_layer("layer-down", 1);
+
+    let undo_action = gio::SimpleAction::new("undo", None);
+    {
+        let (project, history, refresh) = (project.clone(), history.clone(), refresh.clone());
+        undo_action.connect_activate(move |_, _| undo(&project, &history, &refresh));
+    }
+    group.add_action(&undo_action);
+
+    let redo_action = gio::SimpleAction::new("redo", None);
+    {
+        let (project, history, refresh) = (project.clone(), history.clone(), refresh.clone());
+        redo_action.connect_activate(move |_, _| redo(&project, &history, &refresh));
+    }
+    group.add_action(&redo_action);
+
+    let add_marker = gio::SimpleAction::new("add-marker", None);
+    {
+        let (project, history, transport, refresh) = (
+            project.clone(),
+            history.clone(),
+            transport.clone(),
+            refresh.clone(),
+        );
+        add_marker.connect_activate(move |_, _| {
+            let frame = transport.borrow().playhead;
+            perform_edit(
+                "マーカーの追加",
+                &project,
+                &history,
+                &refresh,
+                |timeline| {
+                    let marker = Marker {
+                        frame,
+                        name: format!("マーカー {}", timeline.markers.len() + 1),
+                    };
+                    Ok(Edit::AddMarker { marker })
+                },
+            );
+        });
+    }
+    group.add_action(&add_marker);
+
+    // 表示
+    let fit = gio::SimpleAction::new("fit", None);
+    {
+        let (project, view, refresh) = (project.clone(), view.clone(), refresh.clone());
+        fit.connect_activate(move |_, _| {
+            let end = project.borrow().timeline.end_frame();
+            view.borrow_mut()
+                .zoom_to_range(0, end, refresh.timeline.width() as f64);
+            refresh.timeline();
+            refresh.controls();
+        });
+    }
+    group.add_action(&fit);
+
+    let fit_selection = gio::SimpleAction::new("fit-selection", None);
+    {
+        let (project, view, selection, refresh) = (
+            project.clone(),
+            view.clone(),
+            selection.clone(),
+            refresh.clone(),
+        );
+        fit_selection.connect_activate(move |_, _| {
+            let project = project.borrow();
+            let clips: Vec<&Clip> = selection
+                .borrow()
+                .iter()
+                .filter_map(|id| project.timeline.clip(*id))
+                .collect();
+            let (Some(start), Some(end)) = (
+                clips.iter().map(|c| c.start).min(),
+                clips.iter().map(|c| c.end()).max(),
+            ) else {
+                return;
+            };
+            view.borrow_mut()
+                .zoom_to_range(start, end, refresh.timeline.width() as f64);
+            refresh.timeline();
+            refresh.controls();
+        });
+    }
+    group.add_action(&fit_selection);
+
+    let snap = gio::SimpleAction::new_stateful("snap", None, &snap_enabled.borrow().to_variant());
+    snap.connect_change_state(move |action, value| {
+        if let Some(enabled) = value.and_then(|v| v.get::<bool>()) {
+            *snap_enabled.borrow_mut() = enabled;
+            action.set_state(&enabled.to_variant());
+        }
+    });
+    group.add_action(&snap);
+
+    let time_format_action = gio::SimpleAction::new_stateful(
+        "time-format",
+        Some(glib::VariantTy::STRING),
+        &time_format.borrow().key().to_variant(),
+    );
+    time_format_action.connect_change_state(move |action, value| {
+        if let Some(format) = value.and_then(|v| v.str()).and_then(TimeFormat::from_key) {
+            *time_format.borrow_mut() = format;
+            action.set_state(&format.key().to_variant());
+            refresh.timeline();
+        }
+    });
+    group.add_action(&time_format_action);
+
+    group
+}
+
+// Right-click menus on the timeline. What is offered depends on what was hit:
+// empty layer space, an object, or the layer label column.
+fn connect_context_menu(
+    group: &gio::SimpleActionGroup,
+    project: Rc<RefCell<Project>>,
+    selection: Rc<RefCell<Vec<ClipId>>>,
+    view: Rc<RefCell<TimelineView>>,
+    pointer: Rc<RefCell<Option<(usize, Frame)>>>,
+    draw_area: &DrawingArea,
+    refresh: Refresh,
+) {
+    let action = |name: &str| {
+        group
+            .lookup_action(name)
+            .and_downcast::<gio::SimpleAction>()
+            .expect("timeline action")
+    };
+    let (layer_visible, layer_locked) = (action("layer-visible"), action("layer-locked"));
+    let (layer_up, layer_down) = (action("layer-up"), action("layer-down"));
+
+    let empty_menu = gio::Menu::new();
+    {
+        let add = gio::Menu::new();
+        add.append(
+            Some("メディアオブジェクトを追加"),
+            Some("timeline.add-media"),
+        );
+        add.append_submenu(
+            Some("フィルタオブジェクトを追加"),
+            &filter_menu("timeline.add-filter"),
+        );
+        add.append(Some("テキストを追加"), Some("timeline.add-text"));
+        let shapes = gio::Menu::new();
+        for shape in ShapeKind::ALL {
+            shapes.append(
+                Some(shape.name()),
+                Some(&format!("timeline.add-shape::{}", shape.key())),
+            );
+        }
+        add.append_submenu(Some("図形を追加"), &shapes);
+        empty_menu.append_section(None, &add);
+
+        let edit = gio::Menu::new();
+        edit.append(Some("貼り付け"), Some("timeline.paste"));
+        edit.append(Some(Command::CloseGap.name()), Some("timeline.close-gap"));
+        edit.append(
+            Some(Command::InsertTime.name()),
+            Some("timeline.insert-time"),
+        );
+        empty_menu.append_section(None, &edit);
+    }
+
+    let clip_menu = gio::Menu::new();
+    clip_menu.append_section(None, &clipboard_section());
+    {
+        let edit = gio::Menu::new();
+        edit.append(Some(Command::Delete.name()), Some("timeline.delete"));
+        edit.append(
+            Some(Command::RippleDelete.name()),
+            Some("timeline.ripple-delete"),
+        );
+        edit.append(Some(Command::Split.name()), Some("timeline.split"));
+        clip_menu.append_section(None, &edit);
+    }
+    clip_menu.append_submenu(
+        Some("フィルタを追加"),
+        &filter_menu("timeline.add-clip-filter"),
+    );
+    clip_menu.append(Some("プロパティ"), Some("timeline.properties"));
+
+    let layer_menu = gio::Menu::new();
+    {
+        let flags = gio::Menu::new();
+        flags.append(Some("表示"), Some("timeline.layer-visible"));
+        flags.append(Some("ロック"), Some("timeline.layer-locked"));
+        layer_menu.append_section(None, &flags);
+
+        let order = gio::Menu::new();
+        order.append(Some("上へ移動"), Some("timeline.layer-up"));
+        order.append(Some("下へ移動"), Some("timeline.layer-down"));
+        layer_menu.append_section(None, &order);
+    }
+
+    let popover = PopoverMenu::from_model(None::<&gio::MenuModel>);
+    popover.set_has_arrow(false);
+    popover.set_halign(Align::Start);
+    popover.set_parent(draw_area);
+    {
+        // Later keyboard / menu bar commands act on the playhead again. Deferred
+        // because the chosen item is activated after the popover closes.
+        let pointer = pointer.clone();
+        popover.connect_closed(move |_| {
+            let pointer = pointer.clone();
+            glib::idle_add_local_once(move || *pointer.borrow_mut() = None);
+        });
+    }
+
+    let click = GestureClick::builder().button(3).build(); //left:1 center:2 right:3
+    click.connect_pressed(move |_, _, x, y| {
+        let view = view.borrow();
+        let layer_count = project.borrow().timeline.layers.len();
+        let Some(layer) = view.y_to_layer(y).filter(|&l| l < layer_count) else {
+            return;
+        };
+        *pointer.borrow_mut() = Some((layer, view.x_to_frame(x)));
+
+        let hit = view.hit_test(&project.borrow().timeline, x, y);
+        let model = if x < view.label_area_width {
+            let project = project.borrow();
+            let flags = &project.timeline.layers[layer];
+            layer_visible.set_state(&flags.visible.to_variant());
+            layer_locked.set_state(&flags.locked.to_variant());
+            layer_up.set_enabled(layer > 0);
+            layer_down.set_enabled(layer + 1 < layer_count);
+            &layer_menu
+        } else if let Some((id, _)) = hit {
+            // Right-clicking an unselected object selects just that object
+            if !selection.borrow().contains(&id) {
+                *selection.borrow_mut() = vec![id];
+            }
+            &clip_menu
+        } else {
+            &empty_menu
+        };
+
+        refresh.timeline();
+        refresh.controls();
+        popover.set_menu_model(Some(model));
+        popover.set_pointing_to(Some(&gtk4::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        popover.popup();
+    });
+    draw_area.add_controller(click);
+}
+
+// One item per filter kind, running `action` with the filter's key
+fn filter_menu(action: &str) -> gio::Menu {
+    let filters = gio::Menu::new();
+    for filter in FilterKind::ALL {
+        filters.append(
+            Some(filter.name()),
+            Some(&format!("{}::{}", action, filter.key())),
+        );
+    }
+    filters
+}
+
+fn clipboard_section() -> gio::Menu {
+    let clipboard = gio::Menu::new();
+    clipboard.append(Some("切り取り"), Some("timeline.cut"));
+    clipboard.append(Some("コピー"), Some("timeline.copy"));
+    clipboard.append(Some("貼り付け"), Some("timeline.paste"));
+    clipboard
+}
+
+// Playback commands, so they can be rebound like the editing actions
+fn transport_actions(
+    project: Rc<RefCell<Project>>,
+    transport: Rc<RefCell<Transport>>,
+    refresh: Refresh,
+) -> gio::SimpleActionGroup {
+    let group = gio::SimpleActionGroup::new();
+    type Run = fn(&mut Transport, Frame);
+    let commands: [(&str, Run); 8] = [
+        ("play", |t, _| t.toggle_play()),
+        ("pause", |t, _| t.pause()),
+        ("forward", |t, _| t.shuttle_forward()),
+        ("backward", |t, _| t.shuttle_backward()),
+        ("step-forward", |t, _| t.step(1)),
+        ("step-back", |t, _| t.step(-1)),
+        ("start", |t, _| t.seek(0)),
+        ("end", |t, end| t.seek(end)),
+    ];
+    for (name, run) in commands {
+        let action = gio::SimpleAction::new(name, None);
+        let (project, transport, refresh) = (project.clone(), transport.clone(), refresh.clone());
+        action.connect_activate(move |_, _| {
+            let end = project.borrow().timeline.end_frame();
+            run(&mut transport.borrow_mut(), end);
+            refresh.playhead();
+        });
+        group.add_action(&action);
+    }
+
+    // In / out points (export range) at the playhead
+    type Mark = fn(&mut Project, Frame);
+    let marks: [(&str, Mark); 3] = [
+        ("set-in", |p, frame| {
+            p.in_point = Some(frame);
+            p.out_point = p.out_point.filter(|out| *out >= frame);
+        }),
+        ("set-out", |p, frame| {
+            p.out_point = Some(frame);
+            p.in_point = p.in_point.filter(|i| *i <= frame);
+        }),
+        ("clear-in-out", |p, _| {
+            (p.in_point, p.out_point) = (None, None)
+        }),
+    ];
+    for (name, mark) in marks {
+        let action = gio::SimpleAction::new(name, None);
+        let (project, transport, refresh) = (project.clone(), transport.clone(), refresh.clone());
+        action.connect_activate(move |_, _| {
+            mark(&mut project.borrow_mut(), transport.borrow().playhead);
+            refresh.timeline();
+        });
+        group.add_action(&action);
+    }
+
+    // Jump to a frame (command palette: go to frame / marker)
+    let seek = gio::SimpleAction::new("seek", Some(glib::VariantTy::INT64));
+    seek.connect_activate(move |_, frame| {
+        if let Some(frame) = frame.and_then(|f| f.get::<i64>()) {
+            transport.borrow_mut().seek(frame);
+            refresh.playhead();
+        }
+    });
+    group.add_action(&seek);
+    group
+}
+
+// ファイル / 閉じる: start over with an empty project
+fn close_project(
+    project: &Rc<RefCell<Project>>,
+    project_path: &Rc<RefCell<Option<PathBuf>>>,
+    history: &Rc<RefCell<History>>,
+    selection: &Rc<RefCell<Vec<ClipId>>>,
+    settings: &Settings,
+    refresh: &Refresh,
+) {
+    *project.borrow_mut() = new_project(settings);
+    *project_path.borrow_mut() = None;
+    history.borrow_mut().clear();
+    selection.borrow_mut().clear();
+    refresh.project();
+}
+
+// Empty project with the output settings from 環境設定
+fn new_project(settings: &Settings) -> Project {
+    let output = settings.project_output();
+    Project {
+        profile: profile::builtin_name(&output),
+        output,
+        ..Project::new("untitled")
+    }
+}
+
+// プロファイル: bind the project to another profile, asking first when the
+// objects have to be converted. It can't be undone, so the history is cleared.
+fn switch_profile(
+    window: &ApplicationWindow,
+    name: &str,
+    project: Rc<RefCell<Project>>,
+    history: Rc<RefCell<History>>,
+    transport: Rc<RefCell<Transport>>,
+    profiles: &Profiles,
+    refresh: Refresh,
+) {
+    let Some(target) = profiles.find(name) else {
+        eprintln!("プロファイル {} がありません", name);
+        return;
+    };
+    let apply = {
+        let (window, project, target) = (window.clone(), project.clone(), target.clone());
+        move |convert: bool| {
+            let from = project.borrow().output.frame_rate;
+            if let Err(e) = profile::apply(&mut project.borrow_mut(), &target, convert) {
+                gtk4::AlertDialog::builder()
+                    .message("プロファイルを変更できません")
+                    .detail(e.as_str())
+                    .modal(true)
+                    .build()
+                    .show(Some(&window));
+                return;
+            }
+            if convert {
+                let mut transport = transport.borrow_mut();
+                let playhead = transport.playhead;
+                let to = target.output.frame_rate;
+                transport.seek(profile::convert_frame(playhead, from, to));
+            }
+            history.borrow_mut().clear();
+            refresh.project();
+        }
+    };
+
+    let notes = profile::conversion_notes(&project.borrow(), &target);
+    if notes.is_empty() {
+        apply(false);
+        return;
+    }
+    let detail = format!(
+        "{}\n\n「変換しない」ではフレーム番号と座標がそのまま残ります。\nこの操作は元に戻せません。",
+        notes.join("\n")
+    );
+    let dialog = gtk4::AlertDialog::builder()
+        .message(format!("プロファイルを「{}」に変更します", target.name))
+        .detail(detail)
+        .buttons(["キャンセル", "変換しない", "変換する"])
+        .cancel_button(0)
+        .default_button(2)
+        .modal(true)
+        .build();
+    dialog.choose(
+        Some(window),
+        gio::Cancellable::NONE,
+        move |result| match result {
+            Ok(1) => apply(false),
+            Ok(2) => apply(true),
+            _ => {}
+        },
+    );
+}
+
+// Copy of the project in the cache dir, written only when something changed.
+// `last` holds what was written last time.
+fn autosave(project: &Project, project_path: Option<&PathBuf>, last: &mut String) {
+    let text = match project_file::to_string(project) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("autosave: {}", e);
+            return;
+        }
+    };
+    if text == *last {
+        return;
+    }
+    let name = project_path
+        .and_then(|p| p.file_stem())
+        .map_or(project.name.clone(), |s| s.to_string_lossy().into_owned());
+    let path = config::autosave_file(&format!("{}.{}", name, project_file::EXTENSION));
+    match config::write_atomic(&path, &text) {
+        Ok(()) => *last = text,
+        Err(e) => eprintln!("{}: {}", path.display(), e),
+    }
+}
+
+// Menu bar model. Returns the 編集 menu's undo section too; its labels name the
+// edit that would be undone, so it is rebuilt as the history changes. The same
+// goes for the プロファイル section (see fill_profile_menu).
+fn build_menubar() -> (gio::Menu, gio::Menu, gio::Menu) {
+    let menubar = gio::Menu::new();
+
+    let file = gio::Menu::new();
+    {
+        let open = gio::Menu::new();
+        open.append(Some("開く"), Some("win.open-media"));
+        open.append(Some("閉じる"), Some("win.close-project"));
+        file.append_section(None, &open);
+        let project = gio::Menu::new();
+        project.append(Some("プロジェクトを開く"), Some("win.open-project"));
+        project.append(Some("プロジェクトを保存"), Some("win.save-project"));
+        file.append_section(None, &project);
+        let export = gio::Menu::new();
+        export.append(Some("書き出し…"), Some("win.export"));
+        export.append(Some("レンダーキューに追加…"), Some("win.queue-export"));
+        export.append(Some("レンダーキュー"), Some("win.render-queue"));
+        file.append_section(None, &export);
+        file.append(Some("終了"), Some("app.quit"));
+    }
+    menubar.append_submenu(Some("ファイル"), &file);
+
+    let filter = gio::Menu::new();
+    filter.append_section(
+        Some("選択オブジェクトに追加"),
+        &filter_menu("timeline.add-clip-filter"),
+    );
+    filter.append_section(
+        Some("フィルタオブジェクトを追加"),
+        &filter_menu("timeline.add-filter"),
+    );
+    menubar.append_submenu(Some("フィルタ"), &filter);
+
+    let setting = gio::Menu::new();
+    {
+        setting.append(Some("スナップ"), Some("timeline.snap"));
+        let formats = gio::Menu::new();
+        for format in TimeFormat::ALL {
+            formats.append(
+                Some(format.name()),
+                Some(&format!("timeline.time-format::{}", format.key())),
+            );
+        }
+        setting.append_section(Some("時間の表示"), &formats);
+        let dialogs = gio::Menu::new();
+        dialogs.append(Some("環境設定…"), Some("win.preferences"));
+        dialogs.append(Some("キーボードショートカット…"), Some("win.shortcuts"));
+        setting.append_section(None, &dialogs);
+    }
+    menubar.append_submenu(Some("設定"), &setting);
+
+    let edit = gio::Menu::new();
+    let undo_section = gio::Menu::new();
+    edit.append_section(None, &undo_section);
+    edit.append_section(None, &clipboard_section());
+    {
+        let commands = gio::Menu::new();
+        for (command, action) in [
+            (Command::Split, "timeline.split"),
+            (Command::Delete, "timeline.delete"),
+            (Command::RippleDelete, "timeline.ripple-delete"),
+            (Command::RippleDeleteAll, "timeline.ripple-delete-all"),
+            (Command::CloseGap, "timeline.close-gap"),
+            (Command::InsertTime, "timeline.insert-time"),
+        ] {
+            commands.append(Some(command.name()), Some(action));
+        }
+        edit.append_section(None, &commands);
+        let range = gio::Menu::new();
+        range.append(Some("イン点を設定"), Some("transport.set-in"));
+        range.append(Some("アウト点を設定"), Some("transport.set-out"));
+        range.append(Some("イン・アウト点を解除"), Some("transport.clear-in-out"));
+        edit.append_section(None, &range);
+        let other = gio::Menu::new();
+        other.append(Some("マーカーを追加"), Some("timeline.add-marker"));
+        other.append(Some("プロパティ"), Some("timeline.properties"));
+        edit.append_section(None, &other);
+    }
+    menubar.append_submenu(Some("編集"), &edit);
+
+    let profile = gio::Menu::new();
+    let profile_section = gio::Menu::new();
+    profile.append_section(None, &profile_section);
+    profile.append(Some("プロファイルの管理…"), Some("win.profiles"));
+    menubar.append_submenu(Some("プロファイル"), &profile);
+
+    let show = gio::Menu::new();
+    show.append(Some("プロジェクト全体を表示"), Some("timeline.fit"));
+    show.append(Some("選択範囲を表示"), Some("timeline.fit-selection"));
+    menubar.append_submenu(Some("表示"), &show);
+
+    let other = gio::Menu::new();
+    other.append(Some("コマンドパレット"), Some("win.command-palette"));
+    other.append(Some("Luvita について"), Some("win.about"));
+    menubar.append_submenu(Some("その他"), &other);
+
+    (menubar, undo_section, profile_section)
+}
+
+// プロファイル menu: the project's output settings, then one radio item per profile
+fn fill_profile_menu(section: &gio::Menu, profiles: &Profiles, output: &OutputSettings) {
+    section.remove_all();
+    section.append(Some(&format!("出力: {}", profile::summary(output))), None);
+    for profile in profiles.all() {
+        let item = gio::MenuItem::new(Some(&profile.name), None);
+        item.set_action_and_target_value(Some("win.profile"), Some(&profile.name.to_variant()));
+        section.append_item(&item);
+    }
+}
+
+// Follow the project when it is opened, closed or switched to another profile,
+// and the list when profiles are added, renamed or removed
+fn refresh_profile_menu(
+    action: &gio::SimpleAction,
+    section: &gio::Menu,
+    profiles: &Profiles,
+    project: &Project,
+) {
+    if action.state().and_then(|s| s.get::<String>()).as_deref() != Some(&project.profile) {
+        action.set_state(&project.profile.to_variant());
+    }
+    let label = |i| {
+        section
+            .item_attribute_value(i, "label", Some(glib::VariantTy::STRING))
+            .and_then(|v| v.get::<String>())
+    };
+    let summary = format!("出力: {}", profile::summary(&project.output));
+    let names: Vec<Option<String>> = (1..section.n_items()).map(label).collect();
+    let wanted: Vec<Option<String>> = profiles.all().into_iter().map(|p| Some(p.name)).collect();
+    if label(0).as_deref() != Some(summary.as_str()) || names != wanted {
+        fill_profile_menu(section, profiles, &project.output);
+    }
+}
+
+// Keep menu items in step with the history, selection and clipboard
+fn refresh_actions(
+    group: &gio::SimpleActionGroup,
+    undo_section: &gio::Menu,
+    history: &History,
+    has_selection: bool,
+    has_clipboard: bool,
+    time_format: TimeFormat,
+) {
+    let set_enabled = |name: &str, enabled: bool| {
+        if let Some(action) = group
+            .lookup_action(name)
+            .and_downcast::<gio::SimpleAction>()
+            && action.is_enabled() != enabled
+        {
+            action.set_enabled(enabled);
+        }
+    };
+    let undo_name = history.undo_name();
+    let redo_name = history.redo_name();
+    set_enabled("undo", undo_name.is_some());
+    set_enabled("redo", redo_name.is_some());
+    for name in [
+        "cut",
+        "copy",
+        "delete",
+        "ripple-delete",
+        "ripple-delete-all",
+        "properties",
+        "fit-selection",
+        "add-clip-filter",
+    ] {
+        set_enabled(name, has_selection);
+    }
+    set_enabled("paste", has_clipboard);
+
+    if let Some(action) = group.lookup_action("time-format")
+        && action
+            .state()
+            .and_then(|s| s.str().map(str::to_string))
+            .as_deref()
+            != Some(time_format.key())
+    {
+        action.change_state(&time_format.key().to_variant());
+    }
+
+    let labels = [
+        format!("元に戻す: {}", undo_name.as_deref().unwrap_or("-")),
+        format!("やり直す: {}", redo_name.as_deref().unwrap_or("-")),
+    ];
+    let current = |i: i32| {
+        undo_section
+            .item_attribute_value(i, "label", Some(glib::VariantTy::STRING))
+            .and_then(|v| v.get::<String>())
+    };
+    if undo_section.n_items() != 2
+        || current(0).as_deref() != Some(labels[0].as_str())
+        || current(1).as_deref() != Some(labels[1].as_str())
+    {
+        undo_section.remove_all();
+        undo_section.append(Some(&labels[0]), Some("timeline.undo"));
+        undo_section.append(Some(&labels[1]), Some("timeline.redo"));
+    }
+}
+
+// Object properties; changes while the window is open undo as one step
+fn show_properties(
+    window: &ApplicationWindow,
+    id: ClipId,
+    project: Rc<RefCell<Project>>,
+    history: Rc<RefCell<History>>,
+    refresh: Refresh,
+) {
+    let Some(props) = project.borrow().timeline.clip(id).map(|c| c.props.clone()) else {
+        return;
+    };
+
+    let dialog = gtk4::Window::builder()
+        .title("プロパティ")
+        .transient_for(window)
+        .modal(true)
+        .resizable(false)
+        .build();
+    let grid = Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+
+    // (label, value, min, max, step)
+    let fields = [
+        ("X", props.x, -10000.0, 10000.0, 1.0),
+        ("Y", props.y, -10000.0, 10000.0, 1.0),
+        ("拡大率 (%)", props.zoom * 100.0, 0.0, 5000.0, 1.0),
+        ("不透明度 (%)", props.opacity * 100.0, 0.0, 100.0, 1.0),
+        ("回転 (°)", props.rotation, -3600.0, 3600.0, 1.0),
+    ];
+    let spins: Vec<SpinButton> = fields
+        .iter()
+        .enumerate()
+        .map(|(row, &(name, value, min, max, step))| {
+            let label = Label::new(Some(name));
+            label.set_halign(Align::Start);
+            let spin = SpinButton::with_range(min, max, step);
+            spin.set_digits(1);
+            spin.set_value(value);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(&spin, 1, row as i32, 1, 1);
+            spin
+        })
+        .collect();
+
+    history.borrow_mut().begin_group();
+    for spin in &spins {
+        let (project, history, refresh) = (project.clone(), history.clone(), refresh.clone());
+        let spins = spins.clone();
+        let color = props.color;
+        spin.connect_value_changed(move |_| {
+            let to = Properties {
+                x: spins[0].value(),
+                y: spins[1].value(),
+                zoom: spins[2].value() / 100.0,
+                opacity: spins[3].value() / 100.0,
+                rotation: spins[4].value(),
+                color,
+            };
+            perform_edit(
+                "プロパティの変更",
+                &project,
+                &history,
+                &refresh,
+                |timeline| {
+                    let from = timeline
+                        .clip(id)
+                        .map(|c| c.props.clone())
+                        .ok_or("オブジェクトがありません")?;
+                    Ok(Edit::SetProperties { id, from, to })
+                },
+            );
+        });
+    }
+    dialog.connect_close_request(move |_| {
+        history.borrow_mut().end_group();
+        // The undo item names the group now
+        refresh.controls();
+        glib::Propagation::Proceed
+    });
+
+    let close = Button::with_label("閉じる");
+    {
+        let dialog = dialog.clone();
+        close.connect_clicked(move |_| dialog.close());
+    }
+    grid.attach(&close, 1, fields.len() as i32, 1, 1);
+    dialog.set_child(Some(&grid));
+    dialog.present();
+}
+
+// Scale the rendered frame into the preview area, keeping the aspect ratio
+// Non-square pixels are stretched horizontally by `pixel_aspect`
+fn draw_preview(
+    cr: &Context,
+    frame: &cairo::ImageSurface,
+    pixel_aspect: f64,
+    area_w: f64,
+    area_h: f64,
+) {
+    let (fw, fh) = (frame.width() as f64 * pixel_aspect, frame.height() as f64);
+    let scale = (area_w / fw).min(area_h / fh);
+    let (x, y) = ((area_w - fw * scale) / 2.0, (area_h - fh * scale) / 2.0);
+    cr.save().unwrap();
+    cr.rectangle(x, y, fw * scale, fh * scale);
+    cr.clip();
+    cr.translate(x, y);
+    cr.scale(scale * pixel_aspect, scale);
+    cr.set_source_surface(frame, 0.0, 0.0).unwrap();
+    cr.paint().unwrap();
+    cr.restore().unwrap();
+}
+
+// Draw one object inside its layer row
+fn draw_clip(cr: &Context, clip: &Clip, name: &str, selected: bool, view: &TimelineView) {
+    let (x, y) = (view.frame_to_x(clip.start), view.layer_to_y(clip.layer));
+    let w = clip.length as f64 * view.pixels_per_frame;
+    let layer_height = view.layer_height;
+    match clip.source {
+        Source::Media(_) => cr.set_source_rgb(0.2, 0.4, 0.7),
+        Source::Text(_) => cr.set_source_rgb(0.6, 0.3, 0.6),
+        Source::Shape(_) => cr.set_source_rgb(0.3, 0.6, 0.3),
+        Source::Filter(_) => cr.set_source_rgb(0.7, 0.5, 0.2),
+    }
+    draw_rounded_rectangle(cr, x, y + 2.0, w, layer_height - 4.0, 4.0);
+    cr.fill_preserve().unwrap();
+
+    if selected {
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        cr.set_line_width(2.0);
+        cr.stroke().unwrap();
+
+        // Trim handles
+        let handle = view::HANDLE_WIDTH.min(w / 3.0);
+        cr.set_source_rgba(1.0, 1.0, 1.0, 0.5);
+        cr.rectangle(x + 1.0, y + 4.0, handle - 1.0, layer_height - 8.0);
+        cr.rectangle(x + w - handle, y + 4.0, handle - 1.0, layer_height - 8.0);
+        cr.fill().unwrap();
+    } else {
+        cr.new_path();
+    }
+
+    // Name inside the object
+    cr.save().unwrap();
+    cr.rectangle(x, y, w, layer_height);
+    cr.clip();
+    cr.set_source_rgb(0.95, 0.95, 0.95);
+    cr.set_font_size(12.0);
+    cr.move_to(x + 8.0, y + layer_height / 2.0 + 4.0);
+    cr.show_text(name).unwrap();
+    cr.restore().unwrap();
 }
 
 fn main() {
+    // `luvita render ...` exports without opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(cli::COMMAND) {
+        std::process::exit(cli::run(&args[2..]));
+    }
+
+    // Preferences, loaded from the config dir
+    let settings = Rc::new(RefCell::new(Settings::load_user()));
+
     // UI
-    let preview_height = 430.0;
-    // let preview_height_int = preview_height as i32;
-    let playhead_position = Rc::new(RefCell::new((200.0, 150.0)));
+    let transport = Rc::new(RefCell::new(Transport::new()));
 
     let mouse_position = Rc::new(RefCell::new((0.0, 0.0)));
-    let show_rect = Rc::new(RefCell::new(false)); // ← フラグを作る
+    let time_format = Rc::new(RefCell::new(TimeFormat::default()));
+    // The ruler is at the top of the timeline area
+    let view = Rc::new(RefCell::new(TimelineView::new(
+        0.0,
+        settings.borrow().layer_height,
+    )));
+    let selection: Rc<RefCell<Vec<ClipId>>> = Rc::new(RefCell::new(Vec::new()));
+    let drag_mode = Rc::new(RefCell::new(DragMode::Idle));
+    let snap_enabled = Rc::new(RefCell::new(true));
+    // Frame the dragged edge is currently snapped to
+    let snap_line: Rc<RefCell<Option<Frame>>> = Rc::new(RefCell::new(None));
+
+    // Timeline data
+    let project = Rc::new(RefCell::new(new_project(&settings.borrow())));
+    let project_path: Rc<RefCell<Option<PathBuf>>> = Rc::new(RefCell::new(None));
+    let history = Rc::new(RefCell::new(History::new(settings.borrow().history_limit)));
+    let renderer = Rc::new(RefCell::new(Renderer::new()));
+    renderer
+        .borrow_mut()
+        .set_cache_limit(settings.borrow().image_cache_bytes());
+    let import_options = Rc::new(RefCell::new(settings.borrow().import_options()));
+    // Cut / copied objects
+    let clipboard: Rc<RefCell<Vec<Clip>>> = Rc::new(RefCell::new(Vec::new()));
+    // (layer, frame) that was right-clicked; None for keyboard / menu bar commands
+    let context_pointer: Rc<RefCell<Option<(usize, Frame)>>> = Rc::new(RefCell::new(None));
+    // Output profiles defined by the user, loaded from the config dir
+    let profiles = Rc::new(RefCell::new(Profiles::load_user()));
+    // Keyboard shortcuts, loaded from the config dir
+    let keymap = Rc::new(RefCell::new(Keymap::load_user()));
+    // Commands run from the command palette, most recent first
+    let recent_commands = Rc::new(RefCell::new(palette::Recent::default()));
+    // (frame, layer, file count) while files are dragged over the timeline
+    let drop_ghost: Rc<RefCell<Option<(Frame, usize, usize)>>> = Rc::new(RefCell::new(None));
 
     // Build Adwaita Application
     let app = AdwApplication::builder()
@@ -135,72 +1598,16 @@ fn main() {
                 /* color: white;  ボタンや文字が見えるように */
             }
 
-            .header-label {
-                background-color: transparent;
-                margin: 3px -3px 3px -3px;
+            popovermenubar > item {
                 padding: 0 8px 0 8px;
                 font-size: 13px;
             }
-
-            .header-label-hover {
-                background-color: rgba(151, 151, 151, 0.24); /* 半透明な青 */
-                border-radius: 8px;
-            }
-
-            .header-label-clicked {
-                background-color: rgba(0, 122, 204, 1.0); /* 濃い青 */
-                color: white;
-            }
-
-            .menu-button {
-                margin: 10px 0 0 75px;
-                font-size: 13px;
-                padding: 4px 10px 4px 10px;
-                width: 100px;
-            }
-
-            .menu-button-hover {
-                background-color: rgba(100, 100, 230, 1.0);
-                border-radius: 6px;
-                width: 100px;              /* ← 幅を揃える */
-                display: inline-block;     /* ← ラベルに幅を持たせる */
-            }
-
-            .menu-button1 {
-            margin: 10px 0 0 75px;
-            font-size: 13px;
-            padding: 4px 10px;
-            min-width: 100px;
-            max-width: 100px;
-            }
-
-            .menu-button-hover1 {
-                background-color: rgba(100, 100, 230, 1.0);
-                border-radius: 6px;
-                width: 100px;              /* ← 幅を揃える */
-                display: inline-block;     /* ← ラベルに幅を持たせる */
-            }
-
-            .transparent-button {
-                background-color: transparent;
-                border: none;
-                box-shadow: none;
-                font-size: 13px;
-                font-weight: 400; /* 細い文字 */
-                padding: 4px 1px0 4px 0;
-            }
-
-            .transparent-button:hover {
-                background-color: rgba(100, 100, 230, 1.0); /* ホバー時だけ少し青く */
-                border-radius: 6px;
-            }
         ";
 
         let provider = CssProvider::new();
         provider.load_from_data(css);
 
-        #[allow(deprecated)]
-        StyleContext::add_provider_for_display(
+        gtk4::style_context_add_provider_for_display(
             &Display::default().expect("Display not found"),
             &provider,
             gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION,
@@ -210,79 +1617,179 @@ fn main() {
         let window = ApplicationWindow::builder()
             .application(app)
             .title("Luvita")
-            .default_width(800)
-            .default_height(600)
+            .default_width(settings.borrow().window_width)
+            .default_height(settings.borrow().window_height)
+            .maximized(settings.borrow().window_maximized)
             .build();
 
-        // Get window size
-        let last_size = Rc::new(RefCell::new((0, 0)));
-        let last_size_clone = last_size.clone();
-        window.add_tick_callback(move |win, _| {
-            let alloc = win.allocation();
-            let (w, h) = (alloc.width(), alloc.height());
-            let mut last = last_size_clone.borrow_mut();
-            if w != last.0 || h != last.1 {
-                //println!("Window resized: width = {}, height = {}", w, h);
-                *last = (w, h);
-            }
-            ControlFlow::Continue
-        });
+        // Preview of the frame under the playhead
+        let preview_area = DrawingArea::builder()
+            .content_width(320)
+            .content_height(180)
+            .build();
+        {
+            let (project, renderer) = (project.clone(), renderer.clone());
+            let transport = transport.clone();
+            preview_area.set_draw_func(move |_, cr, width, height| {
+                let project = project.borrow();
+                let playhead = transport.borrow().playhead;
+                match renderer.borrow_mut().render_frame(&project, playhead) {
+                    Ok(surface) => draw_preview(
+                        cr,
+                        &surface,
+                        project.output.pixel_aspect,
+                        width as f64,
+                        height as f64,
+                    ),
+                    Err(e) => eprintln!("render: {}", e),
+                }
+            });
+        }
 
-        // DrawingArea
+        // Timeline (ruler, layers and objects)
         let draw_area = DrawingArea::builder()
-            .content_width(1133)
-            .content_height(700)
+            .content_width(640)
+            .content_height(160)
+            .vexpand(true)
             .build();
+        let refresh = Refresh::new(&draw_area, &preview_area);
 
+        // Horizontal scrollbar under the timeline
+        let hadjustment = Adjustment::new(0.0, 0.0, 1.0, 1.0, 10.0, 1.0);
+        let hscrollbar = Scrollbar::new(Orientation::Horizontal, Some(&hadjustment));
         {
-            let playhead = playhead_position.clone();
-            let mouse_position_clone = mouse_position.clone(); // ★追加
-            let show_rect_clone = show_rect.clone(); // ← clone して中で使えるように
-
-            draw_area.set_draw_func(move |drawing_area, cr, width, height| {
-                //UI
-                let separator_line_x = 800.0;
-
-                // 🎯 [追加] マウスが (0,0)-(50,50) にあるときに赤い四角を表示
-                let (mx, my) = *mouse_position_clone.borrow();
-                if mx >= 0.0 && mx <= 50.0 && my >= 0.0 && my <= 50.0 {
-                    cr.set_source_rgb(1.0, 0.0, 0.0); // 赤色
-                    cr.rectangle(0.0, 0.0, 50.0, 50.0); // 四角形
-                    cr.fill().unwrap();
+            let view = view.clone();
+            let refresh = refresh.clone();
+            hadjustment.connect_value_changed(move |adjustment| {
+                let mut view = view.borrow_mut();
+                if (view.scroll_frame - adjustment.value()).abs() > f64::EPSILON {
+                    view.scroll_frame = adjustment.value();
+                    refresh.timeline();
                 }
+            });
+        }
 
-                // ✅ フラグが true のときだけ四角を描画
-                if *show_rect_clone.borrow() {
-                    cr.set_source_rgb(1.0, 0.0, 0.0); // 赤
-                    cr.rectangle(0.0, 0.0, 50.0, 50.0);
-                    cr.fill().unwrap();
+        // Menus and shortcuts run "timeline." actions on the window
+        let timeline_group = timeline_actions(
+            &window,
+            project.clone(),
+            history.clone(),
+            selection.clone(),
+            transport.clone(),
+            view.clone(),
+            import_options.clone(),
+            clipboard.clone(),
+            context_pointer.clone(),
+            snap_enabled.clone(),
+            time_format.clone(),
+            refresh.clone(),
+        );
+        window.insert_action_group("timeline", Some(&timeline_group));
+        let (menubar_model, undo_section, profile_section) = build_menubar();
+
+        // プロファイル: radio items switch the project's profile
+        let profile_action = gio::SimpleAction::new_stateful(
+            "profile",
+            Some(glib::VariantTy::STRING),
+            &project.borrow().profile.to_variant(),
+        );
+        {
+            let window_for_action = window.clone();
+            let (project, history) = (project.clone(), history.clone());
+            let (transport, profiles) = (transport.clone(), profiles.clone());
+            let refresh = refresh.clone();
+            profile_action.connect_activate(move |_, name| {
+                if let Some(name) = name.and_then(|n| n.get::<String>()) {
+                    switch_profile(
+                        &window_for_action,
+                        &name,
+                        project.clone(),
+                        history.clone(),
+                        transport.clone(),
+                        &profiles.borrow(),
+                        refresh.clone(),
+                    );
                 }
+            });
+        }
+        window.add_action(&profile_action);
+
+        let profiles_action = gio::SimpleAction::new("profiles", None);
+        {
+            let window_for_action = window.clone();
+            let (project, profiles) = (project.clone(), profiles.clone());
+            let refresh = refresh.clone();
+            profiles_action.connect_activate(move |_, _| {
+                let changed: Rc<dyn Fn()> = {
+                    let refresh = refresh.clone();
+                    Rc::new(move || refresh.controls())
+                };
+                profile_manager::show(
+                    &window_for_action,
+                    profiles.clone(),
+                    &project.borrow().output,
+                    changed,
+                );
+            });
+        }
+        window.add_action(&profiles_action);
+
+        {
+            let transport = transport.clone();
+            let view = view.clone();
+            let project_for_draw = project.clone();
+            let time_format = time_format.clone();
+            let drop_ghost = drop_ghost.clone();
+            let import_options = import_options.clone();
+            let selection = selection.clone();
+            let drag_mode = drag_mode.clone();
+            let snap_line = snap_line.clone();
+
+            draw_area.set_draw_func(move |_, cr, width, height| {
+                //UI
+                let view = view.borrow();
+                let label_area_width = view.label_area_width; // 左のラベル描画幅
+                let playhead = transport.borrow().playhead;
+                let ruler_y = view.ruler_y;
 
                 // Draw background
                 //cr.set_source_rgba(0.1, 0.1, 0.1, 0.0);
                 //cr.paint().unwrap();
 
                 // Draw layer
-                let layer_height = 30.0;
-                let label_area_width = 40.0; // 左のラベル描画幅
+                let layer_height = view.layer_height;
                 cr.set_source_rgb(0.3, 0.3, 0.3);
                 cr.set_line_width(1.0);
 
-                let num_layers = (height as f64 / layer_height).ceil() as i32;
-                let top_offset = &preview_height;
-                for i in 0..num_layers {
-                    let y = i as f64 * layer_height + top_offset;
+                let project = project_for_draw.borrow();
+                let first_layer = view.scroll_layer;
+                let last_layer = (first_layer + view.visible_layers(height as f64))
+                    .min(project.timeline.layers.len());
+                for i in first_layer..last_layer {
+                    let y = view.layer_to_y(i);
 
                     // 横線
                     cr.move_to(0.0, y);
                     cr.line_to(width as f64, y);
                     cr.stroke().unwrap();
 
-                    // レイヤー番号テキストを左に描画
+                    // Locked layers are shaded
+                    let layer = &project.timeline.layers[i];
+                    if layer.locked {
+                        cr.set_source_rgba(0.5, 0.5, 0.5, 0.15);
+                        cr.rectangle(0.0, y, width as f64, layer_height);
+                        cr.fill().unwrap();
+                    }
+
+                    // レイヤー番号テキストを左に描画 (hidden layers dimmed)
                     let label = format!(" {}", i + 1);
                     cr.set_font_size(14.0);
                     cr.move_to(5.0, y + 14.0); // 5px右、14px下に調整（フォントサイズ考慮）
-                    cr.set_source_rgb(0.8, 0.8, 0.8);
+                    if layer.visible {
+                        cr.set_source_rgb(0.8, 0.8, 0.8);
+                    } else {
+                        cr.set_source_rgb(0.4, 0.4, 0.4);
+                    }
                     // cr.set_source_rgb(1.0, 1.0, 1.0);
                     cr.show_text(&label).unwrap();
 
@@ -291,222 +1798,709 @@ fn main() {
                     cr.move_to(label_area_width, y);
                     cr.line_to(label_area_width, y + layer_height);
                     cr.stroke().unwrap();
+
+                    // Objects on this layer
+                    cr.save().unwrap();
+                    cr.rectangle(label_area_width, y, width as f64, layer_height);
+                    cr.clip();
+                    for clip in project.timeline.clips_on_layer(i) {
+                        let selected = selection.borrow().contains(&clip.id);
+                        draw_clip(cr, clip, &project.clip_name(clip), selected, &view);
+                    }
+                    cr.restore().unwrap();
+                    cr.set_source_rgb(0.3, 0.3, 0.3);
                 }
 
-                // Get mouse position
-                let (x, _y) = *playhead.borrow();
+                // Rubber band
+                if let DragMode::Select {
+                    rect: (x0, y0, x1, y1),
+                    ..
+                } = *drag_mode.borrow()
+                {
+                    cr.rectangle(x0.min(x1), y0.min(y1), (x1 - x0).abs(), (y1 - y0).abs());
+                    cr.set_source_rgba(0.4, 0.6, 1.0, 0.2);
+                    cr.fill_preserve().unwrap();
+                    cr.set_source_rgb(0.4, 0.6, 1.0);
+                    cr.set_line_width(1.0);
+                    cr.stroke().unwrap();
+                }
+
+                // Where dropped files would land
+                if let Some((frame, layer, count)) = *drop_ghost.borrow() {
+                    let length = project
+                        .output
+                        .frame_rate
+                        .seconds_to_frame(import_options.borrow().image_seconds)
+                        .max(1);
+                    let y = view.layer_to_y(layer);
+                    cr.set_source_rgba(0.4, 0.6, 1.0, 0.35);
+                    for n in 0..count {
+                        let x = view.frame_to_x(frame + n as Frame * length);
+                        let w = length as f64 * view.pixels_per_frame;
+                        draw_rounded_rectangle(cr, x, y + 2.0, w, layer_height - 4.0, 4.0);
+                        cr.fill().unwrap();
+                    }
+                }
+
+                // Time ruler
+                ruler::draw_ruler(
+                    cr,
+                    ruler_y,
+                    label_area_width,
+                    width as f64,
+                    view.frame_to_x(0),
+                    view.pixels_per_frame,
+                    project.output.frame_rate,
+                    *time_format.borrow(),
+                );
+
+                // In / out range on the ruler
+                if project.in_point.is_some() || project.out_point.is_some() {
+                    let (start, end) = project.in_out_range();
+                    let x0 = view.frame_to_x(start).max(label_area_width);
+                    let x1 = view.frame_to_x(end).min(width as f64);
+                    if x1 > x0 {
+                        cr.set_source_rgba(0.3, 0.5, 1.0, 0.35);
+                        cr.rectangle(x0, ruler_y, x1 - x0, RULER_HEIGHT);
+                        cr.fill().unwrap();
+                    }
+                }
+
+                // Markers
+                for marker in &project.timeline.markers {
+                    let x = view.frame_to_x(marker.frame);
+                    if x < label_area_width {
+                        continue;
+                    }
+                    cr.set_source_rgb(0.3, 0.8, 0.5);
+                    cr.move_to(x - 5.0, ruler_y);
+                    cr.line_to(x + 5.0, ruler_y);
+                    cr.line_to(x, ruler_y + 8.0);
+                    cr.close_path();
+                    cr.fill().unwrap();
+                }
+
+                // Snap indicator across the layer rows
+                if let Some(frame) = *snap_line.borrow() {
+                    let x = view.frame_to_x(frame);
+                    cr.set_source_rgb(0.3, 0.9, 1.0);
+                    cr.set_line_width(1.0);
+                    cr.move_to(x, view.layers_top());
+                    cr.line_to(x, height as f64);
+                    cr.stroke().unwrap();
+                }
 
                 // Draw playhead
+                let x = view.frame_to_x(playhead);
                 cr.set_source_rgb(1.0, 0.8, 0.2);
                 cr.set_line_width(1.0);
-                cr.move_to(x, preview_height);
-                cr.line_to(x, preview_height + 300.0);
+                cr.move_to(x, ruler_y);
+                cr.line_to(x, height as f64);
                 let _ = cr.stroke();
+            });
+        }
 
-                // マウスの動きに反応
-                let motion = EventControllerMotion::new();
-                let playhead_position_for_motion = playhead.clone();
-                //let timeline_area_for_motion = drawing_area.clone();
-
-                motion.connect_motion(move |_, x, y| {
-                    *playhead_position_for_motion.borrow_mut() = (x, y); // マウスが動いているときの座標
-                    // println!("{}, {}", x, y);
-                    //timeline_area_for_motion.queue_draw();
-                });
-
-                drawing_area.add_controller(motion);
-                //println!("{}, {}", playhead.borrow().0, playhead.borrow().1);
-
-                // Menubar
-                cr.set_source_rgb(0.2, 0.2, 0.2);
-                let offset_x = 40.0;
-                let offset_y = 1.0;
-                let width = 270.0;
-                let height = 123.0;
-                let radius = 8.0;
-                draw_rounded_rectangle(cr, offset_x, offset_y, width, height, radius);
-                cr.fill().unwrap();
-
-                // Drraw separator line
-                cr.set_source_rgb(0.5, 0.5, 0.5);
-                cr.set_line_width(1.0);
-                cr.move_to(separator_line_x, 0.0);
-                cr.line_to(separator_line_x, preview_height);
-                cr.stroke().unwrap();
+        // Menu items and the scrollbar, updated by `refresh.controls()` from the
+        // code that changes the history, selection, clipboard, zoom or project
+        {
+            let (project, history, selection) =
+                (project.clone(), history.clone(), selection.clone());
+            let (clipboard, time_format, view) =
+                (clipboard.clone(), time_format.clone(), view.clone());
+            let (timeline_group, undo_section) = (timeline_group.clone(), undo_section.clone());
+            let (profile_action, profile_section) =
+                (profile_action.clone(), profile_section.clone());
+            let profiles = profiles.clone();
+            let (hadjustment, draw_area) = (hadjustment.clone(), draw_area.clone());
+            refresh.set_controls(move || {
+                let project = project.borrow();
+                refresh_actions(
+                    &timeline_group,
+                    &undo_section,
+                    &history.borrow(),
+                    !selection.borrow().is_empty(),
+                    !clipboard.borrow().is_empty(),
+                    *time_format.borrow(),
+                );
+                refresh_profile_menu(
+                    &profile_action,
+                    &profile_section,
+                    &profiles.borrow(),
+                    &project,
+                );
+
+                // Scrollbar range from the zoom and project length
+                let (scroll_frame, page) = {
+                    let view = view.borrow();
+                    (
+                        view.scroll_frame,
+                        view.visible_frames(draw_area.width() as f64),
+                    )
+                };
+                let upper = (project.timeline.end_frame() as f64 * 1.2).max(scroll_frame + page);
+                hadjustment.configure(scroll_frame, 0.0, upper, 1.0, page * 0.9, page);
             });
         }
-        // 🎯 ③マウスの動きを追跡する EventControllerMotion を設定
+        {
+            let refresh = refresh.clone();
+            draw_area.connect_resize(move |_, _, _| refresh.controls());
+        }
+        refresh.controls();
+        // Last pointer position, the anchor for Ctrl+wheel zoom
         let motion = EventControllerMotion::new();
-        let mouse_position_for_motion = mouse_position.clone(); // Rc なので clone で共有
-        let draw_area_clone = draw_area.clone(); // DrawingArea を再描画するための clone
-
+        let mouse_position_for_motion = mouse_position.clone();
         motion.connect_motion(move |_, x, y| {
-            *mouse_position_for_motion.borrow_mut() = (x, y); // 座標を更新
-            draw_area_clone.queue_draw(); // 描画エリアを再描画（四角が表示される）
+            *mouse_position_for_motion.borrow_mut() = (x, y);
         });
-
-        // コントローラーを DrawingArea に追加
         draw_area.add_controller(motion);
 
-        // Follow playhead while right-clicking
-        let drag = GestureDrag::new();
-        let drag_offset = Rc::new(RefCell::new((0.0, 0.0)));
-        let playhead_position_for_begin = playhead_position.clone();
-        let playhead_position_for_end = playhead_position.clone();
-        let drag_offset_for_begin = drag_offset.clone();
-        let drag_offset_for_end = drag_offset.clone();
-
-        drag.connect_drag_begin(move |_, start_x, start_y| {
-            let (cx, cy) = *playhead_position_for_begin.borrow();
-            drag_offset_for_begin.borrow_mut().0 = start_x - cx;
-            drag_offset_for_begin.borrow_mut().1 = start_y - cy;
-        });
+        // Wheel: layers, Shift+wheel: time, Ctrl+wheel: zoom at the mouse
+        let scroll = EventControllerScroll::new(EventControllerScrollFlags::BOTH_AXES);
+        {
+            let (project, view) = (project.clone(), view.clone());
+            let mouse_position = mouse_position.clone();
+            let refresh = refresh.clone();
+            scroll.connect_scroll(move |controller, dx, dy| {
+                let state = controller.current_event_state();
+                let mut view = view.borrow_mut();
+                if state.contains(ModifierType::CONTROL_MASK) {
+                    view.zoom_at(1.25_f64.powf(-dy), mouse_position.borrow().0);
+                } else if state.contains(ModifierType::SHIFT_MASK) {
+                    view.scroll_by_pixels((dx + dy) * 40.0);
+                } else {
+                    view.scroll_by_pixels(dx * 40.0);
+                    let layer_count = project.borrow().timeline.layers.len();
+                    view.scroll_layers(dy.round() as i64, layer_count);
+                }
+                refresh.timeline();
+                // Zoom and scrolling move the scrollbar
+                refresh.controls();
+                glib::Propagation::Stop
+            });
+        }
+        draw_area.add_controller(scroll);
 
-        drag.connect_drag_end(move |_, end_x, end_y| {
-            let (cx, cy) = *playhead_position_for_end.borrow();
-            drag_offset_for_end.borrow_mut().0 = end_x - cx;
-            drag_offset_for_end.borrow_mut().1 = end_y - cy;
-            println!("endpoint: {}", end_x) //end_xは移動距離だった
-        });
+        // Drag on the timeline: seek, select, move and trim objects
+        let drag = GestureDrag::new();
+        {
+            let (project, history, view) = (project.clone(), history.clone(), view.clone());
+            let (transport, selection) = (transport.clone(), selection.clone());
+            let drag_mode = drag_mode.clone();
+            let refresh = refresh.clone();
+            drag.connect_drag_begin(move |gesture, x, y| {
+                let view = view.borrow();
+                let state = gesture.current_event_state();
+                let toggle = state.contains(ModifierType::CONTROL_MASK);
+                let extend = toggle || state.contains(ModifierType::SHIFT_MASK);
+                let mut mode = drag_mode.borrow_mut();
+                *mode = DragMode::Idle;
+                if x < view.label_area_width {
+                    return;
+                }
+                if y < view.layers_top() {
+                    // Ruler
+                    transport.borrow_mut().seek(view.x_to_frame(x));
+                    *mode = DragMode::Seek;
+                    refresh.playhead();
+                    return;
+                }
 
-        let playhead_position_for_update = playhead_position.clone();
-        let drag_offset_for_update = drag_offset.clone();
-        let drawing_area_for_update = draw_area.clone();
-
-        drag.connect_drag_update(move |_, offset_x, offset_y| {
-            let dx = offset_x - drag_offset_for_update.borrow().0;
-            let dy = offset_y - drag_offset_for_update.borrow().1;
-            *playhead_position_for_update.borrow_mut() = (dx, dy);
-            drawing_area_for_update.queue_draw();
-            // println!(
-            //     "playhead_position: {}",
-            //     playhead_position_for_update.borrow().0
-            // ) //end_xは移動距離だった
-        });
+                let project = project.borrow();
+                let mut selection = selection.borrow_mut();
+                match view.hit_test(&project.timeline, x, y) {
+                    Some((id, HitPart::Body)) if toggle && selection.contains(&id) => {
+                        selection.retain(|s| *s != id);
+                    }
+                    Some((id, part)) => {
+                        if !selection.contains(&id) {
+                            if !extend {
+                                selection.clear();
+                            }
+                            selection.push(id);
+                        }
+                        let clip = project.timeline.clip(id).unwrap();
+                        if project.timeline.layers[clip.layer].locked {
+                            refresh.timeline();
+                            refresh.controls();
+                            return;
+                        }
+                        *mode = match part {
+                            HitPart::Body => DragMode::Move {
+                                origins: selection
+                                    .iter()
+                                    .filter_map(|id| project.timeline.clip(*id))
+                                    .map(|c| (c.id, c.layer, c.start))
+                                    .collect(),
+                            },
+                            edge => DragMode::Trim {
+                                id,
+                                edge,
+                                start: clip.start,
+                                length: clip.length,
+                            },
+                        };
+                        history.borrow_mut().begin_group();
+                    }
+                    None => {
+                        if !extend {
+                            selection.clear();
+                        }
+                        *mode = DragMode::Select {
+                            base: selection.clone(),
+                            rect: (x, y, x, y),
+                        };
+                    }
+                }
+                refresh.timeline();
+                refresh.controls();
+            });
+        }
+        {
+            let (project, history, view) = (project.clone(), history.clone(), view.clone());
+            let (transport, selection) = (transport.clone(), selection.clone());
+            let (snap_enabled, snap_line) = (snap_enabled.clone(), snap_line.clone());
+            let drag_mode = drag_mode.clone();
+            let refresh = refresh.clone();
+            drag.connect_drag_update(move |gesture, dx, dy| {
+                let Some((start_x, start_y)) = gesture.start_point() else {
+                    return;
+                };
+                let (x, y) = (start_x + dx, start_y + dy);
+                let view = view.borrow();
+                let mut frames = (dx / view.pixels_per_frame).round() as Frame;
+                let layers = (dy / view.layer_height).round() as i64;
+                let mut project = project.borrow_mut();
+                let rate = project.output.frame_rate;
+                let timeline = &mut project.timeline;
+
+                // Alt turns snapping off while held
+                let snapping = *snap_enabled.borrow()
+                    && !gesture
+                        .current_event_state()
+                        .contains(ModifierType::ALT_MASK);
+                let threshold = snap::SNAP_DISTANCE / view.pixels_per_frame;
+                let playhead = transport.borrow().playhead;
+                let mut snapped = None;
+
+                match &mut *drag_mode.borrow_mut() {
+                    DragMode::Idle => return,
+                    DragMode::Seek => transport.borrow_mut().seek(view.x_to_frame(x)),
+                    DragMode::Move { origins } => {
+                        if snapping {
+                            let ids: Vec<ClipId> = origins.iter().map(|o| o.0).collect();
+                            let snapper = Snapper::new(timeline, playhead, rate, &ids);
+                            let edges: Vec<Frame> = origins
+                                .iter()
+                                .filter_map(|(id, _, start)| {
+                                    let clip = timeline.clip(*id)?;
+                                    Some([start + frames, start + frames + clip.length])
+                                })
+                                .flatten()
+                                .collect();
+                            if let Some((offset, target)) = snapper.snap_edges(&edges, threshold) {
+                                frames += offset;
+                                snapped = Some(target);
+                            }
+                        }
+                        if let Some(edit) = drag::move_edit(timeline, origins, frames, layers) {
+                            history.borrow_mut().perform(edit, timeline);
+                        }
+                    }
+                    DragMode::Trim {
+                        id,
+                        edge,
+                        start,
+                        length,
+                    } => {
+                        if snapping {
+                            let snapper = Snapper::new(timeline, playhead, rate, &[*id]);
+                            let edge_frame = match edge {
+                                HitPart::LeftEdge => *start + frames,
+                                _ => *start + *length + frames,
+                            };
+                            if let Some(target) = snapper.snap(edge_frame, threshold) {
+                                frames += target - edge_frame;
+                                snapped = Some(target);
+                            }
+                        }
+                        if let Some(edit) =
+                            drag::trim_edit(timeline, *id, *edge, *start, *length, frames)
+                        {
+                            history.borrow_mut().perform(edit, timeline);
+                        }
+                    }
+                    DragMode::Select { base, rect } => {
+                        *rect = (start_x, start_y, x, y);
+                        let frames = (
+                            view.x_to_frame(start_x.min(x)),
+                            view.x_to_frame(start_x.max(x)),
+                        );
+                        let top = start_y.min(y).max(view.layers_top());
+                        let layers = (
+                            view.y_to_layer(top).unwrap_or(0),
+                            view.y_to_layer(start_y.max(y)).unwrap_or(0),
+                        );
+                        let mut selected = base.clone();
+                        for id in drag::clips_in_range(timeline, frames, layers) {
+                            if !selected.contains(&id) {
+                                selected.push(id);
+                            }
+                        }
+                        *selection.borrow_mut() = selected;
+                    }
+                }
+                *snap_line.borrow_mut() = snapped;
+                match *drag_mode.borrow() {
+                    DragMode::Seek => refresh.playhead(),
+                    DragMode::Select { .. } => {
+                        refresh.timeline();
+                        refresh.controls();
+                    }
+                    _ => refresh.project(),
+                }
+            });
+        }
+        {
+            let (history, view, transport) = (history.clone(), view.clone(), transport.clone());
+            let (drag_mode, snap_line) = (drag_mode.clone(), snap_line.clone());
+            let refresh = refresh.clone();
+            drag.connect_drag_end(move |gesture, dx, dy| {
+                let mode = std::mem::take(&mut *drag_mode.borrow_mut());
+                *snap_line.borrow_mut() = None;
+                // A click (no drag) on empty space moves the playhead there
+                if let (DragMode::Select { .. }, Some((x, _))) = (&mode, gesture.start_point())
+                    && dx.abs() < 3.0
+                    && dy.abs() < 3.0
+                {
+                    transport.borrow_mut().seek(view.borrow().x_to_frame(x));
+                }
+                history.borrow_mut().end_group();
+                refresh.project();
+            });
+        }
         draw_area.add_controller(drag);
 
-        // Detect right click
-        let click = GestureClick::builder().button(3).build(); //left:1 center:2 right:3
-        click.connect_pressed(move |_, n_press, x, y| {
-            println!("右クリック検出: ({}, {}) クリック回数: {}", x, y, n_press);
-        });
-        click.connect_released(move |_, n_press, x, y| {
-            println!(
-                "右クリックが離された！ 座標: ({}, {}), クリック回数: {}",
-                x, y, n_press
-            );
-        });
-        draw_area.add_controller(click);
-
-        // Header
-        let header_box = GtkBox::new(Orientation::Horizontal, 6);
-        header_box.set_halign(Align::Start);
-
-        let file_label = Label::new(Some("ファイル"));
-        //file_label.set_margin_start(12); // left space
-        file_label.set_focusable(true);
-        file_label.add_css_class("header-label");
-
-        let filter_label = Label::new(Some("フィルタ"));
-        filter_label.set_focusable(true);
-        filter_label.add_css_class("header-label");
-
-        let setting_label = Label::new(Some("設定"));
-        setting_label.set_focusable(true);
-        setting_label.add_css_class("header-label");
-
-        let edit_label = Label::new(Some("編集"));
-        edit_label.set_focusable(true);
-        edit_label.add_css_class("header-label");
-
-        let profile_label = Label::new(Some("プロファイル"));
-        profile_label.set_focusable(true);
-        profile_label.add_css_class("header-label");
-
-        let show_label = Label::new(Some("表示"));
-        show_label.set_focusable(true);
-        show_label.add_css_class("header-label");
+        // Drop files from the file manager onto a layer
+        let drop_target = DropTarget::new(FileList::static_type(), DragAction::COPY);
+        drop_target.set_preload(true);
+        {
+            let (project, view, drop_ghost) = (project.clone(), view.clone(), drop_ghost.clone());
+            let refresh = refresh.clone();
+            drop_target.connect_motion(move |target, x, y| {
+                let view = view.borrow();
+                let count = target
+                    .value()
+                    .and_then(|value| value.get::<FileList>().ok())
+                    .map_or(1, |files| files.files().len());
+                let layer_count = project.borrow().timeline.layers.len();
+                *drop_ghost.borrow_mut() = drop_layer(&view, y, layer_count)
+                    .map(|layer| (view.x_to_frame(x), layer, count));
+                refresh.timeline();
+                DragAction::COPY
+            });
+        }
+        {
+            let (drop_ghost, refresh) = (drop_ghost.clone(), refresh.clone());
+            drop_target.connect_leave(move |_| {
+                *drop_ghost.borrow_mut() = None;
+                refresh.timeline();
+            });
+        }
+        {
+            let (project, history, view) = (project.clone(), history.clone(), view.clone());
+            let (import_options, drop_ghost) = (import_options.clone(), drop_ghost.clone());
+            let refresh = refresh.clone();
+            drop_target.connect_drop(move |_, value, x, y| {
+                *drop_ghost.borrow_mut() = None;
+                refresh.timeline();
+                let Ok(files) = value.get::<FileList>() else {
+                    return false;
+                };
+                let view = view.borrow();
+                let layer_count = project.borrow().timeline.layers.len();
+                let Some(layer) = drop_layer(&view, y, layer_count) else {
+                    return false;
+                };
+                let paths: Vec<PathBuf> = files
+                    .files()
+                    .iter()
+                    .filter_map(|file| file.path())
+                    .collect();
+                let added = import::import_files(
+                    &mut project.borrow_mut(),
+                    &mut history.borrow_mut(),
+                    &paths,
+                    view.x_to_frame(x),
+                    Some(layer),
+                    &import_options.borrow(),
+                );
+                refresh.project();
+                !added.is_empty()
+            });
+        }
+        draw_area.add_controller(drop_target);
 
-        let other_label = Label::new(Some("その他"));
-        other_label.set_focusable(true);
-        other_label.add_css_class("header-label");
+        // Click the ruler's label column to switch frames / timecode / seconds
+        let ruler_click = GestureClick::builder().button(1).build();
+        {
+            let time_format = time_format.clone();
+            let view = view.clone();
+            let refresh = refresh.clone();
+            ruler_click.connect_pressed(move |_, _, x, y| {
+                let view = view.borrow();
+                if x < view.label_area_width && (view.ruler_y..view.layers_top()).contains(&y) {
+                    let next = time_format.borrow().next();
+                    *time_format.borrow_mut() = next;
+                    refresh.timeline();
+                    refresh.controls();
+                }
+            });
+        }
+        draw_area.add_controller(ruler_click);
+
+        // Right click on the timeline: menu depending on what is under the pointer
+        connect_context_menu(
+            &timeline_group,
+            project.clone(),
+            selection.clone(),
+            view.clone(),
+            context_pointer.clone(),
+            &draw_area,
+            refresh.clone(),
+        );
 
-        let is_clicked = Rc::new(RefCell::new(false));
+        // Playback driven by the frame clock
+        {
+            let (project, transport) = (project.clone(), transport.clone());
+            let refresh = refresh.clone();
+            draw_area.add_tick_callback(move |_, clock| {
+                let project = project.borrow();
+                let end = project.timeline.end_frame();
+                if transport
+                    .borrow_mut()
+                    .tick(clock.frame_time(), project.output.frame_rate, end)
+                {
+                    refresh.playhead();
+                }
+                ControlFlow::Continue
+            });
+        }
 
-        // Detect mouseover
-        let motion_controller = EventControllerMotion::new();
-        let header_label_for_hover = file_label.clone();
+        // Playback ("transport.") actions; keys come from the keymap like every other action
+        let transport_group =
+            transport_actions(project.clone(), transport.clone(), refresh.clone());
+        window.insert_action_group("transport", Some(&transport_group));
 
-        let is_clicked_clone = is_clicked.clone();
-        motion_controller.connect_enter(move |_, _x, _y| {
-            if !*is_clicked_clone.borrow() {
-                header_label_for_hover.add_css_class("header-label-hover");
-            }
-        });
-        let file_label_for_leave = file_label.clone();
-        let is_clicked_for_leave = is_clicked.clone();
+        // ファイル menu actions
+        let open_media_action = gio::SimpleAction::new("open-media", None);
+        {
+            let window_for_action = window.clone();
+            let (project, history) = (project.clone(), history.clone());
+            let (transport, import_options) = (transport.clone(), import_options.clone());
+            let refresh = refresh.clone();
+            open_media_action.connect_activate(move |_, _| {
+                open_media(
+                    &window_for_action,
+                    None,
+                    project.clone(),
+                    history.clone(),
+                    transport.clone(),
+                    import_options.clone(),
+                    refresh.clone(),
+                );
+            });
+        }
+        window.add_action(&open_media_action);
 
-        motion_controller.connect_leave(move |_| {
-            if !*is_clicked_for_leave.borrow() {
-                file_label_for_leave.remove_css_class("header-label-hover");
-            }
-        });
+        let close_project_action = gio::SimpleAction::new("close-project", None);
+        {
+            let (project, project_path) = (project.clone(), project_path.clone());
+            let (history, selection) = (history.clone(), selection.clone());
+            let (settings, refresh) = (settings.clone(), refresh.clone());
+            close_project_action.connect_activate(move |_, _| {
+                close_project(
+                    &project,
+                    &project_path,
+                    &history,
+                    &selection,
+                    &settings.borrow(),
+                    &refresh,
+                );
+            });
+        }
+        window.add_action(&close_project_action);
 
-        file_label.add_controller(motion_controller);
+        let open_project_action = gio::SimpleAction::new("open-project", None);
+        {
+            let window_for_action = window.clone();
+            let (project, project_path) = (project.clone(), project_path.clone());
+            let (history, selection) = (history.clone(), selection.clone());
+            let refresh = refresh.clone();
+            open_project_action.connect_activate(move |_, _| {
+                open_project(
+                    &window_for_action,
+                    project.clone(),
+                    project_path.clone(),
+                    history.clone(),
+                    selection.clone(),
+                    refresh.clone(),
+                );
+            });
+        }
+        window.add_action(&open_project_action);
 
-        // When header label clicked
-        let gesture = GestureClick::builder().button(1).build();
-        let file_label_for_click = file_label.clone();
-        let is_clicked_for_click = is_clicked.clone();
+        // Exports queued from the export dialog; they render on a worker thread
+        // while editing goes on (see render_queue)
+        let render_queue = Rc::new(RefCell::new(RenderQueue::load_user()));
+        let queue_window = QueueWindow::new(&window, render_queue.clone());
+        {
+            let (app, queue_window) = (app.clone(), queue_window.clone());
+            glib::timeout_add_local(Duration::from_millis(200), move || {
+                let update = render_queue.borrow_mut().poll();
+                match update {
+                    Update::None => {}
+                    Update::Changed => queue_window.refresh(),
+                    Update::Finished { done, failed } => {
+                        queue_window.refresh();
+                        let notification = gio::Notification::new("レンダーキューが完了しました");
+                        notification.set_body(Some(&if failed > 0 {
+                            format!("{} 件完了、{} 件失敗", done, failed)
+                        } else {
+                            format!("{} 件の書き出しが完了しました", done)
+                        }));
+                        app.send_notification(Some("render-queue"), &notification);
+                    }
+                }
+                ControlFlow::Continue
+            });
+        }
 
-        let show_rect_click = show_rect.clone(); ////
-        let draw_area_for_click = draw_area.clone(); ////
+        for (name, queue_first) in [("export", false), ("queue-export", true)] {
+            let export_action = gio::SimpleAction::new(name, None);
+            let window_for_action = window.clone();
+            let (project, project_path) = (project.clone(), project_path.clone());
+            let (selection, queue_window) = (selection.clone(), queue_window.clone());
+            export_action.connect_activate(move |_, _| {
+                // Next to the project file, or the home folder
+                let dir = project_path
+                    .borrow()
+                    .as_ref()
+                    .and_then(|p| p.parent().map(PathBuf::from))
+                    .unwrap_or_else(glib::home_dir);
+                export_dialog::show(
+                    &window_for_action,
+                    &project.borrow(),
+                    &selection.borrow(),
+                    dir,
+                    queue_window.clone(),
+                    queue_first,
+                );
+            });
+            window.add_action(&export_action);
+        }
 
-        gesture.connect_pressed(move |_, _, _, _| {
-            let mut clicked = is_clicked_for_click.borrow_mut();
-            *clicked = !*clicked;
+        let render_queue_action = gio::SimpleAction::new("render-queue", None);
+        render_queue_action.connect_activate(move |_, _| queue_window.present());
+        window.add_action(&render_queue_action);
 
-            if *clicked {
-                file_label_for_click.remove_css_class("header-label-hover");
-                file_label_for_click.add_css_class("header-label-clicked");
-            } else {
-                file_label_for_click.remove_css_class("header-label-clicked");
-            }
+        let save_project_action = gio::SimpleAction::new("save-project", None);
+        {
+            let window_for_action = window.clone();
+            let (project, project_path) = (project.clone(), project_path.clone());
+            save_project_action.connect_activate(move |_, _| {
+                save_project(&window_for_action, project.clone(), project_path.clone());
+            });
+        }
+        window.add_action(&save_project_action);
 
-            *show_rect_click.borrow_mut() = true; // ← 表示フラグON
-            draw_area_for_click.queue_draw(); // ← 再描画
-        });
+        let about_action = gio::SimpleAction::new("about", None);
+        {
+            let window_for_action = window.clone();
+            about_action.connect_activate(move |_, _| {
+                gtk4::AboutDialog::builder()
+                    .transient_for(&window_for_action)
+                    .modal(true)
+                    .program_name("Luvita")
+                    .version(env!("CARGO_PKG_VERSION"))
+                    .build()
+                    .present();
+            });
+        }
+        window.add_action(&about_action);
 
-        file_label.add_controller(gesture);
-        //header_box.append(&file_label);
+        // Connected below, once the layout it saves exists
+        let quit_action = gio::SimpleAction::new("quit", None);
 
-        // Detect clicks elsewhere
-        let file_label_for_outside = file_label.clone();
-        let is_clicked_for_outside = is_clicked.clone();
+        let shortcuts_action = gio::SimpleAction::new("shortcuts", None);
+        {
+            let window_for_action = window.clone();
+            let (app, keymap) = (app.clone(), keymap.clone());
+            shortcuts_action.connect_activate(move |_, _| {
+                shortcut_editor::show(&window_for_action, app.upcast_ref(), keymap.clone());
+            });
+        }
+        window.add_action(&shortcuts_action);
 
-        // 既存の変数をクローンして使う
-        let show_rect_for_outside = show_rect.clone();
-        let draw_area_for_outside = draw_area.clone();
+        let preferences_action = gio::SimpleAction::new("preferences", None);
+        {
+            let window_for_action = window.clone();
+            let settings = settings.clone();
+            // Settings that can change while running; the window layout waits for a restart
+            let changed: Rc<dyn Fn(&Settings)> = {
+                let (view, history) = (view.clone(), history.clone());
+                let (renderer, import_options) = (renderer.clone(), import_options.clone());
+                let refresh = refresh.clone();
+                Rc::new(move |settings: &Settings| {
+                    view.borrow_mut().layer_height = settings.layer_height;
+                    history.borrow_mut().set_limit(settings.history_limit);
+                    renderer
+                        .borrow_mut()
+                        .set_cache_limit(settings.image_cache_bytes());
+                    *import_options.borrow_mut() = settings.import_options();
+                    refresh.project();
+                })
+            };
+            preferences_action.connect_activate(move |_, _| {
+                preferences::show(&window_for_action, settings.clone(), changed.clone());
+            });
+        }
+        window.add_action(&preferences_action);
 
-        let global_click = GestureClick::builder().button(1).build();
-        global_click.connect_pressed(move |_, _, _, _| {
-            if *is_clicked_for_outside.borrow() {
-                file_label_for_outside.remove_css_class("header-label-clicked");
-                *is_clicked_for_outside.borrow_mut() = false;
-            }
+        // Autosave: checked every minute against the interval in the settings
+        {
+            let (settings, project, project_path) =
+                (settings.clone(), project.clone(), project_path.clone());
+            let mut minutes = 0;
+            let mut last = String::new();
+            glib::timeout_add_seconds_local(60, move || {
+                minutes += 1;
+                let interval = settings.borrow().autosave_minutes;
+                if interval > 0 && minutes >= interval {
+                    minutes = 0;
+                    autosave(&project.borrow(), project_path.borrow().as_ref(), &mut last);
+                }
+                ControlFlow::Continue
+            });
+        }
 
-            *show_rect_for_outside.borrow_mut() = false; ////
-            draw_area_for_outside.queue_draw(); ////
-        });
-        window.add_controller(global_click);
+        let palette_action = gio::SimpleAction::new("command-palette", None);
+        {
+            let window_for_action = window.clone();
+            let (keymap, project, recent) =
+                (keymap.clone(), project.clone(), recent_commands.clone());
+            palette_action.connect_activate(move |_, _| {
+                command_palette::show(
+                    &window_for_action,
+                    keymap.clone(),
+                    project.clone(),
+                    recent.clone(),
+                );
+            });
+        }
+        window.add_action(&palette_action);
 
-        apply_hover_effects(&file_label, is_clicked.clone());
-        apply_hover_effects(&filter_label, Rc::new(RefCell::new(false)));
-        apply_hover_effects(&setting_label, Rc::new(RefCell::new(false)));
-        apply_hover_effects(&edit_label, Rc::new(RefCell::new(false)));
-        apply_hover_effects(&profile_label, Rc::new(RefCell::new(false)));
-        apply_hover_effects(&show_label, Rc::new(RefCell::new(false)));
-        apply_hover_effects(&other_label, Rc::new(RefCell::new(false)));
+        // Shortcuts come from the user's keymap (設定 / キーボードショートカット)
+        shortcut_editor::apply_keymap(app.upcast_ref(), &keymap.borrow());
 
         // Icon
         let icon = Image::from_file("bitmap.png");
@@ -514,11 +2508,6 @@ fn main() {
         icon.set_margin_start(4);
         icon.set_margin_end(4);
 
-        let header = HeaderBar::builder()
-            .title_widget(&header_box)
-            .show_end_title_buttons(true)
-            .build();
-
         // Include icon in binary
         let loader = PixbufLoader::new();
         loader.write(ICON_DATA).unwrap();
@@ -527,59 +2516,90 @@ fn main() {
         let logo = Image::from_pixbuf(Some(&pixbuf));
         logo.set_pixel_size(24);
 
-        // Set menu button in header
+        // Menu bar in the header, title hidden
+        let menubar = PopoverMenuBar::from_model(Some(&menubar_model));
+        let header = HeaderBar::builder()
+            .title_widget(&GtkBox::new(Orientation::Horizontal, 0))
+            .show_end_title_buttons(true)
+            .build();
         header.pack_start(&icon);
-        header.pack_start(&file_label);
-        header.pack_start(&filter_label);
-        header.pack_start(&setting_label);
-        header.pack_start(&edit_label);
-        header.pack_start(&profile_label);
-        header.pack_start(&show_label);
-        header.pack_start(&other_label);
-
-        // menu button
-        let overlay = Overlay::new();
-
-        let label = Label::new(Some("開く"));
-        label.set_halign(gtk4::Align::Start);
-        label.set_valign(gtk4::Align::Start);
-        label.set_css_classes(&["menu-button"]);
-
-        let label1 = Label::new(Some("閉じる"));
-        label1.set_halign(gtk4::Align::Start);
-        label1.set_valign(gtk4::Align::Start);
-        label1.set_margin_top(28);
-        label1.set_css_classes(&["menu-button"]);
-
-        let label2 = Label::new(Some("プロジェクトを開く"));
-        label2.set_halign(gtk4::Align::Start);
-        label2.set_valign(gtk4::Align::Start);
-        label2.set_margin_top(28 * 2);
-        label2.set_css_classes(&["menu-button"]);
-
-        let label3 = Label::new(Some("終了"));
-        label3.set_halign(gtk4::Align::Start);
-        label3.set_valign(gtk4::Align::Start);
-        label3.set_margin_top(28 * 3);
-        label3.set_css_classes(&["menu-button"]);
-
-        apply_label_hover(&label, "menu-button-hover");
-        apply_label_hover(&label1, "menu-button-hover");
-        apply_label_hover(&label2, "menu-button-hover");
-        apply_label_hover(&label3, "menu-button-hover");
-
-        // Add button to overlay
-        overlay.set_child(Some(&draw_area));
-        overlay.add_overlay(&label);
-        overlay.add_overlay(&label1);
-        overlay.add_overlay(&label2);
-        overlay.add_overlay(&label3);
-
-        // Assemble the UI
+        header.pack_start(&menubar);
+
+        // Assemble the UI: preview | inspector on top, timeline below
+        let inspector = GtkBox::new(Orientation::Vertical, 0);
+        let top = Paned::builder()
+            .orientation(Orientation::Horizontal)
+            .start_child(&preview_area)
+            .end_child(&inspector)
+            .shrink_start_child(false)
+            .build();
+        let timeline_box = GtkBox::new(Orientation::Vertical, 0);
+        timeline_box.append(&draw_area);
+        timeline_box.append(&hscrollbar);
+        let split = Paned::builder()
+            .orientation(Orientation::Vertical)
+            .start_child(&top)
+            .end_child(&timeline_box)
+            .shrink_start_child(false)
+            .shrink_end_child(false)
+            .vexpand(true)
+            .build();
+
+        // Paned positions are in pixels; restore the saved ratios once the
+        // window has its size
+        {
+            let (split, top) = (split.clone(), top.clone());
+            let (preview_ratio, inspector_ratio) = {
+                let settings = settings.borrow();
+                (settings.preview_ratio, settings.inspector_ratio)
+            };
+            window.add_tick_callback(move |_, _| {
+                if split.height() <= 0 || top.width() <= 0 {
+                    return ControlFlow::Continue;
+                }
+                split.set_position((split.height() as f64 * preview_ratio).round() as i32);
+                top.set_position((top.width() as f64 * inspector_ratio).round() as i32);
+                ControlFlow::Break
+            });
+        }
+
+        // Window size and splits are saved when the window closes or the app quits
+        let save_layout: Rc<dyn Fn()> = {
+            let window = window.clone();
+            let (settings, split, top) = (settings.clone(), split.clone(), top.clone());
+            Rc::new(move || {
+                let mut settings = settings.borrow_mut();
+                settings.window_maximized = window.is_maximized();
+                if !window.is_maximized() {
+                    (settings.window_width, settings.window_height) = window.default_size();
+                }
+                if split.height() > 0 && top.width() > 0 {
+                    settings.preview_ratio = split.position() as f64 / split.height() as f64;
+                    settings.inspector_ratio = top.position() as f64 / top.width() as f64;
+                }
+                settings.validate();
+                settings.save_user();
+            })
+        };
+        {
+            let save_layout = save_layout.clone();
+            window.connect_close_request(move |_| {
+                save_layout();
+                glib::Propagation::Proceed
+            });
+        }
+        {
+            let app = app.clone();
+            quit_action.connect_activate(move |_, _| {
+                save_layout();
+                app.quit();
+            });
+        }
+        app.add_action(&quit_action);
+
         let vbox = GtkBox::new(Orientation::Vertical, 0);
         vbox.append(&header);
-        vbox.append(&draw_area);
-        vbox.append(&overlay);
+        vbox.append(&split);
         window.set_content(Some(&vbox));
         window.present();
     });
@@ -0,0 +1,197 @@
+// Command palette (Ctrl+Shift+P): candidates and fuzzy matching
+
+use crate::actions::{ACTIONS, Keymap};
+use crate::project::FrameRate;
+use crate::ruler::{self, TimeFormat};
+use crate::timeline::{FilterKind, Frame, Marker};
+
+const MAX_RECENT: usize = 10;
+// The palette itself is not worth listing
+const PALETTE_ACTION: &str = "win.command-palette";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Param {
+    None,
+    Str(&'static str),
+    Frame(Frame),
+}
+
+#[derive(Clone, Debug)]
+pub struct PaletteItem {
+    pub title: String,
+    // Detailed action name, activated on the main window
+    pub action: String,
+    pub param: Param,
+    pub accels: Vec<String>,
+}
+
+impl PaletteItem {
+    fn new(title: String, action: &str, param: Param) -> Self {
+        Self {
+            title,
+            action: action.to_string(),
+            param,
+            accels: Vec::new(),
+        }
+    }
+
+    // Identifies the command in the recent list
+    pub fn id(&self) -> String {
+        match &self.param {
+            Param::None => self.action.clone(),
+            Param::Str(s) => format!("{}::{}", self.action, s),
+            Param::Frame(f) => format!("{}::{}", self.action, f),
+        }
+    }
+}
+
+// Most recently run first
+#[derive(Default)]
+pub struct Recent {
+    ids: Vec<String>,
+}
+
+impl Recent {
+    pub fn push(&mut self, id: String) {
+        self.ids.retain(|i| *i != id);
+        self.ids.insert(0, id);
+        self.ids.truncate(MAX_RECENT);
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.ids.iter().position(|i| i == id)
+    }
+}
+
+// Characters of `query` must appear in `text` in order. Consecutive matches and
+// matches at the start score higher. None if it doesn't match.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + text[position..].iter().position(|&c| c == q)?;
+        score += match previous {
+            Some(p) if p + 1 == found => 8,
+            _ if found == 0 => 6,
+            _ if text[found - 1] == ' ' || text[found - 1] == '.' => 4,
+            _ => 1,
+        };
+        // Gaps cost a little so tighter matches win
+        score -= (found - position).min(5) as i64;
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+// "120" (frames), "4.5s" (seconds) or "00:01:02:03" / "1:02" (timecode)
+pub fn parse_time(query: &str, rate: FrameRate) -> Option<Frame> {
+    let query = query.trim();
+    if let Ok(frame) = query.parse::<Frame>() {
+        return (frame >= 0).then_some(frame);
+    }
+    if let Some(seconds) = query.strip_suffix('s') {
+        let seconds = seconds.trim().parse::<f64>().ok()?;
+        return (seconds >= 0.0).then(|| rate.seconds_to_frame(seconds));
+    }
+    if query.contains(':') {
+        let parts: Vec<Frame> = query
+            .split([':', ';'])
+            .map(|p| p.parse::<Frame>().ok().filter(|n| *n >= 0))
+            .collect::<Option<_>>()?;
+        let fps = ruler::nominal_fps(rate);
+        let (h, m, s, f) = match parts[..] {
+            [m, s] => (0, m, s, 0),
+            [h, m, s] => (h, m, s, 0),
+            [h, m, s, f] => (h, m, s, f),
+            _ => return None,
+        };
+        return Some(((h * 60 + m) * 60 + s) * fps + f);
+    }
+    None
+}
+
+// Every registered action, plus jumps to markers and filter commands
+pub fn items(keymap: &Keymap, markers: &[Marker], rate: FrameRate) -> Vec<PaletteItem> {
+    let mut items: Vec<PaletteItem> = ACTIONS
+        .iter()
+        .filter(|a| a.name != PALETTE_ACTION)
+        .map(|a| PaletteItem {
+            accels: keymap.accels(a.name).to_vec(),
+            ..PaletteItem::new(format!("{}: {}", a.category, a.label), a.name, Param::None)
+        })
+        .collect();
+    for marker in markers {
+        items.push(PaletteItem::new(
+            format!(
+                "マーカーへ移動: {} ({})",
+                marker.name,
+                ruler::format_time(marker.frame, rate, TimeFormat::Timecode)
+            ),
+            "transport.seek",
+            Param::Frame(marker.frame),
+        ));
+    }
+    for filter in FilterKind::ALL {
+        items.push(PaletteItem::new(
+            format!("選択オブジェクトにフィルタを追加: {}", filter.name()),
+            "timeline.add-clip-filter",
+            Param::Str(filter.key()),
+        ));
+        items.push(PaletteItem::new(
+            format!("フィルタオブジェクトを追加: {}", filter.name()),
+            "timeline.add-filter",
+            Param::Str(filter.key()),
+        ));
+    }
+    items
+}
+
+// Matching items, best first. Recent commands come first when the query is
+// empty and get a boost otherwise. A query that reads as a time adds a
+// "go to frame" entry on top.
+pub fn rank(
+    items: Vec<PaletteItem>,
+    query: &str,
+    recent: &Recent,
+    rate: FrameRate,
+) -> Vec<PaletteItem> {
+    let mut scored: Vec<(i64, usize, PaletteItem)> = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            // Also match the action name so English words work
+            let score = fuzzy_score(query, &item.title).max(fuzzy_score(query, &item.action))?;
+            let boost = recent
+                .position(&item.id())
+                .map_or(0, |p| (MAX_RECENT - p) as i64 * 3);
+            Some((score + boost, index, item))
+        })
+        .collect();
+    if query.trim().is_empty() {
+        scored.sort_by_key(|(_, index, item)| {
+            (recent.position(&item.id()).unwrap_or(MAX_RECENT), *index)
+        });
+    } else {
+        scored.sort_by_key(|(score, index, _)| (-score, *index));
+    }
+
+    let mut ranked: Vec<PaletteItem> = scored.into_iter().map(|(_, _, item)| item).collect();
+    if let Some(frame) = parse_time(query, rate) {
+        ranked.insert(
+            0,
+            PaletteItem::new(
+                format!(
+                    "フレームへ移動: {} ({})",
+                    frame,
+                    ruler::format_time(frame, rate, TimeFormat::Timecode)
+                ),
+                "transport.seek",
+                Param::Frame(frame),
+            ),
+        );
+    }
+    ranked
+}
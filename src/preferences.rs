@@ -0,0 +1,227 @@
+// 設定 / 環境設定: edit the Settings; every change is saved and applied right away
+
+use crate::config::ConfigFile;
+use crate::profile;
+use crate::settings::{self, Settings};
+use gtk4::prelude::*;
+use gtk4::{Align, SpinButton, StringList};
+use libadwaita::prelude::*;
+use libadwaita::{ActionRow, ComboRow, PreferencesGroup, PreferencesPage, PreferencesWindow};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Change = Rc<dyn Fn(&mut Settings)>;
+
+// A row with a SpinButton. `set` stores the value; the result is validated,
+// saved and handed to `changed`.
+#[allow(clippy::too_many_arguments)]
+fn spin_row(
+    group: &PreferencesGroup,
+    title: &str,
+    subtitle: &str,
+    (min, max): (f64, f64),
+    step: f64,
+    digits: u32,
+    value: f64,
+    commit: &Rc<dyn Fn(Change)>,
+    set: impl Fn(&mut Settings, f64) + 'static,
+) {
+    let spin = SpinButton::with_range(min, max, step);
+    spin.set_digits(digits);
+    spin.set_value(value);
+    spin.set_valign(Align::Center);
+    let row = ActionRow::builder().title(title).subtitle(subtitle).build();
+    row.add_suffix(&spin);
+    row.set_activatable_widget(Some(&spin));
+    group.add(&row);
+
+    let (commit, set) = (commit.clone(), Rc::new(set));
+    spin.connect_value_changed(move |spin| {
+        let (set, value) = (set.clone(), spin.value());
+        commit(Rc::new(move |s| set(s, value)));
+    });
+}
+
+pub fn show(
+    parent: &impl IsA<gtk4::Window>,
+    settings: Rc<RefCell<Settings>>,
+    changed: Rc<dyn Fn(&Settings)>,
+) {
+    let window = PreferencesWindow::builder()
+        .title("環境設定")
+        .transient_for(parent)
+        .modal(true)
+        .default_width(560)
+        .default_height(640)
+        .build();
+
+    let commit: Rc<dyn Fn(Change)> = {
+        let settings = settings.clone();
+        Rc::new(move |change: Change| {
+            let mut settings = settings.borrow_mut();
+            change(&mut settings);
+            settings.validate();
+            settings.save_user();
+            changed(&settings);
+        })
+    };
+    let current = settings.borrow().clone();
+
+    // 表示
+    let layout_page = PreferencesPage::builder()
+        .title("表示")
+        .icon_name("preferences-desktop-display-symbolic")
+        .build();
+    let timeline_group = PreferencesGroup::builder().title("タイムライン").build();
+    spin_row(
+        &timeline_group,
+        "レイヤーの高さ",
+        "1 レイヤーの行の高さ (px)",
+        settings::LAYER_HEIGHT,
+        1.0,
+        0,
+        current.layer_height,
+        &commit,
+        |s, v| s.layer_height = v,
+    );
+    layout_page.add(&timeline_group);
+    window.add(&layout_page);
+
+    // 編集
+    let edit_page = PreferencesPage::builder()
+        .title("編集")
+        .icon_name("document-edit-symbolic")
+        .build();
+    let length_group = PreferencesGroup::builder()
+        .title("オブジェクトの長さ")
+        .build();
+    spin_row(
+        &length_group,
+        "画像",
+        "読み込んだ画像の長さ (秒)",
+        settings::OBJECT_SECONDS,
+        0.5,
+        1,
+        current.image_seconds,
+        &commit,
+        |s, v| s.image_seconds = v,
+    );
+    spin_row(
+        &length_group,
+        "テキスト・図形・フィルタ",
+        "メニューから追加したオブジェクトの長さ (秒)",
+        settings::OBJECT_SECONDS,
+        0.5,
+        1,
+        current.object_seconds,
+        &commit,
+        |s, v| s.object_seconds = v,
+    );
+    edit_page.add(&length_group);
+    let save_group = PreferencesGroup::builder().title("保存").build();
+    spin_row(
+        &save_group,
+        "自動保存の間隔",
+        "分 (0 で無効)。キャッシュディレクトリに保存されます",
+        settings::AUTOSAVE_MINUTES,
+        1.0,
+        0,
+        current.autosave_minutes as f64,
+        &commit,
+        |s, v| s.autosave_minutes = v as u32,
+    );
+    edit_page.add(&save_group);
+    window.add(&edit_page);
+
+    // キャッシュ
+    let cache_page = PreferencesPage::builder()
+        .title("キャッシュ")
+        .icon_name("drive-harddisk-symbolic")
+        .build();
+    let cache_group = PreferencesGroup::builder().title("メモリ").build();
+    spin_row(
+        &cache_group,
+        "画像キャッシュ",
+        "デコード済みの画像を保持する量 (MB)",
+        settings::IMAGE_CACHE_MB,
+        16.0,
+        0,
+        current.image_cache_mb as f64,
+        &commit,
+        |s, v| s.image_cache_mb = v as u32,
+    );
+    spin_row(
+        &cache_group,
+        "元に戻す回数",
+        "履歴に残す編集の数",
+        settings::HISTORY_LIMIT,
+        10.0,
+        0,
+        current.history_limit as f64,
+        &commit,
+        |s, v| s.history_limit = v as usize,
+    );
+    cache_page.add(&cache_group);
+    window.add(&cache_page);
+
+    // 新規プロジェクト
+    let project_page = PreferencesPage::builder()
+        .title("新規プロジェクト")
+        .icon_name("video-x-generic-symbolic")
+        .build();
+    let output_group = PreferencesGroup::builder()
+        .title("出力")
+        .description("新しく作るプロジェクトの既定値です")
+        .build();
+    spin_row(
+        &output_group,
+        "幅",
+        "px",
+        settings::PROJECT_SIZE,
+        2.0,
+        0,
+        current.project_width as f64,
+        &commit,
+        |s, v| s.project_width = v as u32,
+    );
+    spin_row(
+        &output_group,
+        "高さ",
+        "px",
+        settings::PROJECT_SIZE,
+        2.0,
+        0,
+        current.project_height as f64,
+        &commit,
+        |s, v| s.project_height = v as u32,
+    );
+    let mut rates = profile::FRAME_RATES.to_vec();
+    if !rates.contains(&current.project_frame_rate) {
+        rates.push(current.project_frame_rate);
+    }
+    let labels: Vec<String> = rates.iter().map(|r| profile::rate_label(*r)).collect();
+    let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+    let rate_row = ComboRow::builder()
+        .title("フレームレート")
+        .model(&StringList::new(&labels))
+        .build();
+    rate_row.set_selected(
+        rates
+            .iter()
+            .position(|r| *r == current.project_frame_rate)
+            .unwrap_or(0) as u32,
+    );
+    {
+        let commit = commit.clone();
+        rate_row.connect_selected_notify(move |row| {
+            if let Some(rate) = rates.get(row.selected() as usize).copied() {
+                commit(Rc::new(move |s| s.project_frame_rate = rate));
+            }
+        });
+    }
+    output_group.add(&rate_row);
+    project_page.add(&output_group);
+    window.add(&project_page);
+
+    window.present();
+}
@@ -0,0 +1,368 @@
+// Output profiles (プロファイル): named output settings a project is bound to
+
+use crate::config::ConfigFile;
+use crate::project::{FrameRate, OutputSettings, Project};
+use crate::timeline::{Frame, Timeline};
+use serde::{Deserialize, Serialize};
+
+// Frame rates offered in the editors
+pub const FRAME_RATES: [FrameRate; 8] = [
+    FrameRate::new(24000, 1001),
+    FrameRate::new(24, 1),
+    FrameRate::new(25, 1),
+    FrameRate::new(30000, 1001),
+    FrameRate::new(30, 1),
+    FrameRate::new(50, 1),
+    FrameRate::new(60000, 1001),
+    FrameRate::new(60, 1),
+];
+
+pub const SAMPLE_RATES: [u32; 3] = [44100, 48000, 96000];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub output: OutputSettings,
+}
+
+fn builtin_profile(name: &str, width: u32, height: u32, frame_rate: FrameRate) -> Profile {
+    Profile {
+        name: name.to_string(),
+        output: OutputSettings {
+            width,
+            height,
+            frame_rate,
+            ..OutputSettings::default()
+        },
+    }
+}
+
+// Profiles that always exist and can't be edited
+pub fn builtin() -> Vec<Profile> {
+    vec![
+        builtin_profile("1080p30", 1920, 1080, FrameRate::new(30, 1)),
+        builtin_profile("1080p60", 1920, 1080, FrameRate::new(60, 1)),
+        builtin_profile("4K 30p", 3840, 2160, FrameRate::new(30, 1)),
+        builtin_profile("縦 1080×1920", 1080, 1920, FrameRate::new(30, 1)),
+        builtin_profile("正方形 1080×1080", 1080, 1080, FrameRate::new(30, 1)),
+    ]
+}
+
+// "30fps", "29.97fps", "23.976fps"
+pub fn rate_label(rate: FrameRate) -> String {
+    let fps = format!("{:.3}", rate.fps());
+    format!("{}fps", fps.trim_end_matches('0').trim_end_matches('.'))
+}
+
+// "1920×1080 29.97fps 48kHz ステレオ"
+pub fn summary(output: &OutputSettings) -> String {
+    let mut text = format!(
+        "{}×{} {} {}kHz {}",
+        output.width,
+        output.height,
+        rate_label(output.frame_rate),
+        output.sample_rate as f64 / 1000.0,
+        output.channels.name()
+    );
+    if (output.pixel_aspect - 1.0).abs() > 1e-6 {
+        text += &format!(" (ピクセル比 {:.3})", output.pixel_aspect);
+    }
+    text
+}
+
+impl Profile {
+    // Problems that keep the profile from being saved
+    pub fn validate(&self) -> Result<(), String> {
+        let output = &self.output;
+        if self.name.trim().is_empty() {
+            return Err("名前を入力してください".to_string());
+        }
+        if !(16..=8192).contains(&output.width) || !(16..=8192).contains(&output.height) {
+            return Err("解像度は 16〜8192 の範囲で指定してください".to_string());
+        }
+        if output.frame_rate.num == 0 || output.frame_rate.den == 0 {
+            return Err("フレームレートが正しくありません".to_string());
+        }
+        if !(0.1..=10.0).contains(&output.pixel_aspect) {
+            return Err("ピクセル比は 0.1〜10 の範囲で指定してください".to_string());
+        }
+        if !(8000..=192000).contains(&output.sample_rate) {
+            return Err("サンプリングレートが正しくありません".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfilesFile {
+    profiles: Vec<Profile>,
+}
+
+// Built-in profiles followed by the user's own
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profiles {
+    pub user: Vec<Profile>,
+}
+
+impl Profiles {
+    pub fn all(&self) -> Vec<Profile> {
+        let mut all = builtin();
+        all.extend(self.user.iter().cloned());
+        all
+    }
+
+    pub fn find(&self, name: &str) -> Option<Profile> {
+        self.all().into_iter().find(|p| p.name == name)
+    }
+
+    pub fn is_builtin(name: &str) -> bool {
+        builtin().iter().any(|p| p.name == name)
+    }
+
+    // Add a user profile, or replace the one with the same name
+    pub fn put(&mut self, profile: Profile) -> Result<(), String> {
+        profile.validate()?;
+        if Profiles::is_builtin(&profile.name) {
+            return Err("組み込みのプロファイルは変更できません".to_string());
+        }
+        match self.user.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.user.push(profile),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.user.retain(|p| p.name != name);
+    }
+}
+
+impl ConfigFile for Profiles {
+    const NAME: &'static str = "profiles.json";
+    const WHAT: &'static str = "プロファイル";
+    const VERSION: u32 = 1;
+
+    type Document = ProfilesFile;
+
+    fn to_document(&self) -> ProfilesFile {
+        ProfilesFile {
+            profiles: self.user.clone(),
+        }
+    }
+
+    // Invalid entries and ones shadowing a built-in are dropped
+    fn from_document(file: ProfilesFile) -> Profiles {
+        let mut profiles = Profiles::default();
+        for profile in file.profiles {
+            let name = profile.name.clone();
+            if let Err(e) = profiles.put(profile) {
+                eprintln!("プロファイル {}: {}", name, e);
+            }
+        }
+        profiles
+    }
+}
+
+// Name of the built-in profile with exactly these settings, if any
+pub fn builtin_name(output: &OutputSettings) -> String {
+    builtin()
+        .into_iter()
+        .find(|p| p.output == *output)
+        .map_or(String::new(), |p| p.name)
+}
+
+// What switching `project` to `profile` would change on the timeline.
+// One line per change, empty if the objects are unaffected.
+pub fn conversion_notes(project: &Project, profile: &Profile) -> Vec<String> {
+    let (from, to) = (&project.output, &profile.output);
+    let mut notes = Vec::new();
+    if project.timeline.clips.is_empty() && project.timeline.markers.is_empty() {
+        return notes;
+    }
+    if from.frame_rate != to.frame_rate {
+        notes.push(format!(
+            "フレームレート {} → {}: オブジェクトの位置と長さを秒数が変わらないように変換します",
+            rate_label(from.frame_rate),
+            rate_label(to.frame_rate)
+        ));
+    }
+    if (from.width, from.height) != (to.width, to.height) {
+        notes.push(format!(
+            "解像度 {}×{} → {}×{}: オブジェクトの座標と拡大率を画面に合わせます",
+            from.width, from.height, to.width, to.height
+        ));
+    }
+    notes
+}
+
+// Same moment at another frame rate
+pub fn convert_frame(frame: Frame, from: FrameRate, to: FrameRate) -> Frame {
+    let num = frame as i128 * from.den as i128 * to.num as i128;
+    let den = from.num as i128 * to.den as i128;
+    ((2 * num + den).div_euclid(2 * den)) as Frame
+}
+
+// Keep every object and marker at the same time in seconds
+fn convert_rate(timeline: &mut Timeline, from: FrameRate, to: FrameRate) -> Result<(), String> {
+    for clip in &mut timeline.clips {
+        let start = convert_frame(clip.start, from, to);
+        let end = convert_frame(clip.end(), from, to);
+        clip.start = start;
+        clip.length = (end - start).max(1);
+    }
+    for marker in &mut timeline.markers {
+        marker.frame = convert_frame(marker.frame, from, to);
+    }
+    if timeline.has_overlaps() {
+        return Err("変換するとオブジェクトが重なります".to_string());
+    }
+    Ok(())
+}
+
+// Scale positions (relative to the centre) and zoom so the picture fits the new size
+fn scale_objects(timeline: &mut Timeline, from: (u32, u32), to: (u32, u32)) {
+    let scale = (to.0 as f64 / from.0 as f64).min(to.1 as f64 / from.1 as f64);
+    for clip in &mut timeline.clips {
+        clip.props.x *= scale;
+        clip.props.y *= scale;
+        clip.props.zoom *= scale;
+    }
+}
+
+// Bind `project` to `profile`. With `convert`, the timeline is adjusted as
+// described by `conversion_notes`; otherwise frame numbers and coordinates stay.
+pub fn apply(project: &mut Project, profile: &Profile, convert: bool) -> Result<(), String> {
+    if convert {
+        let (from, to) = (&project.output, &profile.output);
+        let mut timeline = project.timeline.clone();
+        if from.frame_rate != to.frame_rate {
+            convert_rate(&mut timeline, from.frame_rate, to.frame_rate)?;
+            let convert = |f: Frame| convert_frame(f, from.frame_rate, to.frame_rate);
+            project.in_point = project.in_point.map(convert);
+            project.out_point = project.out_point.map(convert);
+        }
+        if (from.width, from.height) != (to.width, to.height) {
+            scale_objects(
+                &mut timeline,
+                (from.width, from.height),
+                (to.width, to.height),
+            );
+        }
+        project.timeline = timeline;
+    }
+    project.output = profile.output.clone();
+    project.profile = profile.name.clone();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::{Marker, ShapeKind, Source};
+
+    const NTSC: FrameRate = FrameRate::new(30000, 1001);
+
+    fn shape() -> Source {
+        Source::Shape(ShapeKind::Rectangle)
+    }
+
+    fn profile(name: &str, frame_rate: FrameRate) -> Profile {
+        builtin_profile(name, 1920, 1080, frame_rate)
+    }
+
+    #[test]
+    fn frames_keep_their_time_to_the_nearest_frame() {
+        let thirty = FrameRate::new(30, 1);
+        assert_eq!(convert_frame(30, thirty, NTSC), 30);
+        // 1000 frames at 30 are 999.0 at 29.97
+        assert_eq!(convert_frame(1000, thirty, NTSC), 999);
+        // 1498.5015 rounds up, 499.5005 too
+        assert_eq!(convert_frame(1500, thirty, NTSC), 1499);
+        assert_eq!(convert_frame(500, thirty, NTSC), 500);
+        assert_eq!(convert_frame(999, NTSC, thirty), 1000);
+        assert_eq!(convert_frame(-1, thirty, NTSC), -1);
+    }
+
+    #[test]
+    fn converting_moves_objects_markers_and_the_range() {
+        let mut project = Project::new("p");
+        project.output.frame_rate = FrameRate::new(30, 1);
+        let id = project.timeline.add_clip(0, 300, 150, shape());
+        project.timeline.clips[0].props.x = 100.0;
+        project.timeline.markers.push(Marker {
+            frame: 600,
+            name: "m".to_string(),
+        });
+        (project.in_point, project.out_point) = (Some(30), Some(900));
+
+        let mut target = profile("60p 720", FrameRate::new(60, 1));
+        (target.output.width, target.output.height) = (1280, 720);
+        assert_eq!(conversion_notes(&project, &target).len(), 2);
+        apply(&mut project, &target, true).unwrap();
+
+        let clip = project.timeline.clip(id).unwrap();
+        assert_eq!((clip.start, clip.length), (600, 300));
+        assert!((clip.props.x - 100.0 * 1280.0 / 1920.0).abs() < 1e-9);
+        assert_eq!(project.timeline.markers[0].frame, 1200);
+        assert_eq!(
+            (project.in_point, project.out_point),
+            (Some(60), Some(1800))
+        );
+        assert_eq!(project.profile, "60p 720");
+        assert_eq!(project.output, target.output);
+    }
+
+    #[test]
+    fn without_converting_only_the_output_changes() {
+        let mut project = Project::new("p");
+        let id = project.timeline.add_clip(0, 300, 150, shape());
+        project.in_point = Some(30);
+        apply(
+            &mut project,
+            &profile("1080p60", FrameRate::new(60, 1)),
+            false,
+        )
+        .unwrap();
+        let clip = project.timeline.clip(id).unwrap();
+        assert_eq!((clip.start, clip.length), (300, 150));
+        assert_eq!(project.in_point, Some(30));
+        assert_eq!(project.output.frame_rate, FrameRate::new(60, 1));
+    }
+
+    #[test]
+    fn a_conversion_that_overlaps_objects_changes_nothing() {
+        let mut project = Project::new("p");
+        project.output.frame_rate = FrameRate::new(60, 1);
+        // Two one-frame objects become the same frame at 24fps
+        project.timeline.add_clip(0, 0, 1, shape());
+        project.timeline.add_clip(0, 1, 1, shape());
+        project.in_point = Some(60);
+        let before = project.clone();
+        assert_eq!(
+            apply(&mut project, &profile("24p", FrameRate::new(24, 1)), true),
+            Err("変換するとオブジェクトが重なります".to_string())
+        );
+        assert_eq!(project, before);
+    }
+
+    #[test]
+    fn built_in_names_are_taken() {
+        let mut profiles = Profiles::default();
+        assert_eq!(
+            profiles.put(profile("1080p30", NTSC)),
+            Err("組み込みのプロファイルは変更できません".to_string())
+        );
+        assert!(profiles.put(profile(" ", NTSC)).is_err());
+        assert!(profiles.user.is_empty());
+
+        profiles.put(profile("mine", NTSC)).unwrap();
+        profiles
+            .put(profile("mine", FrameRate::new(24, 1)))
+            .unwrap();
+        assert_eq!(profiles.user.len(), 1);
+        assert_eq!(
+            profiles.find("mine").unwrap().output.frame_rate,
+            FrameRate::new(24, 1)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+// プロファイル / プロファイルの管理: create, edit and delete user profiles
+
+use crate::config::ConfigFile;
+use crate::profile::{self, Profile, Profiles};
+use crate::project::{ChannelLayout, FrameRate, OutputSettings};
+use gtk4::gdk::RGBA;
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box as GtkBox, Button, ColorDialog, ColorDialogButton, DropDown, Entry, Grid, Label,
+    ListBox, Orientation, ScrolledWindow, SelectionMode, SpinButton, StringList,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Input widgets of the profile being edited
+struct Form {
+    name: Entry,
+    width: SpinButton,
+    height: SpinButton,
+    rate: DropDown,
+    // Entries of `rate`; rates not in profile::FRAME_RATES are appended as needed
+    rates: RefCell<Vec<FrameRate>>,
+    pixel_aspect: SpinButton,
+    sample_rate: DropDown,
+    channels: DropDown,
+    background: ColorDialogButton,
+}
+
+impl Form {
+    fn new() -> Form {
+        let rates = profile::FRAME_RATES.to_vec();
+        let rate_labels: Vec<String> = rates.iter().map(|r| profile::rate_label(*r)).collect();
+        let rate_labels: Vec<&str> = rate_labels.iter().map(String::as_str).collect();
+        let sample_labels: Vec<String> = profile::SAMPLE_RATES
+            .iter()
+            .map(|r| format!("{} Hz", r))
+            .collect();
+        let sample_labels: Vec<&str> = sample_labels.iter().map(String::as_str).collect();
+        let channel_labels: Vec<&str> = ChannelLayout::ALL.iter().map(|c| c.name()).collect();
+        let pixel_aspect = SpinButton::with_range(0.1, 10.0, 0.001);
+        pixel_aspect.set_digits(3);
+        Form {
+            name: Entry::new(),
+            width: SpinButton::with_range(16.0, 8192.0, 2.0),
+            height: SpinButton::with_range(16.0, 8192.0, 2.0),
+            rate: DropDown::from_strings(&rate_labels),
+            rates: RefCell::new(rates),
+            pixel_aspect,
+            sample_rate: DropDown::from_strings(&sample_labels),
+            channels: DropDown::from_strings(&channel_labels),
+            background: ColorDialogButton::new(Some(ColorDialog::new())),
+        }
+    }
+
+    fn grid(&self) -> Grid {
+        let grid = Grid::builder().row_spacing(6).column_spacing(12).build();
+        let rows: [(&str, &gtk4::Widget); 8] = [
+            ("名前", self.name.upcast_ref()),
+            ("幅", self.width.upcast_ref()),
+            ("高さ", self.height.upcast_ref()),
+            ("フレームレート", self.rate.upcast_ref()),
+            ("ピクセル比", self.pixel_aspect.upcast_ref()),
+            ("サンプリングレート", self.sample_rate.upcast_ref()),
+            ("チャンネル", self.channels.upcast_ref()),
+            ("背景色", self.background.upcast_ref()),
+        ];
+        for (row, (label, widget)) in rows.into_iter().enumerate() {
+            let label = Label::new(Some(label));
+            label.set_halign(Align::Start);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(widget, 1, row as i32, 1, 1);
+        }
+        grid
+    }
+
+    fn set(&self, name: &str, output: &OutputSettings) {
+        self.name.set_text(name);
+        self.width.set_value(output.width as f64);
+        self.height.set_value(output.height as f64);
+        let position = self
+            .rates
+            .borrow()
+            .iter()
+            .position(|r| *r == output.frame_rate);
+        let position = position.unwrap_or_else(|| {
+            let mut rates = self.rates.borrow_mut();
+            rates.push(output.frame_rate);
+            if let Some(model) = self.rate.model().and_downcast::<StringList>() {
+                model.append(&profile::rate_label(output.frame_rate));
+            }
+            rates.len() - 1
+        });
+        self.rate.set_selected(position as u32);
+        self.pixel_aspect.set_value(output.pixel_aspect);
+        self.sample_rate.set_selected(
+            profile::SAMPLE_RATES
+                .iter()
+                .position(|r| *r == output.sample_rate)
+                .unwrap_or(1) as u32,
+        );
+        self.channels.set_selected(
+            ChannelLayout::ALL
+                .iter()
+                .position(|c| *c == output.channels)
+                .unwrap_or(1) as u32,
+        );
+        let [r, g, b, a] = output.background;
+        self.background
+            .set_rgba(&RGBA::new(r as f32, g as f32, b as f32, a as f32));
+    }
+
+    fn get(&self) -> Profile {
+        let rates = self.rates.borrow();
+        let color = self.background.rgba();
+        Profile {
+            name: self.name.text().trim().to_string(),
+            output: OutputSettings {
+                width: self.width.value() as u32,
+                height: self.height.value() as u32,
+                frame_rate: rates[(self.rate.selected() as usize).min(rates.len() - 1)],
+                pixel_aspect: self.pixel_aspect.value(),
+                sample_rate: profile::SAMPLE_RATES
+                    [(self.sample_rate.selected() as usize).min(profile::SAMPLE_RATES.len() - 1)],
+                channels: ChannelLayout::ALL
+                    [(self.channels.selected() as usize).min(ChannelLayout::ALL.len() - 1)],
+                background: [
+                    color.red() as f64,
+                    color.green() as f64,
+                    color.blue() as f64,
+                    color.alpha() as f64,
+                ],
+            },
+        }
+    }
+}
+
+// `current` fills the form at first so the project's settings can be saved as a
+// profile. `changed` runs after every save / delete.
+pub fn show(
+    parent: &impl IsA<gtk4::Window>,
+    profiles: Rc<RefCell<Profiles>>,
+    current: &OutputSettings,
+    changed: Rc<dyn Fn()>,
+) {
+    let dialog = gtk4::Window::builder()
+        .title("プロファイルの管理")
+        .transient_for(parent)
+        .modal(true)
+        .default_width(720)
+        .default_height(420)
+        .build();
+
+    let content = GtkBox::new(Orientation::Vertical, 8);
+    content.set_margin_top(12);
+    content.set_margin_bottom(12);
+    content.set_margin_start(12);
+    content.set_margin_end(12);
+
+    let columns = GtkBox::new(Orientation::Horizontal, 12);
+    let list = ListBox::new();
+    list.set_selection_mode(SelectionMode::Single);
+    let scrolled = ScrolledWindow::builder()
+        .child(&list)
+        .hexpand(true)
+        .vexpand(true)
+        .build();
+    columns.append(&scrolled);
+    let form = Rc::new(Form::new());
+    columns.append(&form.grid());
+    content.append(&columns);
+
+    let status = Label::new(None);
+    status.set_halign(Align::Start);
+    status.set_wrap(true);
+    content.append(&status);
+
+    let buttons = GtkBox::new(Orientation::Horizontal, 6);
+    let save = Button::with_label("保存");
+    let delete = Button::with_label("削除");
+    let close = Button::with_label("閉じる");
+    close.set_hexpand(true);
+    close.set_halign(Align::End);
+    buttons.append(&save);
+    buttons.append(&delete);
+    buttons.append(&close);
+    content.append(&buttons);
+
+    // Profiles in list order
+    let shown: Rc<RefCell<Vec<Profile>>> = Rc::new(RefCell::new(Vec::new()));
+    let refresh: Rc<dyn Fn()> = {
+        let (list, profiles, shown) = (list.clone(), profiles.clone(), shown.clone());
+        Rc::new(move || {
+            while let Some(row) = list.first_child() {
+                list.remove(&row);
+            }
+            let all = profiles.borrow().all();
+            for profile in &all {
+                let row = GtkBox::new(Orientation::Vertical, 2);
+                let name = if Profiles::is_builtin(&profile.name) {
+                    format!("{} (組み込み)", profile.name)
+                } else {
+                    profile.name.clone()
+                };
+                let title = Label::new(Some(&name));
+                title.set_halign(Align::Start);
+                let detail = Label::new(Some(&profile::summary(&profile.output)));
+                detail.set_halign(Align::Start);
+                detail.add_css_class("dim-label");
+                row.append(&title);
+                row.append(&detail);
+                list.append(&row);
+            }
+            *shown.borrow_mut() = all;
+        })
+    };
+
+    {
+        let (form, shown, delete) = (form.clone(), shown.clone(), delete.clone());
+        list.connect_row_selected(move |_, row| {
+            let Some(profile) = row.and_then(|r| shown.borrow().get(r.index() as usize).cloned())
+            else {
+                return;
+            };
+            form.set(&profile.name, &profile.output);
+            delete.set_sensitive(!Profiles::is_builtin(&profile.name));
+        });
+    }
+    {
+        let (form, profiles, status) = (form.clone(), profiles.clone(), status.clone());
+        let (refresh, changed) = (refresh.clone(), changed.clone());
+        save.connect_clicked(move |_| {
+            let profile = form.get();
+            let name = profile.name.clone();
+            let result = profiles.borrow_mut().put(profile);
+            match result {
+                Ok(()) => {
+                    profiles.borrow().save_user();
+                    status.set_text(&format!("「{}」を保存しました", name));
+                    refresh();
+                    changed();
+                }
+                Err(e) => status.set_text(&e),
+            }
+        });
+    }
+    {
+        let (form, profiles, status) = (form.clone(), profiles.clone(), status.clone());
+        let refresh = refresh.clone();
+        delete.connect_clicked(move |_| {
+            let name = form.get().name;
+            if Profiles::is_builtin(&name) {
+                status.set_text("組み込みのプロファイルは削除できません");
+                return;
+            }
+            profiles.borrow_mut().remove(&name);
+            profiles.borrow().save_user();
+            status.set_text(&format!("「{}」を削除しました", name));
+            refresh();
+            changed();
+        });
+    }
+    {
+        let dialog = dialog.clone();
+        close.connect_clicked(move |_| dialog.close());
+    }
+
+    refresh();
+    form.set("", current);
+    status.set_text("名前を付けて保存すると新しいプロファイルになります");
+    dialog.set_child(Some(&content));
+    dialog.present();
+}
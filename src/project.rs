@@ -0,0 +1,168 @@
+// Project: output settings, media list and the timeline
+
+use crate::timeline::{Clip, Frame, Source, Timeline};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRate {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl FrameRate {
+    pub const fn new(num: u32, den: u32) -> Self {
+        Self { num, den }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    pub fn frame_to_seconds(&self, frame: Frame) -> f64 {
+        frame as f64 * self.den as f64 / self.num as f64
+    }
+
+    pub fn seconds_to_frame(&self, seconds: f64) -> Frame {
+        (seconds * self.fps()).floor() as Frame
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelLayout {
+    Mono,
+    #[default]
+    Stereo,
+    // 5.1 surround
+    Surround51,
+}
+
+impl ChannelLayout {
+    pub const ALL: [ChannelLayout; 3] = [
+        ChannelLayout::Mono,
+        ChannelLayout::Stereo,
+        ChannelLayout::Surround51,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelLayout::Mono => "モノラル",
+            ChannelLayout::Stereo => "ステレオ",
+            ChannelLayout::Surround51 => "5.1ch",
+        }
+    }
+
+    pub fn channels(self) -> u32 {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: FrameRate,
+    // Width / height of one pixel (1.0 = square pixels)
+    pub pixel_aspect: f64,
+    pub sample_rate: u32,
+    pub channels: ChannelLayout,
+    pub background: [f64; 4],
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            frame_rate: FrameRate::new(30, 1),
+            pixel_aspect: 1.0,
+            sample_rate: 48000,
+            channels: ChannelLayout::Stereo,
+            background: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct MediaId(pub u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Media {
+    pub id: MediaId,
+    pub path: PathBuf,
+    pub kind: MediaKind,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub name: String,
+    // Name of the output profile `output` was taken from ("" = custom)
+    pub profile: String,
+    pub output: OutputSettings,
+    pub media: Vec<Media>,
+    pub timeline: Timeline,
+    // Export range: first frame and last frame (inclusive)
+    pub in_point: Option<Frame>,
+    pub out_point: Option<Frame>,
+}
+
+impl Project {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Register a file (reuses the entry if the path is already known)
+    pub fn add_media(&mut self, path: PathBuf, kind: MediaKind) -> MediaId {
+        if let Some(media) = self.media.iter().find(|m| m.path == path) {
+            return media.id;
+        }
+        let id = MediaId(self.media.iter().map(|m| m.id.0 + 1).max().unwrap_or(1));
+        self.media.push(Media { id, path, kind });
+        id
+    }
+
+    // In / out points as start..end (end exclusive). A missing point falls back
+    // to the start / end of the timeline.
+    pub fn in_out_range(&self) -> (Frame, Frame) {
+        let start = self.in_point.unwrap_or(0);
+        let end = self
+            .out_point
+            .map_or(self.timeline.end_frame(), |out| out + 1);
+        (start, end)
+    }
+
+    pub fn media(&self, id: MediaId) -> Option<&Media> {
+        self.media.iter().find(|m| m.id == id)
+    }
+
+    // Name shown on the timeline
+    pub fn clip_name(&self, clip: &Clip) -> String {
+        match &clip.source {
+            Source::Media(id) => self
+                .media(*id)
+                .and_then(|m| m.path.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "(メディアなし)".to_string()),
+            Source::Text(text) => text.clone(),
+            Source::Shape(shape) => shape.name().to_string(),
+            Source::Filter(filter) => filter.name().to_string(),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// .luvita project file (pretty printed JSON so it can be diffed)
+
+use crate::config;
+use crate::project::Project;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const EXTENSION: &str = "luvita";
+
+// Bump this when the layout of Project changes and add a migration below
+pub const FORMAT_VERSION: u32 = 1;
+
+const MIGRATIONS: &[config::Migration] = &[];
+
+#[derive(Debug)]
+pub enum ProjectFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    MissingVersion,
+    TooNew(u32),
+}
+
+impl fmt::Display for ProjectFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectFileError::Io(e) => write!(f, "ファイルを読み書きできません: {}", e),
+            ProjectFileError::Json(e) => write!(f, "プロジェクトファイルが壊れています: {}", e),
+            ProjectFileError::MissingVersion => write!(f, "バージョン情報がありません"),
+            ProjectFileError::TooNew(v) => write!(
+                f,
+                "新しいバージョン ({}) のプロジェクトです (対応: {})",
+                v, FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProjectFileError {
+    fn from(e: std::io::Error) -> Self {
+        ProjectFileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProjectFileError {
+    fn from(e: serde_json::Error) -> Self {
+        ProjectFileError::Json(e)
+    }
+}
+
+#[derive(Serialize)]
+struct ProjectFile<'a> {
+    version: u32,
+    project: &'a Project,
+}
+
+pub fn to_string(project: &Project) -> Result<String, ProjectFileError> {
+    let file = ProjectFile {
+        version: FORMAT_VERSION,
+        project,
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+pub fn from_str(text: &str) -> Result<Project, ProjectFileError> {
+    let mut document: Value = serde_json::from_str(text)?;
+    // Versions start at 1
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .filter(|&v| v >= 1)
+        .ok_or(ProjectFileError::MissingVersion)?;
+    if version > FORMAT_VERSION as u64 {
+        return Err(ProjectFileError::TooNew(
+            version.try_into().unwrap_or(u32::MAX),
+        ));
+    }
+
+    config::migrate(&mut document, version as u32, MIGRATIONS);
+
+    let project = document
+        .get_mut("project")
+        .map(Value::take)
+        .unwrap_or(Value::Null);
+    Ok(serde_json::from_value(project)?)
+}
+
+pub fn save(project: &Project, path: &Path) -> Result<(), ProjectFileError> {
+    let text = to_string(project)?;
+    config::write_atomic(path, &text)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Project, ProjectFileError> {
+    let text = fs::read_to_string(path)?;
+    from_str(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::MediaKind;
+    use crate::timeline::Source;
+    use std::path::PathBuf;
+
+    #[test]
+    fn projects_round_trip() {
+        let mut project = Project::new("round trip");
+        project.output.width = 640;
+        let media = project.add_media(PathBuf::from("/media/clip.mp4"), MediaKind::Video);
+        project.timeline.add_clip(1, 30, 90, Source::Media(media));
+        project
+            .timeline
+            .add_clip(2, 0, 10, Source::Text("title".to_string()));
+
+        let text = to_string(&project).unwrap();
+        assert_eq!(from_str(&text).unwrap(), project);
+    }
+
+    #[test]
+    fn a_document_needs_a_version_from_1() {
+        for text in [
+            r#"{"project": {}}"#,
+            r#"{"version": "1", "project": {}}"#,
+            r#"{"version": 0, "project": {}}"#,
+        ] {
+            assert!(
+                matches!(from_str(text), Err(ProjectFileError::MissingVersion)),
+                "{}",
+                text
+            );
+        }
+        assert!(from_str(r#"{"version": 1, "project": {}}"#).is_ok());
+    }
+
+    #[test]
+    fn newer_documents_are_refused() {
+        let text = format!(r#"{{"version": {}, "project": {{}}}}"#, FORMAT_VERSION + 1);
+        assert!(matches!(
+            from_str(&text),
+            Err(ProjectFileError::TooNew(v)) if v == FORMAT_VERSION + 1
+        ));
+        // Too big for u32 is still too new, not version 1
+        assert!(matches!(
+            from_str(r#"{"version": 4294967297, "project": {}}"#),
+            Err(ProjectFileError::TooNew(u32::MAX))
+        ));
+    }
+}
@@ -0,0 +1,399 @@
+// Frame renderer (no GTK: only cairo, pango and gdk-pixbuf)
+// Composites the objects active at a frame into an image at project resolution.
+
+use crate::project::{MediaKind, Project};
+use crate::timeline::{Clip, FilterKind, Frame, ShapeKind, Source};
+use cairo::{Context, Format, ImageSurface};
+use gdk_pixbuf::Pixbuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Size of shape objects at zoom 1.0
+const SHAPE_SIZE: f64 = 200.0;
+const TEXT_FONT: &str = "Sans 64";
+
+// Memory for decoded images unless the settings say otherwise
+const DEFAULT_CACHE_BYTES: usize = 512 * 1024 * 1024;
+
+pub struct Renderer {
+    // Decoded still images (None when decoding failed, so we don't retry every frame)
+    images: HashMap<PathBuf, Option<ImageSurface>>,
+    // Least recently used first
+    image_order: Vec<PathBuf>,
+    cache_limit: usize,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self {
+            images: HashMap::new(),
+            image_order: Vec::new(),
+            cache_limit: DEFAULT_CACHE_BYTES,
+        }
+    }
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_cache_limit(&mut self, bytes: usize) {
+        self.cache_limit = bytes;
+        self.trim_cache();
+    }
+
+    fn cache_bytes(&self) -> usize {
+        self.images
+            .values()
+            .flatten()
+            .map(|image| image.stride() as usize * image.height() as usize)
+            .sum()
+    }
+
+    // Drop the least recently used images until the cache fits. The image in use
+    // (last in the order) is always kept.
+    fn trim_cache(&mut self) {
+        while self.image_order.len() > 1 && self.cache_bytes() > self.cache_limit {
+            let oldest = self.image_order.remove(0);
+            self.images.remove(&oldest);
+        }
+    }
+
+    pub fn render_frame(
+        &mut self,
+        project: &Project,
+        frame: Frame,
+    ) -> Result<ImageSurface, cairo::Error> {
+        let output = &project.output;
+        let mut canvas =
+            ImageSurface::create(Format::ARgb32, output.width as i32, output.height as i32)?;
+
+        {
+            let cr = Context::new(&canvas)?;
+            let [r, g, b, a] = output.background;
+            cr.set_source_rgba(r, g, b, a);
+            cr.paint()?;
+        }
+
+        // Layer 1 is drawn first, higher layers on top (AviUtl order)
+        for clip in project.timeline.active_clips(frame) {
+            match &clip.source {
+                // A filter object affects everything below it
+                Source::Filter(filter) => apply_filter(&mut canvas, filter),
+                _ => {
+                    let Some(mut object) = self.render_object(project, clip)? else {
+                        continue;
+                    };
+                    for filter in &clip.filters {
+                        apply_filter(&mut object, filter);
+                    }
+                    let cr = Context::new(&canvas)?;
+                    draw_object(&cr, &object, clip, output.width, output.height)?;
+                }
+            }
+        }
+
+        canvas.flush();
+        Ok(canvas)
+    }
+
+    // Draw one object into its own surface at native size
+    fn render_object(
+        &mut self,
+        project: &Project,
+        clip: &Clip,
+    ) -> Result<Option<ImageSurface>, cairo::Error> {
+        let [r, g, b] = clip.props.color;
+        match &clip.source {
+            Source::Media(id) => {
+                let Some(media) = project.media(*id) else {
+                    return Ok(None);
+                };
+                if media.kind != MediaKind::Image {
+                    // Video and audio decoding is not supported yet
+                    return Ok(None);
+                }
+                self.image_order.retain(|p| *p != media.path);
+                self.image_order.push(media.path.clone());
+                if !self.images.contains_key(&media.path) {
+                    self.images
+                        .insert(media.path.clone(), load_image(&media.path));
+                    self.trim_cache();
+                }
+                match &self.images[&media.path] {
+                    Some(image) => Ok(Some(copy_surface(image)?)),
+                    None => Ok(None),
+                }
+            }
+            Source::Text(text) => {
+                // Measure on a dummy surface first
+                let measure = ImageSurface::create(Format::ARgb32, 1, 1)?;
+                let layout = pangocairo::functions::create_layout(&Context::new(&measure)?);
+                layout.set_font_description(Some(&pango::FontDescription::from_string(TEXT_FONT)));
+                layout.set_text(text);
+                let (w, h) = layout.pixel_size();
+                if w <= 0 || h <= 0 {
+                    return Ok(None);
+                }
+
+                let surface = ImageSurface::create(Format::ARgb32, w, h)?;
+                {
+                    let cr = Context::new(&surface)?;
+                    let layout = pangocairo::functions::create_layout(&cr);
+                    layout.set_font_description(Some(&pango::FontDescription::from_string(
+                        TEXT_FONT,
+                    )));
+                    layout.set_text(text);
+                    cr.set_source_rgb(r, g, b);
+                    pangocairo::functions::show_layout(&cr, &layout);
+                }
+                Ok(Some(surface))
+            }
+            Source::Shape(shape) => {
+                let size = SHAPE_SIZE as i32;
+                let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+                {
+                    let cr = Context::new(&surface)?;
+                    cr.set_source_rgb(r, g, b);
+                    match shape {
+                        ShapeKind::Rectangle => cr.rectangle(0.0, 0.0, SHAPE_SIZE, SHAPE_SIZE),
+                        ShapeKind::Circle => cr.arc(
+                            SHAPE_SIZE / 2.0,
+                            SHAPE_SIZE / 2.0,
+                            SHAPE_SIZE / 2.0,
+                            0.0,
+                            2.0 * std::f64::consts::PI,
+                        ),
+                    }
+                    cr.fill()?;
+                }
+                Ok(Some(surface))
+            }
+            Source::Filter(_) => Ok(None),
+        }
+    }
+}
+
+// Place an object: (x, y) is the offset of its center from the frame center
+fn draw_object(
+    cr: &Context,
+    object: &ImageSurface,
+    clip: &Clip,
+    width: u32,
+    height: u32,
+) -> Result<(), cairo::Error> {
+    let props = &clip.props;
+    cr.translate(width as f64 / 2.0 + props.x, height as f64 / 2.0 + props.y);
+    cr.rotate(props.rotation.to_radians());
+    cr.scale(props.zoom, props.zoom);
+    cr.set_source_surface(
+        object,
+        -object.width() as f64 / 2.0,
+        -object.height() as f64 / 2.0,
+    )?;
+    cr.paint_with_alpha(props.opacity.clamp(0.0, 1.0))
+}
+
+fn copy_surface(source: &ImageSurface) -> Result<ImageSurface, cairo::Error> {
+    let surface = ImageSurface::create(Format::ARgb32, source.width(), source.height())?;
+    {
+        let cr = Context::new(&surface)?;
+        cr.set_source_surface(source, 0.0, 0.0)?;
+        cr.paint()?;
+    }
+    Ok(surface)
+}
+
+// Decode any format gdk-pixbuf knows into a cairo surface
+pub fn load_image(path: &Path) -> Option<ImageSurface> {
+    let pixbuf = match Pixbuf::from_file(path) {
+        Ok(pixbuf) => pixbuf,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return None;
+        }
+    };
+    pixbuf_to_surface(&pixbuf).ok()
+}
+
+pub fn pixbuf_to_surface(pixbuf: &Pixbuf) -> Result<ImageSurface, cairo::Error> {
+    let (w, h) = (pixbuf.width(), pixbuf.height());
+    let channels = pixbuf.n_channels() as usize;
+    let rowstride = pixbuf.rowstride() as usize;
+    let has_alpha = pixbuf.has_alpha();
+    let pixels = pixbuf.read_pixel_bytes();
+
+    let mut surface = ImageSurface::create(Format::ARgb32, w, h)?;
+    let stride = surface.stride() as usize;
+    {
+        let mut data = surface.data().map_err(|_| cairo::Error::SurfaceFinished)?;
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                let src = &pixels[y * rowstride + x * channels..];
+                let a = if has_alpha { src[3] as u32 } else { 255 };
+                // cairo wants premultiplied alpha
+                let r = src[0] as u32 * a / 255;
+                let g = src[1] as u32 * a / 255;
+                let b = src[2] as u32 * a / 255;
+                let argb = (a << 24) | (r << 16) | (g << 8) | b;
+                let dst = y * stride + x * 4;
+                data[dst..dst + 4].copy_from_slice(&argb.to_ne_bytes());
+            }
+        }
+    }
+    surface.mark_dirty();
+    Ok(surface)
+}
+
+// Back to straight (non-premultiplied) RGB(A) for gdk-pixbuf savers
+pub fn surface_to_pixbuf(surface: &mut ImageSurface, alpha: bool) -> Option<Pixbuf> {
+    let (w, h) = (surface.width(), surface.height());
+    let stride = surface.stride() as usize;
+    let channels = if alpha { 4 } else { 3 };
+    // The top byte of RGB24 pixels is unused
+    let opaque = surface.format() == Format::Rgb24;
+    let mut pixels = Vec::with_capacity(w as usize * h as usize * channels);
+    surface.flush();
+    let data = surface.data().ok()?;
+    for y in 0..h as usize {
+        for x in 0..w as usize {
+            let src = y * stride + x * 4;
+            let argb = u32::from_ne_bytes(data[src..src + 4].try_into().ok()?);
+            let a = if opaque { 255 } else { argb >> 24 };
+            let unpremultiply = |c: u32| (c * 255).checked_div(a).map_or(0, |v| v.min(255) as u8);
+            pixels.push(unpremultiply((argb >> 16) & 0xff));
+            pixels.push(unpremultiply((argb >> 8) & 0xff));
+            pixels.push(unpremultiply(argb & 0xff));
+            if alpha {
+                pixels.push(a as u8);
+            }
+        }
+    }
+    Some(Pixbuf::from_bytes(
+        &glib::Bytes::from_owned(pixels),
+        gdk_pixbuf::Colorspace::Rgb,
+        alpha,
+        8,
+        w,
+        h,
+        w * channels as i32,
+    ))
+}
+
+fn apply_filter(surface: &mut ImageSurface, filter: &FilterKind) {
+    surface.flush();
+    let (w, h) = (surface.width() as usize, surface.height() as usize);
+    let stride = surface.stride() as usize;
+    let Ok(mut data) = surface.data() else {
+        // Still referenced by a context; skip rather than fail the frame
+        return;
+    };
+    match *filter {
+        FilterKind::Brightness { amount } => brightness(&mut data, amount),
+        FilterKind::Blur { radius } => {
+            let radius = radius.round().max(0.0) as usize;
+            if radius > 0 {
+                box_blur(&mut data, w, h, stride, radius);
+            }
+        }
+        FilterKind::Mosaic { size } => {
+            let size = size.round().max(1.0) as usize;
+            if size > 1 {
+                mosaic(&mut data, w, h, stride, size);
+            }
+        }
+    }
+    drop(data);
+    surface.mark_dirty();
+}
+
+// amount: -1.0 (black) .. 0.0 (unchanged) .. 1.0 (twice as bright)
+fn brightness(data: &mut [u8], amount: f64) {
+    let factor = (1.0 + amount).max(0.0);
+    for px in data.chunks_exact_mut(4) {
+        let argb = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+        let a = argb >> 24;
+        let scale = |c: u32| ((c as f64 * factor).round() as u32).min(a);
+        let r = scale((argb >> 16) & 0xff);
+        let g = scale((argb >> 8) & 0xff);
+        let b = scale(argb & 0xff);
+        px.copy_from_slice(&((a << 24) | (r << 16) | (g << 8) | b).to_ne_bytes());
+    }
+}
+
+// Separable box blur on premultiplied pixels (horizontal then vertical)
+fn box_blur(data: &mut [u8], w: usize, h: usize, stride: usize, radius: usize) {
+    let mut line = vec![[0u32; 4]; w.max(h)];
+    for y in 0..h {
+        for (x, px) in line[..w].iter_mut().enumerate() {
+            let i = y * stride + x * 4;
+            *px = [
+                data[i] as u32,
+                data[i + 1] as u32,
+                data[i + 2] as u32,
+                data[i + 3] as u32,
+            ];
+        }
+        blur_line(&line[..w], radius, |x, px| {
+            let i = y * stride + x * 4;
+            data[i..i + 4].copy_from_slice(&px);
+        });
+    }
+    for x in 0..w {
+        for (y, px) in line[..h].iter_mut().enumerate() {
+            let i = y * stride + x * 4;
+            *px = [
+                data[i] as u32,
+                data[i + 1] as u32,
+                data[i + 2] as u32,
+                data[i + 3] as u32,
+            ];
+        }
+        blur_line(&line[..h], radius, |y, px| {
+            let i = y * stride + x * 4;
+            data[i..i + 4].copy_from_slice(&px);
+        });
+    }
+}
+
+fn blur_line(line: &[[u32; 4]], radius: usize, mut write: impl FnMut(usize, [u8; 4])) {
+    let n = line.len();
+    for i in 0..n {
+        let lo = i.saturating_sub(radius);
+        let hi = (i + radius).min(n - 1);
+        let mut sum = [0u32; 4];
+        for px in &line[lo..=hi] {
+            for c in 0..4 {
+                sum[c] += px[c];
+            }
+        }
+        let count = (hi - lo + 1) as u32;
+        write(i, sum.map(|s| (s / count) as u8));
+    }
+}
+
+fn mosaic(data: &mut [u8], w: usize, h: usize, stride: usize, size: usize) {
+    for by in (0..h).step_by(size) {
+        for bx in (0..w).step_by(size) {
+            let (ey, ex) = ((by + size).min(h), (bx + size).min(w));
+            let mut sum = [0u32; 4];
+            for y in by..ey {
+                for x in bx..ex {
+                    let i = y * stride + x * 4;
+                    for c in 0..4 {
+                        sum[c] += data[i + c] as u32;
+                    }
+                }
+            }
+            let count = ((ey - by) * (ex - bx)) as u32;
+            let avg = sum.map(|s| (s / count) as u8);
+            for y in by..ey {
+                for x in bx..ex {
+                    let i = y * stride + x * 4;
+                    data[i..i + 4].copy_from_slice(&avg);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,600 @@
+// ファイル / レンダーキュー: exports run one after another on a worker thread
+// while editing continues. Each job renders a copy of the project taken when it
+// was queued, and the queue is saved so it survives a restart.
+
+use crate::config::{self, ConfigFile};
+use crate::encoder::{self, EncoderOptions};
+use crate::export::{self, ExportJob, ExportRange};
+use crate::project::Project;
+use crate::project_file;
+use crate::timeline::Frame;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    Waiting,
+    Running,
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobState::Waiting => "待機中",
+            JobState::Running => "書き出し中",
+            JobState::Paused => "一時停止",
+            JobState::Done => "完了",
+            JobState::Failed => "失敗",
+            JobState::Cancelled => "キャンセル",
+        }
+    }
+
+    // Has a worker thread
+    pub fn is_active(self) -> bool {
+        matches!(self, JobState::Running | JobState::Paused)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueueJob {
+    pub id: u64,
+    pub project_name: String,
+    // Copy of the project as it was when queued (see config::queue_file)
+    pub snapshot: PathBuf,
+    pub range: ExportRange,
+    // encoder::Backend id
+    pub backend: String,
+    pub options: EncoderOptions,
+    pub dir: PathBuf,
+    pub name: String,
+    pub state: JobState,
+    pub done: Frame,
+    // The encoder's file, known once the job has started
+    pub output: Option<PathBuf>,
+    pub log: Vec<String>,
+}
+
+impl QueueJob {
+    pub fn total(&self) -> Frame {
+        (self.range.end - self.range.start).max(0)
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        encoder::backends()
+            .into_iter()
+            .find(|b| b.id == self.backend)
+            .map_or("?", |b| b.name)
+    }
+
+    // Where the file goes; the extension is up to the backend until it has started
+    pub fn output_label(&self) -> String {
+        match &self.output {
+            Some(path) => path.display().to_string(),
+            None => self
+                .dir
+                .join(format!("{}.*", self.name))
+                .display()
+                .to_string(),
+        }
+    }
+}
+
+// A log line with the time it was written
+fn stamp(text: &str) -> String {
+    let time = glib::DateTime::now_local()
+        .and_then(|t| t.format("%H:%M:%S"))
+        .map_or(String::new(), |t| t.to_string());
+    format!("{} {}", time, text)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueueFile {
+    next_id: u64,
+    jobs: Vec<QueueJob>,
+}
+
+// Worker thread → queue
+enum Event {
+    Started(PathBuf),
+    Progress(Frame),
+    Log(String),
+    Finished(JobState),
+}
+
+// Queue → worker thread, checked between frames
+#[derive(Default)]
+struct Control {
+    pause: AtomicBool,
+    cancel: AtomicBool,
+}
+
+struct Worker {
+    id: u64,
+    control: Arc<Control>,
+    events: Receiver<Event>,
+    thread: JoinHandle<()>,
+}
+
+// Renders one job; everything it touches is created on this thread
+fn work(job: QueueJob, control: Arc<Control>, events: Sender<Event>) {
+    let send = |event: Event| {
+        let _ = events.send(event);
+    };
+    let log = |text: String| send(Event::Log(stamp(&text)));
+    let fail = |text: String| {
+        log(text);
+        send(Event::Finished(JobState::Failed));
+    };
+
+    let project = match project_file::load(&job.snapshot) {
+        Ok(project) => project,
+        Err(e) => return fail(format!("プロジェクトを読めません: {}", e)),
+    };
+    let missing = export::missing_media(&project, job.range.start, job.range.end);
+    if !missing.is_empty() {
+        for path in &missing {
+            log(format!("素材ファイルが見つかりません: {}", path.display()));
+        }
+        return fail("素材ファイルが足りないため中止しました".to_string());
+    }
+    let backends = encoder::backends();
+    let Some(backend) = backends.iter().find(|b| b.id == job.backend) else {
+        return fail(format!("形式 {} は使えません", job.backend));
+    };
+    let mut export = match ExportJob::new(
+        &project,
+        job.range.clone(),
+        backend,
+        &job.options,
+        job.dir.clone(),
+        job.name.clone(),
+    ) {
+        Ok(export) => export,
+        Err(e) => return fail(e.to_string()),
+    };
+
+    log(format!("開始: {}", export.output().display()));
+    send(Event::Started(export.output()));
+    let started = Instant::now();
+    loop {
+        if control.cancel.load(Ordering::Relaxed) {
+            // Leave a playable file with the frames written so far
+            if let Err(e) = export.cancel() {
+                return fail(e.to_string());
+            }
+            log(format!(
+                "キャンセルしました ({} / {} フレームを書き出し済み)",
+                export.done(),
+                export.total()
+            ));
+            return send(Event::Finished(JobState::Cancelled));
+        }
+        if control.pause.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        match export.step() {
+            Ok(true) => send(Event::Progress(export.done())),
+            Ok(false) => break,
+            Err(e) => return fail(e.to_string()),
+        }
+    }
+    log(format!(
+        "{} フレームを {:.1} 秒で書き出しました",
+        export.total(),
+        started.elapsed().as_secs_f64()
+    ));
+    send(Event::Finished(JobState::Done));
+}
+
+// What `poll` saw
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Update {
+    None,
+    Changed,
+    // The last waiting job has ended; counts of jobs done / failed in this run
+    Finished { done: usize, failed: usize },
+}
+
+pub struct RenderQueue {
+    pub jobs: Vec<QueueJob>,
+    next_id: u64,
+    // Off after a restart until 開始 is pressed, so leftover jobs don't start
+    // on their own
+    active: bool,
+    worker: Option<Worker>,
+    run_done: usize,
+    run_failed: usize,
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self {
+            jobs: Vec::new(),
+            next_id: 1,
+            active: true,
+            worker: None,
+            run_done: 0,
+            run_failed: 0,
+        }
+    }
+}
+
+impl RenderQueue {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Turning it off lets the running job finish but starts no more
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn job(&self, id: u64) -> Option<&QueueJob> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    fn job_mut(&mut self, id: u64) -> Option<&mut QueueJob> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    // Queue an export of `project` as it is now
+    pub fn add(
+        &mut self,
+        project: &Project,
+        range: ExportRange,
+        backend: &str,
+        options: &EncoderOptions,
+        dir: PathBuf,
+        name: String,
+    ) -> Result<u64, String> {
+        let id = self.next_id;
+        let snapshot = config::queue_file(&format!("{}.{}", id, project_file::EXTENSION));
+        if let Some(parent) = snapshot.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        project_file::save(project, &snapshot).map_err(|e| e.to_string())?;
+        self.next_id += 1;
+        self.jobs.push(QueueJob {
+            id,
+            project_name: project.name.clone(),
+            snapshot,
+            range,
+            backend: backend.to_string(),
+            options: options.clone(),
+            dir,
+            name,
+            state: JobState::Waiting,
+            done: 0,
+            output: None,
+            log: vec![stamp("キューに追加しました")],
+        });
+        self.active = true;
+        self.save_user();
+        Ok(id)
+    }
+
+    pub fn set_paused(&mut self, id: u64, paused: bool) {
+        let Some(worker) = self.worker.as_ref().filter(|w| w.id == id) else {
+            return;
+        };
+        worker.control.pause.store(paused, Ordering::Relaxed);
+        if let Some(job) = self.job_mut(id) {
+            job.state = if paused {
+                JobState::Paused
+            } else {
+                JobState::Running
+            };
+            job.log
+                .push(stamp(if paused { "一時停止" } else { "再開" }));
+        }
+        self.save_user();
+    }
+
+    // A running job stops after its current frame (see `poll`)
+    pub fn cancel(&mut self, id: u64) {
+        if let Some(worker) = self.worker.as_ref().filter(|w| w.id == id) {
+            worker.control.cancel.store(true, Ordering::Relaxed);
+            return;
+        }
+        if let Some(job) = self.job_mut(id)
+            && job.state == JobState::Waiting
+        {
+            job.state = JobState::Cancelled;
+            job.log.push(stamp("キャンセルしました"));
+            self.save_user();
+        }
+    }
+
+    // Run an ended job again from the start
+    pub fn retry(&mut self, id: u64) {
+        if let Some(job) = self.job_mut(id)
+            && !job.state.is_active()
+            && job.state != JobState::Waiting
+        {
+            job.state = JobState::Waiting;
+            job.done = 0;
+            job.output = None;
+            job.log.push(stamp("再試行します"));
+            self.active = true;
+            self.save_user();
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let Some(index) = self.jobs.iter().position(|j| j.id == id) else {
+            return;
+        };
+        if self.jobs[index].state.is_active() {
+            return;
+        }
+        let job = self.jobs.remove(index);
+        let _ = fs::remove_file(&job.snapshot);
+        self.save_user();
+    }
+
+    pub fn clear_finished(&mut self) {
+        let ended: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|j| matches!(j.state, JobState::Done | JobState::Cancelled))
+            .map(|j| j.id)
+            .collect();
+        for id in ended {
+            self.remove(id);
+        }
+    }
+
+    fn start_next(&mut self) -> bool {
+        let Some(job) = self.jobs.iter_mut().find(|j| j.state == JobState::Waiting) else {
+            return false;
+        };
+        job.state = JobState::Running;
+        job.done = 0;
+        let control = Arc::new(Control::default());
+        let (sender, events) = mpsc::channel();
+        let thread = {
+            let (job, control) = (job.clone(), control.clone());
+            thread::spawn(move || work(job, control, sender))
+        };
+        self.worker = Some(Worker {
+            id: job.id,
+            control,
+            events,
+            thread,
+        });
+        self.save_user();
+        true
+    }
+
+    // Call regularly from the main loop: picks up the worker's progress and
+    // starts the next job when it is done
+    pub fn poll(&mut self) -> Update {
+        let mut update = Update::None;
+        if let Some(worker) = &self.worker {
+            let id = worker.id;
+            let mut ended = None;
+            loop {
+                let event = match worker.events.try_recv() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => break,
+                    // The thread is gone without saying how it ended
+                    Err(TryRecvError::Disconnected) => {
+                        if ended.is_none() {
+                            ended = Some(JobState::Failed);
+                            if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+                                job.log.push(stamp("書き出しが異常終了しました"));
+                            }
+                        }
+                        break;
+                    }
+                };
+                let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) else {
+                    continue;
+                };
+                match event {
+                    Event::Started(output) => job.output = Some(output),
+                    Event::Progress(done) => job.done = done,
+                    Event::Log(line) => job.log.push(line),
+                    Event::Finished(state) => ended = Some(state),
+                }
+                update = Update::Changed;
+            }
+            if let Some(state) = ended {
+                if let Some(worker) = self.worker.take() {
+                    let _ = worker.thread.join();
+                }
+                if let Some(job) = self.job_mut(id) {
+                    job.state = state;
+                }
+                match state {
+                    JobState::Done => self.run_done += 1,
+                    JobState::Failed => self.run_failed += 1,
+                    _ => {}
+                }
+                self.save_user();
+                update = Update::Changed;
+            }
+        }
+
+        if self.worker.is_none() && self.active {
+            if self.start_next() {
+                return Update::Changed;
+            }
+            if self.run_done + self.run_failed > 0 {
+                update = Update::Finished {
+                    done: self.run_done,
+                    failed: self.run_failed,
+                };
+                self.run_done = 0;
+                self.run_failed = 0;
+            }
+        }
+        update
+    }
+}
+
+impl ConfigFile for RenderQueue {
+    const NAME: &'static str = "render_queue.json";
+    const WHAT: &'static str = "レンダーキュー";
+    const VERSION: u32 = 1;
+
+    type Document = QueueFile;
+
+    fn to_document(&self) -> QueueFile {
+        QueueFile {
+            next_id: self.next_id,
+            jobs: self.jobs.clone(),
+        }
+    }
+
+    // Jobs cut short by quitting go back to waiting
+    fn from_document(file: QueueFile) -> RenderQueue {
+        let mut queue = RenderQueue {
+            jobs: file.jobs,
+            next_id: file.next_id,
+            ..RenderQueue::default()
+        };
+        for job in &mut queue.jobs {
+            if job.state.is_active() {
+                job.state = JobState::Waiting;
+                job.done = 0;
+                job.log
+                    .push(stamp("終了時に中断されたので待機中に戻しました"));
+            }
+        }
+        queue.next_id = queue
+            .jobs
+            .iter()
+            .map(|j| j.id + 1)
+            .max()
+            .unwrap_or(1)
+            .max(queue.next_id);
+        queue.active = !queue.jobs.iter().any(|j| j.state == JobState::Waiting);
+        queue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: u64, state: JobState) -> QueueJob {
+        QueueJob {
+            id,
+            project_name: "p".to_string(),
+            snapshot: config::queue_file(&format!("test-{}.{}", id, project_file::EXTENSION)),
+            range: ExportRange {
+                start: 0,
+                end: 30,
+                scale: 1.0,
+                alpha: false,
+            },
+            backend: "y4m".to_string(),
+            options: EncoderOptions::default(),
+            dir: PathBuf::from("/tmp"),
+            name: format!("out{}", id),
+            state,
+            done: 10,
+            output: Some(PathBuf::from(format!("/tmp/out{}.y4m", id))),
+            log: Vec::new(),
+        }
+    }
+
+    const STATES: [JobState; 6] = [
+        JobState::Waiting,
+        JobState::Running,
+        JobState::Paused,
+        JobState::Done,
+        JobState::Failed,
+        JobState::Cancelled,
+    ];
+
+    // One job in every state, ids 1 to 6, not yet started
+    fn queue() -> RenderQueue {
+        RenderQueue {
+            jobs: (1..)
+                .zip(STATES)
+                .map(|(id, state)| job(id, state))
+                .collect(),
+            next_id: 7,
+            active: false,
+            ..RenderQueue::default()
+        }
+    }
+
+    fn states(queue: &RenderQueue) -> Vec<JobState> {
+        queue.jobs.iter().map(|j| j.state).collect()
+    }
+
+    #[test]
+    fn only_ended_jobs_are_retried() {
+        let mut queue = queue();
+        for id in 1..=6 {
+            queue.retry(id);
+        }
+        use JobState::*;
+        assert_eq!(
+            states(&queue),
+            [Waiting, Running, Paused, Waiting, Waiting, Waiting]
+        );
+        for job in &queue.jobs[3..] {
+            assert_eq!((job.done, &job.output), (0, &None));
+        }
+        // Running and paused jobs keep their progress
+        assert_eq!(queue.jobs[1].done, 10);
+        assert!(queue.is_active());
+    }
+
+    #[test]
+    fn a_waiting_job_is_cancelled_at_once() {
+        let mut queue = queue();
+        queue.cancel(1);
+        queue.cancel(4);
+        assert_eq!(queue.jobs[0].state, JobState::Cancelled);
+        assert_eq!(queue.jobs[3].state, JobState::Done);
+        assert!(queue.jobs[3].log.is_empty());
+    }
+
+    #[test]
+    fn loading_puts_interrupted_jobs_back_to_waiting() {
+        let mut saved = queue();
+        // An id below the jobs' must not be handed out again
+        saved.next_id = 2;
+        let queue = RenderQueue::parse(&saved.to_json().unwrap()).unwrap();
+        use JobState::*;
+        assert_eq!(
+            states(&queue),
+            [Waiting, Waiting, Waiting, Done, Failed, Cancelled]
+        );
+        assert_eq!((queue.jobs[1].done, queue.jobs[2].done), (0, 0));
+        assert_eq!(queue.jobs[3].done, 10);
+        assert_eq!(queue.next_id, 7);
+        // Leftover jobs wait for 開始
+        assert!(!queue.is_active());
+
+        let mut ended = RenderQueue::default();
+        ended.jobs.push(job(3, Done));
+        let ended = RenderQueue::parse(&ended.to_json().unwrap()).unwrap();
+        assert_eq!(ended.next_id, 4);
+        assert!(ended.is_active());
+    }
+
+    #[test]
+    fn clearing_keeps_failed_and_unfinished_jobs() {
+        let mut queue = queue();
+        queue.clear_finished();
+        let ids: Vec<u64> = queue.jobs.iter().map(|j| j.id).collect();
+        assert_eq!(ids, [1, 2, 3, 5]);
+    }
+}
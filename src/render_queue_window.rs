@@ -0,0 +1,254 @@
+// ファイル / レンダーキュー: queued exports with their progress, controls and logs.
+// The window is kept while the app runs; closing it only hides it.
+
+use crate::render_queue::{JobState, QueueJob, RenderQueue};
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box as GtkBox, Button, Expander, Label, ListBox, Orientation, ProgressBar,
+    ScrolledWindow, SelectionMode,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub struct QueueWindow {
+    window: gtk4::Window,
+    queue: Rc<RefCell<RenderQueue>>,
+    toggle: Button,
+    summary: Label,
+    list: ListBox,
+    shown: RefCell<Shape>,
+    // Progress bars of the rows, updated in place while a job runs
+    bars: RefCell<Vec<(u64, ProgressBar)>>,
+    // Jobs whose log is open, kept across rebuilds
+    expanded: RefCell<HashSet<u64>>,
+}
+
+// Job id, state and log length of each row; the rows are rebuilt when it changes
+type Shape = Vec<(u64, JobState, usize)>;
+
+fn shape(queue: &RenderQueue) -> Shape {
+    queue
+        .jobs
+        .iter()
+        .map(|j| (j.id, j.state, j.log.len()))
+        .collect()
+}
+
+fn set_progress(bar: &ProgressBar, job: &QueueJob) {
+    let total = job.total().max(1);
+    bar.set_fraction(job.done as f64 / total as f64);
+    bar.set_text(Some(&match job.state {
+        JobState::Waiting => job.state.name().to_string(),
+        _ => format!("{} / {}", job.done, job.total()),
+    }));
+}
+
+impl QueueWindow {
+    pub fn new(
+        parent: &impl IsA<gtk4::Window>,
+        queue: Rc<RefCell<RenderQueue>>,
+    ) -> Rc<QueueWindow> {
+        let window = gtk4::Window::builder()
+            .title("レンダーキュー")
+            .transient_for(parent)
+            .hide_on_close(true)
+            .default_width(560)
+            .default_height(420)
+            .build();
+
+        let content = GtkBox::new(Orientation::Vertical, 8);
+        content.set_margin_top(12);
+        content.set_margin_bottom(12);
+        content.set_margin_start(12);
+        content.set_margin_end(12);
+
+        let toolbar = GtkBox::new(Orientation::Horizontal, 6);
+        let toggle = Button::new();
+        let clear = Button::with_label("完了したものを削除");
+        let summary = Label::new(None);
+        summary.set_hexpand(true);
+        summary.set_halign(Align::End);
+        summary.add_css_class("dim-label");
+        toolbar.append(&toggle);
+        toolbar.append(&clear);
+        toolbar.append(&summary);
+        content.append(&toolbar);
+
+        let list = ListBox::new();
+        list.set_selection_mode(SelectionMode::None);
+        list.set_placeholder(Some(&Label::new(Some(
+            "キューは空です。書き出しダイアログの「キューに追加」で追加できます",
+        ))));
+        let scrolled = ScrolledWindow::builder()
+            .child(&list)
+            .hexpand(true)
+            .vexpand(true)
+            .build();
+        content.append(&scrolled);
+        window.set_child(Some(&content));
+
+        let this = Rc::new(QueueWindow {
+            window,
+            queue,
+            toggle,
+            summary,
+            list,
+            shown: RefCell::new(Vec::new()),
+            bars: RefCell::new(Vec::new()),
+            expanded: RefCell::new(HashSet::new()),
+        });
+        {
+            let this_for_toggle = this.clone();
+            this.toggle.connect_clicked(move |_| {
+                let active = this_for_toggle.queue.borrow().is_active();
+                this_for_toggle.queue.borrow_mut().set_active(!active);
+                this_for_toggle.refresh();
+            });
+        }
+        {
+            let this = this.clone();
+            clear.connect_clicked(move |_| {
+                this.queue.borrow_mut().clear_finished();
+                this.refresh();
+            });
+        }
+        this.refresh();
+        this
+    }
+
+    pub fn queue(&self) -> &Rc<RefCell<RenderQueue>> {
+        &self.queue
+    }
+
+    pub fn present(self: &Rc<Self>) {
+        self.refresh();
+        self.window.present();
+    }
+
+    pub fn refresh(self: &Rc<Self>) {
+        let queue = self.queue.borrow();
+        let waiting = queue
+            .jobs
+            .iter()
+            .filter(|j| j.state == JobState::Waiting)
+            .count();
+        self.summary
+            .set_text(&format!("{} 件 (待機中 {} 件)", queue.jobs.len(), waiting));
+        self.toggle.set_label(if queue.is_active() {
+            "キューを停止"
+        } else {
+            "キューを開始"
+        });
+
+        let current = shape(&queue);
+        if *self.shown.borrow() == current {
+            for (id, bar) in self.bars.borrow().iter() {
+                if let Some(job) = queue.job(*id) {
+                    set_progress(bar, job);
+                }
+            }
+            return;
+        }
+        while let Some(row) = self.list.first_child() {
+            self.list.remove(&row);
+        }
+        let mut bars = Vec::new();
+        for job in &queue.jobs {
+            let (row, bar) = self.job_row(job);
+            self.list.append(&row);
+            bars.push((job.id, bar));
+        }
+        *self.bars.borrow_mut() = bars;
+        *self.shown.borrow_mut() = current;
+    }
+
+    // One job: what it writes, its progress, the buttons that apply to its state
+    // and the log
+    fn job_row(self: &Rc<Self>, job: &QueueJob) -> (GtkBox, ProgressBar) {
+        let row = GtkBox::new(Orientation::Vertical, 4);
+        row.set_margin_top(6);
+        row.set_margin_bottom(6);
+        row.set_margin_start(6);
+        row.set_margin_end(6);
+
+        let header = GtkBox::new(Orientation::Horizontal, 6);
+        let title = Label::new(Some(&format!(
+            "{} — {}",
+            job.project_name,
+            job.backend_name()
+        )));
+        title.set_hexpand(true);
+        title.set_halign(Align::Start);
+        title.add_css_class("heading");
+        let state = Label::new(Some(job.state.name()));
+        if job.state == JobState::Failed {
+            state.add_css_class("error");
+        }
+        header.append(&title);
+        header.append(&state);
+        row.append(&header);
+
+        let detail = Label::new(Some(&format!(
+            "フレーム {}〜{} ({} フレーム) → {}",
+            job.range.start,
+            job.range.end - 1,
+            job.total(),
+            job.output_label()
+        )));
+        detail.set_halign(Align::Start);
+        detail.set_ellipsize(gtk4::pango::EllipsizeMode::Start);
+        detail.add_css_class("dim-label");
+        row.append(&detail);
+
+        let bar = ProgressBar::new();
+        bar.set_show_text(true);
+        set_progress(&bar, job);
+        row.append(&bar);
+
+        let buttons = GtkBox::new(Orientation::Horizontal, 6);
+        let id = job.id;
+        let button = |label: &str, action: fn(&mut RenderQueue, u64)| {
+            let button = Button::with_label(label);
+            let this = self.clone();
+            button.connect_clicked(move |_| {
+                action(&mut this.queue.borrow_mut(), id);
+                this.refresh();
+            });
+            buttons.append(&button);
+        };
+        match job.state {
+            JobState::Running => button("一時停止", |q, id| q.set_paused(id, true)),
+            JobState::Paused => button("再開", |q, id| q.set_paused(id, false)),
+            _ => {}
+        }
+        if job.state.is_active() || job.state == JobState::Waiting {
+            button("キャンセル", RenderQueue::cancel);
+        } else {
+            button("再試行", RenderQueue::retry);
+            button("削除", RenderQueue::remove);
+        }
+        row.append(&buttons);
+
+        let log = Label::new(Some(&job.log.join("\n")));
+        log.set_halign(Align::Start);
+        log.set_wrap(true);
+        log.set_selectable(true);
+        log.add_css_class("monospace");
+        let expander = Expander::new(Some("ログ"));
+        expander.set_child(Some(&log));
+        expander.set_expanded(self.expanded.borrow().contains(&id));
+        {
+            let this = self.clone();
+            expander.connect_expanded_notify(move |expander| {
+                if expander.is_expanded() {
+                    this.expanded.borrow_mut().insert(id);
+                } else {
+                    this.expanded.borrow_mut().remove(&id);
+                }
+            });
+        }
+        row.append(&expander);
+        (row, bar)
+    }
+}
@@ -0,0 +1,252 @@
+// Time ruler between the preview and layer 1
+
+use crate::project::FrameRate;
+use crate::timeline::Frame;
+use cairo::Context;
+
+pub const RULER_HEIGHT: f64 = 22.0;
+
+// Labels need about this much room
+const MIN_MAJOR_SPACING: f64 = 90.0;
+const MIN_MINOR_SPACING: f64 = 8.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    Frames,
+    #[default]
+    Timecode,
+    Seconds,
+}
+
+impl TimeFormat {
+    // Clicking the ruler's label column cycles through the formats
+    pub fn next(self) -> Self {
+        match self {
+            TimeFormat::Frames => TimeFormat::Timecode,
+            TimeFormat::Timecode => TimeFormat::Seconds,
+            TimeFormat::Seconds => TimeFormat::Frames,
+        }
+    }
+
+    pub fn short_name(self) -> &'static str {
+        match self {
+            TimeFormat::Frames => "F",
+            TimeFormat::Timecode => "TC",
+            TimeFormat::Seconds => "秒",
+        }
+    }
+
+    pub const ALL: [TimeFormat; 3] = [
+        TimeFormat::Frames,
+        TimeFormat::Timecode,
+        TimeFormat::Seconds,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeFormat::Frames => "フレーム",
+            TimeFormat::Timecode => "タイムコード",
+            TimeFormat::Seconds => "秒",
+        }
+    }
+
+    // Stable identifier for menus / action state
+    pub fn key(self) -> &'static str {
+        match self {
+            TimeFormat::Frames => "frames",
+            TimeFormat::Timecode => "timecode",
+            TimeFormat::Seconds => "seconds",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<TimeFormat> {
+        Self::ALL.into_iter().find(|f| f.key() == key)
+    }
+}
+
+// Frames counted per timecode second (30 for 29.97)
+pub fn nominal_fps(rate: FrameRate) -> Frame {
+    ((rate.num as f64 / rate.den as f64).round() as Frame).max(1)
+}
+
+// 29.97 and 59.94 use drop-frame timecode
+fn dropped_frames(rate: FrameRate) -> Frame {
+    if rate.den == 1001 && rate.num.is_multiple_of(30000) {
+        2 * (rate.num / 30000) as Frame
+    } else {
+        0
+    }
+}
+
+pub fn format_time(frame: Frame, rate: FrameRate, format: TimeFormat) -> String {
+    match format {
+        TimeFormat::Frames => frame.to_string(),
+        TimeFormat::Timecode => timecode(frame, rate),
+        TimeFormat::Seconds => format!("{:.2}s", rate.frame_to_seconds(frame)),
+    }
+}
+
+// HH:MM:SS:FF (HH:MM:SS;FF for drop-frame)
+pub fn timecode(frame: Frame, rate: FrameRate) -> String {
+    let fps = nominal_fps(rate);
+    let drop = dropped_frames(rate);
+    let mut frame = frame.max(0);
+    let separator = if drop > 0 {
+        // Skip frame numbers 0 and 1 (0-3 at 59.94) at the start of each minute,
+        // except every tenth minute
+        let per_10_minutes = fps * 600 - drop * 9;
+        let per_minute = fps * 60 - drop;
+        let tens = frame / per_10_minutes;
+        let rest = frame % per_10_minutes;
+        frame += drop * 9 * tens;
+        if rest > drop {
+            frame += drop * ((rest - drop) / per_minute);
+        }
+        ';'
+    } else {
+        ':'
+    };
+
+    let ff = frame % fps;
+    let total_seconds = frame / fps;
+    format!(
+        "{:02}:{:02}:{:02}{}{:02}",
+        total_seconds / 3600,
+        total_seconds / 60 % 60,
+        total_seconds % 60,
+        separator,
+        ff
+    )
+}
+
+// (major, minor) tick interval in frames for the current zoom
+pub fn tick_spacing(rate: FrameRate, pixels_per_frame: f64) -> (Frame, Frame) {
+    let fps = nominal_fps(rate);
+    let mut steps: Vec<Frame> = [1, 2, 5, 10, 15]
+        .iter()
+        .copied()
+        .filter(|&f| f < fps)
+        .collect();
+    for seconds in [1, 2, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600] {
+        steps.push(seconds * fps);
+    }
+
+    let major = steps
+        .iter()
+        .copied()
+        .find(|&f| f as f64 * pixels_per_frame >= MIN_MAJOR_SPACING)
+        .unwrap_or(steps[steps.len() - 1]);
+    let minor = steps
+        .iter()
+        .copied()
+        .filter(|&f| f < major && major % f == 0)
+        .find(|&f| f as f64 * pixels_per_frame >= MIN_MINOR_SPACING)
+        .unwrap_or(major);
+    (major, minor)
+}
+
+// Draw the ruler strip; `origin` is the x of frame 0
+#[allow(clippy::too_many_arguments)]
+pub fn draw_ruler(
+    cr: &Context,
+    y: f64,
+    left: f64,
+    right: f64,
+    origin: f64,
+    pixels_per_frame: f64,
+    rate: FrameRate,
+    format: TimeFormat,
+) {
+    cr.save().unwrap();
+    cr.set_source_rgb(0.16, 0.16, 0.16);
+    cr.rectangle(0.0, y, right, RULER_HEIGHT);
+    cr.fill().unwrap();
+
+    // Current format in the label column
+    cr.set_source_rgb(0.6, 0.6, 0.6);
+    cr.set_font_size(11.0);
+    cr.move_to(6.0, y + 15.0);
+    cr.show_text(format.short_name()).unwrap();
+
+    cr.rectangle(left, y, right - left, RULER_HEIGHT);
+    cr.clip();
+
+    let (major, minor) = tick_spacing(rate, pixels_per_frame);
+    let first = (((left - origin) / pixels_per_frame).floor() as Frame).max(0);
+    let last = ((right - origin) / pixels_per_frame).ceil() as Frame;
+    let mut frame = first - first % minor;
+    cr.set_line_width(1.0);
+    while frame <= last {
+        let x = (origin + frame as f64 * pixels_per_frame).floor() + 0.5;
+        let is_major = frame % major == 0;
+        let tick = if is_major { RULER_HEIGHT } else { 5.0 };
+        cr.set_source_rgb(0.5, 0.5, 0.5);
+        cr.move_to(x, y + RULER_HEIGHT - tick);
+        cr.line_to(x, y + RULER_HEIGHT);
+        cr.stroke().unwrap();
+
+        if is_major {
+            cr.set_source_rgb(0.8, 0.8, 0.8);
+            cr.move_to(x + 3.0, y + 11.0);
+            cr.show_text(&format_time(frame, rate, format)).unwrap();
+        }
+        frame += minor;
+    }
+
+    cr.restore().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC: FrameRate = FrameRate::new(30000, 1001);
+
+    #[test]
+    fn drop_frame_timecode_skips_two_numbers_a_minute() {
+        let tc = |frame| format_time(frame, NTSC, TimeFormat::Timecode);
+        assert_eq!(tc(0), "00:00:00;00");
+        assert_eq!(tc(1799), "00:00:59;29");
+        assert_eq!(tc(1800), "00:01:00;02");
+        // Except every tenth minute
+        assert_eq!(tc(17981), "00:09:59;29");
+        assert_eq!(tc(17982), "00:10:00;00");
+        assert_eq!(tc(-5), "00:00:00;00");
+    }
+
+    #[test]
+    fn drop_frame_at_59_94_skips_four() {
+        let rate = FrameRate::new(60000, 1001);
+        assert_eq!(timecode(3599, rate), "00:00:59;59");
+        assert_eq!(timecode(3600, rate), "00:01:00;04");
+    }
+
+    #[test]
+    fn whole_rates_count_every_frame() {
+        let rate = FrameRate::new(30, 1);
+        let tc = |frame| format_time(frame, rate, TimeFormat::Timecode);
+        assert_eq!(tc(1799), "00:00:59:29");
+        assert_eq!(tc(1800), "00:01:00:00");
+        assert_eq!(tc(30 * 3600 + 1), "01:00:00:01");
+        assert_eq!(format_time(45, rate, TimeFormat::Frames), "45");
+        assert_eq!(format_time(45, rate, TimeFormat::Seconds), "1.50s");
+    }
+
+    #[test]
+    fn ticks_follow_the_zoom() {
+        let rate = FrameRate::new(30, 1);
+        // Zoomed in: every 10 frames, minor ticks on every frame
+        assert_eq!(tick_spacing(rate, 10.0), (10, 1));
+        // 5 seconds, minor ticks every 10 frames
+        assert_eq!(tick_spacing(rate, 1.0), (150, 10));
+        // 5 minutes, minor ticks every 30 seconds
+        assert_eq!(tick_spacing(rate, 0.01), (9000, 900));
+        // Past the largest step the hour stays, without minor ticks
+        assert_eq!(tick_spacing(rate, 0.0001), (108_000, 108_000));
+        // Majors are always a multiple of the minors
+        for ppf in [0.003, 0.05, 0.3, 2.0, 7.0, 40.0] {
+            let (major, minor) = tick_spacing(NTSC, ppf);
+            assert_eq!(major % minor, 0, "{}", ppf);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// User preferences (設定 / 環境設定), stored as settings.json in the config dir
+
+use crate::config::{ConfigFile, Migration};
+use crate::history::DEFAULT_HISTORY_LIMIT;
+use crate::import::ImportOptions;
+use crate::project::{FrameRate, OutputSettings};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// v1 -> v2: the fixed preview height (px) became the split ratio of the window
+fn preview_height_to_ratio(document: &mut Value) {
+    let Value::Object(fields) = document else {
+        return;
+    };
+    if let Some(height) = fields.remove("preview_height").and_then(|v| v.as_f64()) {
+        let window = fields
+            .get("window_height")
+            .and_then(Value::as_f64)
+            .unwrap_or(600.0);
+        fields.insert("preview_ratio".to_string(), (height / window).into());
+    }
+}
+
+// Missing fields take their default, so older files keep loading
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // Window layout, saved on exit and restored on launch
+    pub window_width: i32,
+    pub window_height: i32,
+    pub window_maximized: bool,
+    // Preview / timeline split, as a fraction of the height
+    pub preview_ratio: f64,
+    // Preview / inspector split, as a fraction of the width
+    pub inspector_ratio: f64,
+    pub layer_height: f64,
+    // Default object lengths
+    pub image_seconds: f64,
+    pub object_seconds: f64,
+    // Decoded still images kept in memory
+    pub image_cache_mb: u32,
+    pub history_limit: usize,
+    // 0 = off
+    pub autosave_minutes: u32,
+    // Output settings of new projects
+    pub project_width: u32,
+    pub project_height: u32,
+    pub project_frame_rate: FrameRate,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let output = OutputSettings::default();
+        let import = ImportOptions::default();
+        Self {
+            window_width: 800,
+            window_height: 600,
+            window_maximized: false,
+            preview_ratio: 0.6,
+            inspector_ratio: 0.7,
+            layer_height: 30.0,
+            image_seconds: import.image_seconds,
+            object_seconds: import.object_seconds,
+            image_cache_mb: 512,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            autosave_minutes: 5,
+            project_width: output.width,
+            project_height: output.height,
+            project_frame_rate: output.frame_rate,
+        }
+    }
+}
+
+// Accepted range of every numeric setting; the preferences window uses the same bounds
+pub const WINDOW_WIDTH: (f64, f64) = (400.0, 7680.0);
+pub const WINDOW_HEIGHT: (f64, f64) = (300.0, 4320.0);
+pub const SPLIT_RATIO: (f64, f64) = (0.1, 0.9);
+pub const LAYER_HEIGHT: (f64, f64) = (16.0, 120.0);
+pub const OBJECT_SECONDS: (f64, f64) = (0.1, 3600.0);
+pub const IMAGE_CACHE_MB: (f64, f64) = (16.0, 16384.0);
+pub const HISTORY_LIMIT: (f64, f64) = (1.0, 10000.0);
+pub const AUTOSAVE_MINUTES: (f64, f64) = (0.0, 120.0);
+pub const PROJECT_SIZE: (f64, f64) = (16.0, 8192.0);
+pub const FRAME_RATE_NUM: (f64, f64) = (1.0, 240000.0);
+pub const FRAME_RATE_DEN: (f64, f64) = (1.0, 1001.0);
+
+fn clamp_f64(value: &mut f64, (min, max): (f64, f64), name: &str, fixed: &mut Vec<String>) {
+    // NaN fails both comparisons, so it is replaced by the minimum
+    let clamped = if *value >= min { value.min(max) } else { min };
+    if clamped != *value {
+        fixed.push(name.to_string());
+        *value = clamped;
+    }
+}
+
+fn clamp_int<T>(value: &mut T, range: (f64, f64), name: &str, fixed: &mut Vec<String>)
+where
+    T: Copy + Into<f64> + TryFrom<i64>,
+{
+    let mut v: f64 = (*value).into();
+    clamp_f64(&mut v, range, name, fixed);
+    if let Ok(v) = T::try_from(v as i64) {
+        *value = v;
+    }
+}
+
+impl Settings {
+    // Bring out-of-range values back into range. Returns the fields that were fixed.
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut fixed = Vec::new();
+        let f = &mut fixed;
+        clamp_int(&mut self.window_width, WINDOW_WIDTH, "window_width", f);
+        clamp_int(&mut self.window_height, WINDOW_HEIGHT, "window_height", f);
+        clamp_f64(&mut self.preview_ratio, SPLIT_RATIO, "preview_ratio", f);
+        clamp_f64(&mut self.inspector_ratio, SPLIT_RATIO, "inspector_ratio", f);
+        clamp_f64(&mut self.layer_height, LAYER_HEIGHT, "layer_height", f);
+        clamp_f64(&mut self.image_seconds, OBJECT_SECONDS, "image_seconds", f);
+        clamp_f64(
+            &mut self.object_seconds,
+            OBJECT_SECONDS,
+            "object_seconds",
+            f,
+        );
+        clamp_int(
+            &mut self.image_cache_mb,
+            IMAGE_CACHE_MB,
+            "image_cache_mb",
+            f,
+        );
+        let mut history_limit = self.history_limit as f64;
+        clamp_f64(&mut history_limit, HISTORY_LIMIT, "history_limit", f);
+        self.history_limit = history_limit as usize;
+        clamp_int(
+            &mut self.autosave_minutes,
+            AUTOSAVE_MINUTES,
+            "autosave_minutes",
+            f,
+        );
+        clamp_int(&mut self.project_width, PROJECT_SIZE, "project_width", f);
+        clamp_int(&mut self.project_height, PROJECT_SIZE, "project_height", f);
+        let rate = &mut self.project_frame_rate;
+        clamp_int(&mut rate.num, FRAME_RATE_NUM, "project_frame_rate", f);
+        clamp_int(&mut rate.den, FRAME_RATE_DEN, "project_frame_rate", f);
+        fixed.dedup();
+        fixed
+    }
+
+    pub fn import_options(&self) -> ImportOptions {
+        ImportOptions {
+            image_seconds: self.image_seconds,
+            object_seconds: self.object_seconds,
+        }
+    }
+
+    // Output settings for a new project
+    pub fn project_output(&self) -> OutputSettings {
+        OutputSettings {
+            width: self.project_width,
+            height: self.project_height,
+            frame_rate: self.project_frame_rate,
+            ..OutputSettings::default()
+        }
+    }
+
+    pub fn image_cache_bytes(&self) -> usize {
+        self.image_cache_mb as usize * 1024 * 1024
+    }
+}
+
+impl ConfigFile for Settings {
+    const NAME: &'static str = "settings.json";
+    const WHAT: &'static str = "設定";
+    const VERSION: u32 = 2;
+    const MIGRATIONS: &'static [Migration] = &[preview_height_to_ratio];
+
+    type Document = Settings;
+
+    fn to_document(&self) -> Settings {
+        self.clone()
+    }
+
+    fn from_document(mut settings: Settings) -> Settings {
+        let fixed = settings.validate();
+        if !fixed.is_empty() {
+            eprintln!("設定: 範囲外の値を修正しました: {}", fixed.join(", "));
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigError;
+
+    #[test]
+    fn version_1_preview_height_becomes_a_ratio() {
+        let text = r#"{"version": 1, "window_height": 1000, "preview_height": 300}"#;
+        let settings = Settings::parse(text).unwrap();
+        assert_eq!(settings.window_height, 1000);
+        assert!((settings.preview_ratio - 0.3).abs() < 1e-9);
+        // Files from before versioning are version 1 too
+        let text = r#"{"preview_height": 450}"#;
+        assert!((Settings::parse(text).unwrap().preview_ratio - 0.75).abs() < 1e-9);
+        // A version 2 file is not migrated again
+        let text = r#"{"version": 2, "preview_ratio": 0.4, "preview_height": 300}"#;
+        assert_eq!(Settings::parse(text).unwrap().preview_ratio, 0.4);
+    }
+
+    #[test]
+    fn out_of_range_values_are_brought_back() {
+        let mut settings = Settings {
+            preview_ratio: f64::NAN,
+            layer_height: 1000.0,
+            window_width: 10,
+            history_limit: 0,
+            ..Settings::default()
+        };
+        let fixed = settings.validate();
+        assert_eq!(
+            fixed,
+            [
+                "window_width",
+                "preview_ratio",
+                "layer_height",
+                "history_limit"
+            ]
+        );
+        assert_eq!(settings.window_width, WINDOW_WIDTH.0 as i32);
+        assert_eq!(settings.preview_ratio, SPLIT_RATIO.0);
+        assert_eq!(settings.layer_height, LAYER_HEIGHT.1);
+        assert_eq!(settings.history_limit, 1);
+        assert!(settings.validate().is_empty());
+
+        // The same happens on load
+        let text = r#"{"version": 2, "inspector_ratio": 5.0, "image_cache_mb": 1}"#;
+        let settings = Settings::parse(text).unwrap();
+        assert_eq!(settings.inspector_ratio, SPLIT_RATIO.1);
+        assert_eq!(settings.image_cache_mb, IMAGE_CACHE_MB.0 as u32);
+    }
+
+    #[test]
+    fn defaults_round_trip() {
+        let settings = Settings::default();
+        assert_eq!(
+            Settings::parse(&settings.to_json().unwrap()).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn newer_and_broken_versions_are_refused() {
+        assert!(matches!(
+            Settings::parse(r#"{"version": 3}"#),
+            Err(ConfigError::TooNew {
+                version: 3,
+                supported: 2,
+                ..
+            })
+        ));
+        for text in [r#"{"version": 0}"#, r#"{"version": "2"}"#] {
+            assert!(
+                matches!(Settings::parse(text), Err(ConfigError::BadVersion { .. })),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,326 @@
+// 設定 / キーボードショートカット: rebind the actions listed in actions::ACTIONS
+
+use crate::actions::{self, ACTIONS, Keymap, Preset};
+use crate::config::ConfigFile;
+use gtk4::gdk::Key;
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box as GtkBox, Button, DropDown, EventControllerKey, FileDialog, FileFilter, Label,
+    ListBox, Orientation, PropagationPhase, ScrolledWindow, SelectionMode,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Install the keymap as the application's accelerators
+pub fn apply_keymap(app: &gtk4::Application, keymap: &Keymap) {
+    for action in ACTIONS {
+        let accels: Vec<&str> = keymap
+            .accels(action.name)
+            .iter()
+            .map(String::as_str)
+            .collect();
+        app.set_accels_for_action(action.name, &accels);
+    }
+}
+
+// "Ctrl+Shift+Z" style text for an accelerator string
+pub fn accel_label(accel: &str) -> String {
+    match gtk4::accelerator_parse(accel) {
+        Some((key, mods)) => gtk4::accelerator_get_label(key, mods).to_string(),
+        None => accel.to_string(),
+    }
+}
+
+fn action_label(name: &str) -> &str {
+    actions::find(name).map_or(name, |a| a.label)
+}
+
+// One line per shortcut that is bound to several actions
+fn conflict_text(keymap: &Keymap) -> String {
+    keymap
+        .conflicts()
+        .iter()
+        .map(|(accel, names)| {
+            let names: Vec<String> = names
+                .iter()
+                .map(|n| format!("「{}」", action_label(n)))
+                .collect();
+            format!("重複: {} → {}", accel_label(accel), names.concat())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn json_filters() -> gio::ListStore {
+    let filter = FileFilter::new();
+    filter.set_name(Some("キー設定 (JSON)"));
+    filter.add_suffix("json");
+    let filters = gio::ListStore::new::<FileFilter>();
+    filters.append(&filter);
+    filters
+}
+
+pub fn show(parent: &impl IsA<gtk4::Window>, app: &gtk4::Application, keymap: Rc<RefCell<Keymap>>) {
+    // Deliberately not attached to the application so its shortcuts don't fire
+    // while keys are being captured here
+    let dialog = gtk4::Window::builder()
+        .title("キーボードショートカット")
+        .transient_for(parent)
+        .modal(true)
+        .default_width(560)
+        .default_height(640)
+        .build();
+
+    let content = GtkBox::new(Orientation::Vertical, 8);
+    content.set_margin_top(12);
+    content.set_margin_bottom(12);
+    content.set_margin_start(12);
+    content.set_margin_end(12);
+
+    let hint = Label::new(Some(
+        "「変更」を押してから新しいキーを押してください (Esc で取り消し)",
+    ));
+    hint.set_halign(Align::Start);
+    content.append(&hint);
+
+    // Action list, grouped by category
+    let list = ListBox::new();
+    list.set_selection_mode(SelectionMode::None);
+    let capturing: Rc<RefCell<Option<&'static str>>> = Rc::new(RefCell::new(None));
+    let mut key_labels = Vec::new();
+    let mut buttons = Vec::new();
+    let mut category = "";
+    for action in ACTIONS {
+        if action.category != category {
+            category = action.category;
+            let header = Label::new(None);
+            header.set_markup(&format!("<b>{}</b>", category));
+            header.set_halign(Align::Start);
+            header.set_margin_top(8);
+            list.append(&header);
+        }
+        let row = GtkBox::new(Orientation::Horizontal, 6);
+        let name = Label::new(Some(action.label));
+        name.set_halign(Align::Start);
+        name.set_hexpand(true);
+        let keys = Label::new(None);
+        keys.set_width_chars(20);
+        keys.set_xalign(0.0);
+        let change = Button::with_label("変更");
+        let clear = Button::with_label("解除");
+        row.append(&name);
+        row.append(&keys);
+        row.append(&change);
+        row.append(&clear);
+        list.append(&row);
+        key_labels.push((action.name, keys));
+        buttons.push((action.name, change, clear));
+    }
+    let scrolled = ScrolledWindow::builder().child(&list).vexpand(true).build();
+    content.append(&scrolled);
+
+    let status = Label::new(None);
+    status.set_halign(Align::Start);
+    status.set_wrap(true);
+    content.append(&status);
+
+    // Show the current keys; conflicting rows are marked
+    let refresh: Rc<dyn Fn()> = {
+        let (keymap, capturing) = (keymap.clone(), capturing.clone());
+        Rc::new(move || {
+            let keymap = keymap.borrow();
+            let conflicts = keymap.conflicts();
+            for (name, label) in &key_labels {
+                let text = if *capturing.borrow() == Some(*name) {
+                    "キーを押してください…".to_string()
+                } else {
+                    let keys: Vec<String> =
+                        keymap.accels(name).iter().map(|a| accel_label(a)).collect();
+                    if keys.is_empty() {
+                        "-".to_string()
+                    } else {
+                        keys.join(", ")
+                    }
+                };
+                label.set_text(&text);
+                if conflicts
+                    .iter()
+                    .any(|(_, names)| names.iter().any(|n| n == name))
+                {
+                    label.add_css_class("error");
+                } else {
+                    label.remove_css_class("error");
+                }
+            }
+        })
+    };
+
+    // Every change is applied and saved right away
+    let commit: Rc<dyn Fn()> = {
+        let (app, keymap, refresh) = (app.clone(), keymap.clone(), refresh.clone());
+        Rc::new(move || {
+            keymap.borrow().save_user();
+            apply_keymap(&app, &keymap.borrow());
+            refresh();
+        })
+    };
+
+    for (name, change, clear) in buttons {
+        {
+            let (capturing, refresh, status) = (capturing.clone(), refresh.clone(), status.clone());
+            change.connect_clicked(move |_| {
+                *capturing.borrow_mut() = Some(name);
+                status.set_text("");
+                refresh();
+            });
+        }
+        let (keymap, commit, status) = (keymap.clone(), commit.clone(), status.clone());
+        clear.connect_clicked(move |_| {
+            keymap.borrow_mut().clear(name);
+            status.set_text(&format!(
+                "「{}」のショートカットを解除しました",
+                action_label(name)
+            ));
+            commit();
+        });
+    }
+
+    // The next key pressed after 変更 becomes the shortcut
+    let keys = EventControllerKey::new();
+    keys.set_propagation_phase(PropagationPhase::Capture);
+    {
+        let (keymap, capturing) = (keymap.clone(), capturing.clone());
+        let (commit, refresh, status) = (commit.clone(), refresh.clone(), status.clone());
+        keys.connect_key_pressed(move |_, key, _, state| {
+            let Some(name) = *capturing.borrow() else {
+                return glib::Propagation::Proceed;
+            };
+            if key == Key::Escape {
+                *capturing.borrow_mut() = None;
+                refresh();
+                return glib::Propagation::Stop;
+            }
+            let mods = state & gtk4::accelerator_get_default_mod_mask();
+            let key = key.to_lower();
+            // Modifier pressed on its own: wait for the real key
+            if !gtk4::accelerator_valid(key, mods) {
+                return glib::Propagation::Stop;
+            }
+            let accel = gtk4::accelerator_name(key, mods);
+            *capturing.borrow_mut() = None;
+            let taken = keymap.borrow_mut().bind(name, &accel);
+            let mut message = format!(
+                "{} を「{}」に割り当てました",
+                accel_label(&accel),
+                action_label(name)
+            );
+            if !taken.is_empty() {
+                let names: Vec<String> = taken
+                    .iter()
+                    .map(|n| format!("「{}」", action_label(n)))
+                    .collect();
+                message += &format!(" ({}から外しました)", names.concat());
+            }
+            status.set_text(&message);
+            commit();
+            glib::Propagation::Stop
+        });
+    }
+    dialog.add_controller(keys);
+
+    // Presets and keymap files
+    let bottom = GtkBox::new(Orientation::Horizontal, 6);
+    let preset_names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+    let presets = DropDown::from_strings(&preset_names);
+    let apply_preset = Button::with_label("プリセットを適用");
+    let import = Button::with_label("読み込み…");
+    let export = Button::with_label("書き出し…");
+    let close = Button::with_label("閉じる");
+    close.set_hexpand(true);
+    close.set_halign(Align::End);
+    bottom.append(&presets);
+    bottom.append(&apply_preset);
+    bottom.append(&import);
+    bottom.append(&export);
+    bottom.append(&close);
+    content.append(&bottom);
+
+    {
+        let (keymap, commit, status) = (keymap.clone(), commit.clone(), status.clone());
+        apply_preset.connect_clicked(move |_| {
+            let preset = Preset::ALL[presets.selected() as usize % Preset::ALL.len()];
+            *keymap.borrow_mut() = Keymap::preset(preset);
+            status.set_text(&format!("{} を適用しました", preset.name()));
+            commit();
+        });
+    }
+    {
+        let dialog_for_import = dialog.clone();
+        let (keymap, commit, status) = (keymap.clone(), commit.clone(), status.clone());
+        import.connect_clicked(move |_| {
+            let file_dialog = FileDialog::builder()
+                .title("キー設定を読み込む")
+                .modal(true)
+                .filters(&json_filters())
+                .build();
+            let (keymap, commit, status) = (keymap.clone(), commit.clone(), status.clone());
+            file_dialog.open(
+                Some(&dialog_for_import),
+                gio::Cancellable::NONE,
+                move |result| {
+                    let Some(path) = result.ok().and_then(|file| file.path()) else {
+                        return;
+                    };
+                    match Keymap::load(&path) {
+                        Ok(loaded) => {
+                            let conflicts = conflict_text(&loaded);
+                            *keymap.borrow_mut() = loaded;
+                            if conflicts.is_empty() {
+                                status.set_text("読み込みました");
+                            } else {
+                                status.set_text(&format!("読み込みました\n{}", conflicts));
+                            }
+                            commit();
+                        }
+                        Err(e) => status.set_text(&format!("{}: {}", path.display(), e)),
+                    }
+                },
+            );
+        });
+    }
+    {
+        let dialog_for_export = dialog.clone();
+        let (keymap, status) = (keymap.clone(), status.clone());
+        export.connect_clicked(move |_| {
+            let file_dialog = FileDialog::builder()
+                .title("キー設定を書き出す")
+                .modal(true)
+                .initial_name("keymap.json")
+                .filters(&json_filters())
+                .build();
+            let (keymap, status) = (keymap.clone(), status.clone());
+            file_dialog.save(
+                Some(&dialog_for_export),
+                gio::Cancellable::NONE,
+                move |result| {
+                    let Some(path) = result.ok().and_then(|file| file.path()) else {
+                        return;
+                    };
+                    match keymap.borrow().save(&path) {
+                        Ok(()) => status.set_text(&format!("{} に書き出しました", path.display())),
+                        Err(e) => status.set_text(&format!("{}: {}", path.display(), e)),
+                    }
+                },
+            );
+        });
+    }
+    {
+        let dialog = dialog.clone();
+        close.connect_clicked(move |_| dialog.close());
+    }
+
+    status.set_text(&conflict_text(&keymap.borrow()));
+    refresh();
+    dialog.set_child(Some(&content));
+    dialog.present();
+}
@@ -0,0 +1,50 @@
+// Magnetic snapping of object edges while moving / trimming
+
+use crate::project::FrameRate;
+use crate::timeline::{ClipId, Frame, Timeline};
+
+// Snap distance on screen, converted to frames with the current zoom
+pub const SNAP_DISTANCE: f64 = 8.0;
+
+pub struct Snapper {
+    targets: Vec<Frame>,
+    rate: FrameRate,
+}
+
+impl Snapper {
+    // Playhead, marker and object edges, except the objects being dragged
+    pub fn new(timeline: &Timeline, playhead: Frame, rate: FrameRate, exclude: &[ClipId]) -> Self {
+        let mut targets = vec![playhead];
+        targets.extend(timeline.markers.iter().map(|m| m.frame));
+        for clip in timeline.clips.iter().filter(|c| !exclude.contains(&c.id)) {
+            targets.push(clip.start);
+            targets.push(clip.end());
+        }
+        targets.sort_unstable();
+        targets.dedup();
+        Self { targets, rate }
+    }
+
+    // Nearest target to `frame` within `threshold` frames
+    pub fn snap(&self, frame: Frame, threshold: f64) -> Option<Frame> {
+        // Whole seconds are targets too
+        let second = self
+            .rate
+            .seconds_to_frame(self.rate.frame_to_seconds(frame).round() + 1e-9);
+        self.targets
+            .iter()
+            .copied()
+            .chain(std::iter::once(second))
+            .filter(|t| ((t - frame).abs() as f64) <= threshold)
+            .min_by_key(|t| (t - frame).abs())
+    }
+
+    // Best correction for a group of edges that move together.
+    // Returns (offset to add, frame snapped to).
+    pub fn snap_edges(&self, edges: &[Frame], threshold: f64) -> Option<(Frame, Frame)> {
+        edges
+            .iter()
+            .filter_map(|&edge| self.snap(edge, threshold).map(|t| (t - edge, t)))
+            .min_by_key(|(offset, _)| offset.abs())
+    }
+}
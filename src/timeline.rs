@@ -0,0 +1,402 @@
+// Timeline model (AviUtl style: objects are placed on numbered layers)
+
+use crate::project::MediaId;
+use serde::{Deserialize, Serialize};
+
+pub type Frame = i64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ClipId(pub u64);
+
+// What an object shows
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Source {
+    Media(MediaId),
+    Text(String),
+    Shape(ShapeKind),
+    Filter(FilterKind),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShapeKind {
+    Rectangle,
+    Circle,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 2] = [ShapeKind::Rectangle, ShapeKind::Circle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "四角形",
+            ShapeKind::Circle => "円",
+        }
+    }
+
+    // Stable identifier for menus / action targets
+    pub fn key(self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "rectangle",
+            ShapeKind::Circle => "circle",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<ShapeKind> {
+        Self::ALL.into_iter().find(|s| s.key() == key)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FilterKind {
+    Blur { radius: f64 },
+    Brightness { amount: f64 },
+    Mosaic { size: f64 },
+}
+
+impl FilterKind {
+    // Every filter with its default parameters
+    pub const ALL: [FilterKind; 3] = [
+        FilterKind::Blur { radius: 5.0 },
+        FilterKind::Brightness { amount: 0.2 },
+        FilterKind::Mosaic { size: 16.0 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Blur { .. } => "ぼかし",
+            FilterKind::Brightness { .. } => "明るさ",
+            FilterKind::Mosaic { .. } => "モザイク",
+        }
+    }
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            FilterKind::Blur { .. } => "blur",
+            FilterKind::Brightness { .. } => "brightness",
+            FilterKind::Mosaic { .. } => "mosaic",
+        }
+    }
+
+    // Default-parameter filter for a key
+    pub fn from_key(key: &str) -> Option<FilterKind> {
+        Self::ALL.into_iter().find(|f| f.key() == key)
+    }
+}
+
+// Per-object properties
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Properties {
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+    pub opacity: f64,
+    pub rotation: f64,
+    pub color: [f64; 3],
+}
+
+impl Default for Properties {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            zoom: 1.0,
+            opacity: 1.0,
+            rotation: 0.0,
+            color: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    pub id: ClipId,
+    pub layer: usize,
+    pub start: Frame,
+    pub length: Frame,
+    pub source: Source,
+    pub props: Properties,
+    pub filters: Vec<FilterKind>,
+}
+
+impl Clip {
+    // Exclusive end frame
+    pub fn end(&self) -> Frame {
+        self.start + self.length
+    }
+
+    pub fn contains(&self, frame: Frame) -> bool {
+        frame >= self.start && frame < self.end()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            visible: true,
+            locked: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub frame: Frame,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Timeline {
+    pub layers: Vec<Layer>,
+    pub clips: Vec<Clip>,
+    pub next_clip_id: u64,
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+}
+
+// AviUtl has 100 layers
+pub const DEFAULT_LAYER_COUNT: usize = 100;
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new(DEFAULT_LAYER_COUNT)
+    }
+}
+
+impl Timeline {
+    pub fn new(layer_count: usize) -> Self {
+        Self {
+            layers: vec![Layer::default(); layer_count],
+            clips: Vec::new(),
+            next_clip_id: 1,
+            markers: Vec::new(),
+        }
+    }
+
+    // Add object and return its id
+    #[cfg(test)]
+    pub fn add_clip(
+        &mut self,
+        layer: usize,
+        start: Frame,
+        length: Frame,
+        source: Source,
+    ) -> ClipId {
+        let clip = self.new_clip(layer, start, length, source);
+        let id = clip.id;
+        self.insert_clip(clip);
+        id
+    }
+
+    pub fn allocate_id(&mut self) -> ClipId {
+        let id = ClipId(self.next_clip_id);
+        self.next_clip_id += 1;
+        id
+    }
+
+    // Make an object with a fresh id without inserting it (for undoable adds)
+    pub fn new_clip(&mut self, layer: usize, start: Frame, length: Frame, source: Source) -> Clip {
+        let id = self.allocate_id();
+        Clip {
+            id,
+            layer,
+            start,
+            length: length.max(1),
+            source,
+            props: Properties::default(),
+            filters: Vec::new(),
+        }
+    }
+
+    // Put an existing object back (keeps its id)
+    pub fn insert_clip(&mut self, clip: Clip) {
+        if clip.layer >= self.layers.len() {
+            self.layers.resize(clip.layer + 1, Layer::default());
+        }
+        self.next_clip_id = self.next_clip_id.max(clip.id.0 + 1);
+        self.clips.push(clip);
+    }
+
+    pub fn remove_clip(&mut self, id: ClipId) -> Option<Clip> {
+        let index = self.clips.iter().position(|c| c.id == id)?;
+        Some(self.clips.remove(index))
+    }
+
+    pub fn clip(&self, id: ClipId) -> Option<&Clip> {
+        self.clips.iter().find(|c| c.id == id)
+    }
+
+    pub fn clip_mut(&mut self, id: ClipId) -> Option<&mut Clip> {
+        self.clips.iter_mut().find(|c| c.id == id)
+    }
+
+    // Objects on a layer sorted by start frame
+    pub fn clips_on_layer(&self, layer: usize) -> Vec<&Clip> {
+        let mut clips: Vec<&Clip> = self.clips.iter().filter(|c| c.layer == layer).collect();
+        clips.sort_by_key(|c| c.start);
+        clips
+    }
+
+    // Objects active at `frame` on `layer`
+    #[cfg(test)]
+    pub fn clips_at(&self, frame: Frame, layer: usize) -> Vec<&Clip> {
+        self.clips
+            .iter()
+            .filter(|c| c.layer == layer && c.contains(frame))
+            .collect()
+    }
+
+    // All objects active at `frame`, bottom layer (1) first
+    pub fn active_clips(&self, frame: Frame) -> Vec<&Clip> {
+        let mut clips: Vec<&Clip> = self
+            .clips
+            .iter()
+            .filter(|c| c.contains(frame))
+            .filter(|c| self.layers.get(c.layer).is_none_or(|l| l.visible))
+            .collect();
+        clips.sort_by_key(|c| (c.layer, c.start));
+        clips
+    }
+
+    // True if [start, start + length) on `layer` is not used by another object
+    pub fn is_free(&self, layer: usize, start: Frame, length: Frame, ignore: &[ClipId]) -> bool {
+        let end = start + length;
+        self.clips
+            .iter()
+            .filter(|c| c.layer == layer && !ignore.contains(&c.id))
+            .all(|c| end <= c.start || start >= c.end())
+    }
+
+    // First layer where the range fits
+    pub fn first_free_layer(&self, start: Frame, length: Frame) -> Option<usize> {
+        (0..self.layers.len()).find(|&layer| self.is_free(layer, start, length, &[]))
+    }
+
+    // True if two objects on one layer overlap
+    pub fn has_overlaps(&self) -> bool {
+        (0..self.layers.len()).any(|layer| {
+            self.clips_on_layer(layer)
+                .windows(2)
+                .any(|pair| pair[0].end() > pair[1].start)
+        })
+    }
+
+    // Last frame used by any object (exclusive)
+    pub fn end_frame(&self) -> Frame {
+        self.clips.iter().map(|c| c.end()).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(name: &str) -> Source {
+        Source::Text(name.to_string())
+    }
+
+    fn ids(clips: &[&Clip]) -> Vec<ClipId> {
+        clips.iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn clip_is_active_from_start_until_end() {
+        let mut timeline = Timeline::new(4);
+        let id = timeline.add_clip(1, 10, 5, text("a"));
+        assert!(timeline.clips_at(9, 1).is_empty());
+        assert_eq!(ids(&timeline.clips_at(10, 1)), [id]);
+        assert_eq!(ids(&timeline.clips_at(14, 1)), [id]);
+        // end() is exclusive
+        assert_eq!(timeline.clip(id).unwrap().end(), 15);
+        assert!(timeline.clips_at(15, 1).is_empty());
+        assert!(timeline.active_clips(15).is_empty());
+    }
+
+    #[test]
+    fn empty_layers_have_no_clips() {
+        let mut timeline = Timeline::new(4);
+        assert!(timeline.active_clips(0).is_empty());
+        assert!(timeline.clips_on_layer(2).is_empty());
+        timeline.add_clip(0, 0, 10, text("a"));
+        assert!(timeline.clips_at(5, 2).is_empty());
+        assert!(timeline.clips_at(5, 3).is_empty());
+        assert_eq!(timeline.end_frame(), 10);
+    }
+
+    #[test]
+    fn clips_at_only_sees_its_layer() {
+        let mut timeline = Timeline::new(4);
+        let a = timeline.add_clip(0, 0, 10, text("a"));
+        let b = timeline.add_clip(1, 5, 10, text("b"));
+        assert_eq!(ids(&timeline.clips_at(7, 0)), [a]);
+        assert_eq!(ids(&timeline.clips_at(7, 1)), [b]);
+        assert_eq!(ids(&timeline.clips_at(12, 1)), [b]);
+        assert!(timeline.clips_at(12, 0).is_empty());
+    }
+
+    #[test]
+    fn active_clips_are_ordered_bottom_layer_first() {
+        let mut timeline = Timeline::new(4);
+        let top = timeline.add_clip(3, 0, 20, text("top"));
+        let bottom = timeline.add_clip(0, 5, 10, text("bottom"));
+        let middle = timeline.add_clip(2, 0, 10, text("middle"));
+        assert_eq!(ids(&timeline.active_clips(6)), [bottom, middle, top]);
+        assert_eq!(ids(&timeline.active_clips(2)), [middle, top]);
+        assert_eq!(ids(&timeline.active_clips(15)), [top]);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_active() {
+        let mut timeline = Timeline::new(2);
+        timeline.add_clip(0, 0, 10, text("a"));
+        let b = timeline.add_clip(1, 0, 10, text("b"));
+        timeline.layers[0].visible = false;
+        assert_eq!(ids(&timeline.active_clips(0)), [b]);
+        // clips_at is a plain hit-test and still sees it
+        assert_eq!(timeline.clips_at(0, 0).len(), 1);
+    }
+
+    #[test]
+    fn back_to_back_clips_do_not_overlap() {
+        let mut timeline = Timeline::new(2);
+        let a = timeline.add_clip(0, 0, 10, text("a"));
+        let b = timeline.add_clip(0, 10, 10, text("b"));
+        assert_eq!(ids(&timeline.clips_at(9, 0)), [a]);
+        assert_eq!(ids(&timeline.clips_at(10, 0)), [b]);
+        assert!(!timeline.has_overlaps());
+        assert!(timeline.is_free(0, 20, 5, &[]));
+        assert!(!timeline.is_free(0, 19, 5, &[]));
+        assert!(timeline.is_free(0, 5, 10, &[a, b]));
+    }
+
+    #[test]
+    fn overlaps_on_one_layer_are_found() {
+        let mut timeline = Timeline::new(3);
+        let a = timeline.add_clip(0, 0, 10, text("a"));
+        let b = timeline.add_clip(0, 5, 10, text("b"));
+        assert!(timeline.has_overlaps());
+        let mut both = ids(&timeline.clips_at(7, 0));
+        both.sort();
+        assert_eq!(both, [a, b]);
+        assert_eq!(timeline.first_free_layer(5, 10), Some(1));
+    }
+
+    #[test]
+    fn inserting_past_the_last_layer_adds_layers() {
+        let mut timeline = Timeline::new(2);
+        let clip = timeline.new_clip(5, 0, 0, text("a"));
+        assert_eq!(clip.length, 1);
+        timeline.insert_clip(clip);
+        assert_eq!(timeline.layers.len(), 6);
+        assert_eq!(timeline.active_clips(0).len(), 1);
+    }
+}
@@ -0,0 +1,91 @@
+// Playback position and play / pause / shuttle state
+
+use crate::project::FrameRate;
+use crate::timeline::Frame;
+
+// J / L shuttle speeds
+const SHUTTLE_SPEEDS: [f64; 4] = [1.0, 2.0, 4.0, 8.0];
+
+#[derive(Default)]
+pub struct Transport {
+    pub playhead: Frame,
+    // Frames per frame-duration; 0.0 = paused, negative = backwards
+    speed: f64,
+    // (frame clock time in µs, playhead) when playback last (re)started
+    anchor: Option<(i64, Frame)>,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.speed != 0.0
+    }
+
+    pub fn toggle_play(&mut self) {
+        if self.is_playing() {
+            self.pause();
+        } else {
+            self.set_speed(1.0);
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.set_speed(0.0);
+    }
+
+    // L: play forward, faster on every press
+    pub fn shuttle_forward(&mut self) {
+        self.set_speed(next_speed(self.speed));
+    }
+
+    // J: play backward, faster on every press
+    pub fn shuttle_backward(&mut self) {
+        self.set_speed(-next_speed(-self.speed));
+    }
+
+    pub fn seek(&mut self, frame: Frame) {
+        self.playhead = frame.max(0);
+        self.anchor = None;
+    }
+
+    pub fn step(&mut self, delta: Frame) {
+        self.pause();
+        self.seek(self.playhead + delta);
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.anchor = None;
+    }
+
+    // Called from the widget frame clock. The playhead is derived from the elapsed
+    // time, so slow rendering skips frames instead of drifting. Returns true if moved.
+    pub fn tick(&mut self, now_us: i64, rate: FrameRate, end: Frame) -> bool {
+        if !self.is_playing() {
+            return false;
+        }
+        let (start_us, start_frame) = *self.anchor.get_or_insert((now_us, self.playhead));
+        let elapsed = (now_us - start_us) as f64 / 1_000_000.0;
+        let mut frame = start_frame + (elapsed * rate.fps() * self.speed).floor() as Frame;
+
+        // Stop at either end of the project
+        if (self.speed > 0.0 && frame >= end) || (self.speed < 0.0 && frame <= 0) {
+            frame = frame.clamp(0, end.max(0));
+            self.pause();
+        }
+        let moved = frame != self.playhead;
+        self.playhead = frame;
+        moved
+    }
+}
+
+fn next_speed(current: f64) -> f64 {
+    SHUTTLE_SPEEDS
+        .iter()
+        .copied()
+        .find(|&s| s > current)
+        .unwrap_or(SHUTTLE_SPEEDS[SHUTTLE_SPEEDS.len() - 1])
+}
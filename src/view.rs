@@ -0,0 +1,134 @@
+// Timeline zoom / scroll and the pixel <-> frame / layer mapping
+
+use crate::ruler::RULER_HEIGHT;
+use crate::timeline::{ClipId, Frame, Timeline};
+
+const MIN_PIXELS_PER_FRAME: f64 = 0.01;
+const MAX_PIXELS_PER_FRAME: f64 = 64.0;
+// Grab width of the trim handles at both ends of an object
+pub const HANDLE_WIDTH: f64 = 6.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitPart {
+    Body,
+    LeftEdge,
+    RightEdge,
+}
+
+pub struct TimelineView {
+    pub pixels_per_frame: f64,
+    // First visible frame at the left edge (after the label column)
+    pub scroll_frame: f64,
+    // First visible layer row
+    pub scroll_layer: usize,
+    pub layer_height: f64,
+    pub label_area_width: f64,
+    // y of the ruler; layer rows start right below it
+    pub ruler_y: f64,
+}
+
+impl TimelineView {
+    pub fn new(ruler_y: f64, layer_height: f64) -> Self {
+        Self {
+            pixels_per_frame: 2.0,
+            scroll_frame: 0.0,
+            scroll_layer: 0,
+            layer_height,
+            label_area_width: 40.0,
+            ruler_y,
+        }
+    }
+
+    pub fn layers_top(&self) -> f64 {
+        self.ruler_y + RULER_HEIGHT
+    }
+
+    pub fn frame_to_x(&self, frame: Frame) -> f64 {
+        self.label_area_width + (frame as f64 - self.scroll_frame) * self.pixels_per_frame
+    }
+
+    pub fn x_to_frame(&self, x: f64) -> Frame {
+        self.x_to_frame_f64(x).round().max(0.0) as Frame
+    }
+
+    fn x_to_frame_f64(&self, x: f64) -> f64 {
+        (x - self.label_area_width) / self.pixels_per_frame + self.scroll_frame
+    }
+
+    pub fn layer_to_y(&self, layer: usize) -> f64 {
+        self.layers_top() + (layer as f64 - self.scroll_layer as f64) * self.layer_height
+    }
+
+    // None above the first row
+    pub fn y_to_layer(&self, y: f64) -> Option<usize> {
+        if y < self.layers_top() {
+            return None;
+        }
+        Some(((y - self.layers_top()) / self.layer_height) as usize + self.scroll_layer)
+    }
+
+    // Number of frames that fit in `width`
+    pub fn visible_frames(&self, width: f64) -> f64 {
+        ((width - self.label_area_width) / self.pixels_per_frame).max(0.0)
+    }
+
+    pub fn visible_layers(&self, height: f64) -> usize {
+        ((height - self.layers_top()) / self.layer_height)
+            .ceil()
+            .max(0.0) as usize
+    }
+
+    // Zoom keeping the frame under `anchor_x` in place
+    pub fn zoom_at(&mut self, factor: f64, anchor_x: f64) {
+        let anchor = self.x_to_frame_f64(anchor_x.max(self.label_area_width));
+        self.pixels_per_frame =
+            (self.pixels_per_frame * factor).clamp(MIN_PIXELS_PER_FRAME, MAX_PIXELS_PER_FRAME);
+        self.scroll_frame = anchor
+            - (anchor_x.max(self.label_area_width) - self.label_area_width) / self.pixels_per_frame;
+        self.scroll_frame = self.scroll_frame.max(0.0);
+    }
+
+    pub fn scroll_by_pixels(&mut self, dx: f64) {
+        self.scroll_frame = (self.scroll_frame + dx / self.pixels_per_frame).max(0.0);
+    }
+
+    pub fn scroll_layers(&mut self, delta: i64, layer_count: usize) {
+        let layer = (self.scroll_layer as i64 + delta).clamp(0, layer_count.max(1) as i64 - 1);
+        self.scroll_layer = layer as usize;
+    }
+
+    // Show [start, end) across the whole width with a little margin
+    pub fn zoom_to_range(&mut self, start: Frame, end: Frame, width: f64) {
+        let length = (end - start).max(1) as f64;
+        let margin = length * 0.05;
+        let available = (width - self.label_area_width).max(1.0);
+        self.pixels_per_frame =
+            (available / (length + margin * 2.0)).clamp(MIN_PIXELS_PER_FRAME, MAX_PIXELS_PER_FRAME);
+        self.scroll_frame = (start as f64 - margin).max(0.0);
+    }
+
+    // Object (and which part of it) under the pointer
+    pub fn hit_test(&self, timeline: &Timeline, x: f64, y: f64) -> Option<(ClipId, HitPart)> {
+        if x < self.label_area_width {
+            return None;
+        }
+        let layer = self.y_to_layer(y)?;
+        timeline.clips_on_layer(layer).into_iter().find_map(|clip| {
+            let left = self.frame_to_x(clip.start);
+            let right = self.frame_to_x(clip.end());
+            if x < left || x > right {
+                return None;
+            }
+            // Short objects: keep some body to grab
+            let handle = HANDLE_WIDTH.min((right - left) / 3.0);
+            let part = if x <= left + handle {
+                HitPart::LeftEdge
+            } else if x >= right - handle {
+                HitPart::RightEdge
+            } else {
+                HitPart::Body
+            };
+            Some((clip.id, part))
+        })
+    }
+}
@@ -0,0 +1,84 @@
+// WAV (RIFF / 16-bit PCM) writer. The sizes in the header are filled in by `finish`.
+
+use crate::project::FrameRate;
+use crate::timeline::Frame;
+use std::io::{self, Seek, SeekFrom, Write};
+
+pub const BITS_PER_SAMPLE: u16 = 16;
+
+// Audio sample frames from the start to the beginning of `frame`. Rounding the
+// running total keeps the audio from drifting at rates like 29.97fps.
+pub fn samples_until(frame: Frame, rate: FrameRate, sample_rate: u32) -> u64 {
+    let num = frame.max(0) as u128 * sample_rate as u128 * rate.den as u128;
+    let den = rate.num.max(1) as u128;
+    ((2 * num + den) / (2 * den)) as u64
+}
+
+// Samples belonging to one video frame
+pub fn samples_in_frame(frame: Frame, rate: FrameRate, sample_rate: u32) -> u64 {
+    samples_until(frame + 1, rate, sample_rate) - samples_until(frame, rate, sample_rate)
+}
+
+// WAVEFORMATEX without the cbSize field (16 bytes)
+pub fn format_chunk(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let block_align = channels * BITS_PER_SAMPLE / 8;
+    let mut chunk = Vec::with_capacity(16);
+    chunk.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    chunk.extend_from_slice(&channels.to_le_bytes());
+    chunk.extend_from_slice(&sample_rate.to_le_bytes());
+    chunk.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    chunk.extend_from_slice(&block_align.to_le_bytes());
+    chunk.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    chunk
+}
+
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    channels: u16,
+    // Sample frames (one sample per channel) written so far
+    samples: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&format_chunk(sample_rate, channels))?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            out,
+            channels,
+            samples: 0,
+        })
+    }
+
+    fn data_size(&self) -> u64 {
+        self.samples * self.channels as u64 * (BITS_PER_SAMPLE / 8) as u64
+    }
+
+    // Interleaved samples; the length must be a multiple of the channel count
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let added = (samples.len() / self.channels as usize) as u64;
+        if (self.data_size() + added * self.channels as u64 * 2) > (u32::MAX - 36) as u64 {
+            return Err(io::Error::other("WAV ファイルが 4GB を超えます"));
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.out.write_all(&bytes)?;
+        self.samples += added;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.data_size() as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
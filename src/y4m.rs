@@ -0,0 +1,182 @@
+// YUV4MPEG2 (.y4m) writer: planar 8-bit YUV frames after a one-line header
+
+use crate::project::FrameRate;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chroma {
+    C420,
+    C444,
+}
+
+impl Chroma {
+    fn tag(self) -> &'static str {
+        match self {
+            // Chroma sited between the luma samples, as JPEG / MPEG-1
+            Chroma::C420 => "C420jpeg",
+            Chroma::C444 => "C444",
+        }
+    }
+
+    // Size of one chroma plane
+    fn plane_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Chroma::C420 => (width.div_ceil(2), height.div_ceil(2)),
+            Chroma::C444 => (width, height),
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// 0.9 -> (9, 10); three decimals are enough for the usual DV / anamorphic ratios
+pub fn aspect_ratio(pixel_aspect: f64) -> (u32, u32) {
+    let num = (pixel_aspect * 1000.0).round().max(1.0) as u32;
+    let g = gcd(num, 1000);
+    (num / g, 1000 / g)
+}
+
+// Limited range (16-235) coefficients * 256: BT.709 for HD, BT.601 below,
+// which is what players assume when the file doesn't say
+fn coefficients(height: usize) -> [[i32; 3]; 3] {
+    if height >= 720 {
+        [[47, 157, 16], [-26, -86, 112], [112, -102, -10]]
+    } else {
+        [[66, 129, 25], [-38, -74, 112], [112, -94, -18]]
+    }
+}
+
+// Packed 8-bit RGB (no row padding) to Y, U and V planes
+pub fn rgb_to_yuv(rgb: &[u8], width: usize, height: usize, chroma: Chroma) -> Vec<u8> {
+    let m = coefficients(height);
+    let dot =
+        |row: [i32; 3], [r, g, b]: [i32; 3]| (row[0] * r + row[1] * g + row[2] * b + 128) >> 8;
+    let pixel = |x: usize, y: usize| {
+        let i = (y * width + x) * 3;
+        [rgb[i] as i32, rgb[i + 1] as i32, rgb[i + 2] as i32]
+    };
+
+    let (cw, ch) = chroma.plane_size(width, height);
+    let mut out = Vec::with_capacity(width * height + cw * ch * 2);
+    for y in 0..height {
+        for x in 0..width {
+            out.push((dot(m[0], pixel(x, y)) + 16).clamp(0, 255) as u8);
+        }
+    }
+    for row in [m[1], m[2]] {
+        for cy in 0..ch {
+            for cx in 0..cw {
+                let value = match chroma {
+                    Chroma::C444 => dot(row, pixel(cx, cy)),
+                    // Average of the 2×2 block; the last row / column repeats on odd sizes
+                    Chroma::C420 => {
+                        let (x0, y0) = (cx * 2, cy * 2);
+                        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                        let sum = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+                            .into_iter()
+                            .map(|(x, y)| dot(row, pixel(x, y)))
+                            .sum::<i32>();
+                        (sum + 2).div_euclid(4)
+                    }
+                };
+                out.push((value + 128).clamp(0, 255) as u8);
+            }
+        }
+    }
+    out
+}
+
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    chroma: Chroma,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        rate: FrameRate,
+        pixel_aspect: f64,
+        chroma: Chroma,
+    ) -> io::Result<Self> {
+        let (aspect_num, aspect_den) = aspect_ratio(pixel_aspect);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A{}:{} {}",
+            width,
+            height,
+            rate.num,
+            rate.den,
+            aspect_num,
+            aspect_den,
+            chroma.tag()
+        )?;
+        Ok(Self {
+            out,
+            width,
+            height,
+            chroma,
+        })
+    }
+
+    // `rgb` is width × height packed RGB
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        if rgb.len() != self.width * self.height * 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "フレームの大きさが違います",
+            ));
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out
+            .write_all(&rgb_to_yuv(rgb, self.width, self.height, self.chroma))?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grey_has_no_chroma() {
+        for (height, chroma) in [(2, Chroma::C444), (720, Chroma::C444), (720, Chroma::C420)] {
+            for level in [0u8, 128, 255] {
+                let rgb = vec![level; 2 * height * 3];
+                let yuv = rgb_to_yuv(&rgb, 2, height, chroma);
+                assert!(
+                    yuv[2 * height..].iter().all(|&c| c == 128),
+                    "{} {}",
+                    height,
+                    level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn white_and_black_are_limited_range() {
+        for height in [2, 720] {
+            let white = rgb_to_yuv(&vec![255; 2 * height * 3], 2, height, Chroma::C444);
+            let black = rgb_to_yuv(&vec![0; 2 * height * 3], 2, height, Chroma::C444);
+            assert_eq!((white[0], black[0]), (235, 16));
+        }
+    }
+
+    #[test]
+    fn pixel_aspect_is_reduced() {
+        assert_eq!(aspect_ratio(1.0), (1, 1));
+        assert_eq!(aspect_ratio(0.9), (9, 10));
+        assert_eq!(aspect_ratio(4.0 / 3.0), (1333, 1000));
+    }
+}